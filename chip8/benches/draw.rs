@@ -0,0 +1,25 @@
+use chip8::Chip8;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// Returns an emulator with `program` loaded, which should end by jumping back into itself.
+fn looping(program: &[u8]) -> Chip8 {
+    let mut emu = Chip8::new();
+    emu.load(program).unwrap();
+    emu
+}
+
+fn draw(c: &mut Criterion) {
+    // I = 0x50 (the font), then draw 15 rows at (V0, V1) forever
+    let mut emu = looping(&[0xa0, 0x50, 0xd0, 0x1f, 0x12, 0x02]);
+    c.bench_function("DXYN 8x15", |b| b.iter(|| emu.step().unwrap()));
+
+    // Same in high resolution with a 16x16 sprite
+    let mut emu = looping(&[0x00, 0xff, 0xa0, 0x50, 0xd0, 0x10, 0x12, 0x04]);
+    c.bench_function("DXY0 16x16 hires", |b| b.iter(|| emu.step().unwrap()));
+
+    let mut emu = looping(&[0x00, 0xe0, 0x12, 0x00]);
+    c.bench_function("00E0", |b| b.iter(|| emu.step().unwrap()));
+}
+
+criterion_group!(benches, draw);
+criterion_main!(benches);
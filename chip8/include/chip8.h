@@ -0,0 +1,26 @@
+/* C interface of the chip8 crate, built with its `ffi` feature. See src/ffi.rs for the details. */
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define CHIP8_OK 0
+#define CHIP8_NULL (-1)
+#define CHIP8_ERROR (-2)
+
+/* An opaque machine, owned by the caller and freed with chip8_free. */
+typedef struct Chip8 Chip8;
+
+Chip8 *chip8_new(void);
+void chip8_free(Chip8 *emu);
+int chip8_load(Chip8 *emu, const uint8_t *rom, size_t len);
+int chip8_step(Chip8 *emu);
+void chip8_tick_timers(Chip8 *emu);
+void chip8_press_key(Chip8 *emu, uint8_t key);
+void chip8_unpress_key(Chip8 *emu, uint8_t key);
+size_t chip8_display_width(const Chip8 *emu);
+size_t chip8_display_height(const Chip8 *emu);
+size_t chip8_get_display(const Chip8 *emu, uint8_t *out, size_t len);
+
+#endif
@@ -0,0 +1,380 @@
+//! Assembling mnemonics into programs, the reverse of `disasm`.
+//!
+//! Each line holds an optional `label:`, then an instruction or a data directive, then an
+//! optional `;` comment. Instructions use the mnemonics `Instruction` is shown with, like
+//! `LD V0, 0x12` or `DRW V0, V1, 5`, in any case. Addresses can be numbers or labels, and numbers
+//! are decimal, `0x` hexadecimal or `0b` binary. `db` emits bytes and `dw` emits big endian words,
+//! which can also be labels. `LD I, LONG <addr>` takes a 16 bit address and emits it after its
+//! opcode. Labels are case sensitive and the program is assembled to run at 0x200, where
+//! `Chip8::load` puts it.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+
+use crate::disasm::Instruction;
+
+/// The address the program is loaded at, which labels are relative to.
+const ORIGIN: u16 = 0x200;
+
+/// Every instruction mnemonic, to tell bad operands from a misspelled instruction.
+const MNEMONICS: &[&str] = &[
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE", "SNE",
+    "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP",
+    "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+/// An error in the source, with the line it's on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// The line of the error, starting at 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// A line with an instruction or a directive, split into its parts.
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+/// Assembles `source` into a program ready for `Chip8::load`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    // The first pass finds where every label is, so the second can encode jumps forward
+    let mut labels = BTreeMap::new();
+    let mut statements = Vec::new();
+    let mut addr = ORIGIN as usize;
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |message: String| AsmError { line: line_number, message };
+        let mut text = line.split(';').next().unwrap_or_default().trim();
+
+        if let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if !is_identifier(label) {
+                return Err(error(format!("invalid label `{}`", label)));
+            }
+            if labels.insert(label, addr).is_some() {
+                return Err(error(format!("label `{}` is already defined", label)));
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let rest = rest.trim();
+        let operands: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else if mnemonic == "SAVE" || mnemonic == "LOAD" {
+            rest.split('-').map(str::trim).collect()
+        } else {
+            rest.split(',').map(str::trim).collect()
+        };
+        addr += match mnemonic.as_str() {
+            "DB" => operands.len(),
+            "DW" => operands.len() * 2,
+            "LD" if long_address(&operands).is_some() => 4,
+            _ => 2,
+        };
+        statements.push(Statement { line: line_number, mnemonic, operands });
+    }
+
+    let mut program = Vec::new();
+    for statement in &statements {
+        assemble_statement(statement, &labels, &mut program)
+            .map_err(|message| AsmError { line: statement.line, message })?;
+    }
+    Ok(program)
+}
+
+/// Appends the bytes of `statement` to `program`, returns the error message if it's invalid.
+fn assemble_statement(
+    statement: &Statement,
+    labels: &BTreeMap<&str, usize>,
+    program: &mut Vec<u8>,
+) -> Result<(), String> {
+    let operands = &statement.operands;
+    match statement.mnemonic.as_str() {
+        "DB" => {
+            expect_some(operands)?;
+            for operand in operands {
+                program.push(number(operand, 8)? as u8);
+            }
+        },
+        "DW" => {
+            expect_some(operands)?;
+            for operand in operands {
+                program.extend_from_slice(&address(operand, labels, 16)?.to_be_bytes());
+            }
+        },
+        _ => {
+            let instruction = instruction(&statement.mnemonic, operands, labels)?;
+            program.extend_from_slice(&instruction.opcode().to_be_bytes());
+            if let Instruction::LoadIndexLong(addr) = instruction {
+                program.extend_from_slice(&addr.to_be_bytes());
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Encodes the instruction `mnemonic` with `operands`.
+fn instruction(
+    mnemonic: &str,
+    operands: &[&str],
+    labels: &BTreeMap<&str, usize>,
+) -> Result<Instruction, String> {
+    use Instruction::*;
+
+    // Keywords like `I` or `DT` are matched in any case, registers are checked separately
+    let keywords: Vec<String> = operands.iter().map(|operand| operand.to_ascii_uppercase()).collect();
+    let keywords: Vec<&str> = keywords.iter().map(String::as_str).collect();
+    let nibble = |operand: &str| number(operand, 4).map(|n| n as u8);
+    let byte = |operand: &str| number(operand, 8).map(|n| n as u8);
+    let addr = |operand: &str| address(operand, labels, 12);
+
+    let instruction = match (mnemonic, keywords.as_slice()) {
+        ("CLS", []) => ClearScreen,
+        ("RET", []) => Return,
+        ("SCD", [_]) => ScrollDown(nibble(operands[0])?),
+        ("SCU", [_]) => ScrollUp(nibble(operands[0])?),
+        ("SCR", []) => ScrollRight,
+        ("SCL", []) => ScrollLeft,
+        ("EXIT", []) => Exit,
+        ("LOW", []) => LowRes,
+        ("HIGH", []) => HighRes,
+        ("SYS", [_]) => System(addr(operands[0])?),
+        ("JP", ["V0", _]) => JumpOffset(addr(operands[1])?),
+        ("JP", [_]) => Jump(addr(operands[0])?),
+        ("CALL", [_]) => Call(addr(operands[0])?),
+        ("SE", [_, _]) => match register(operands[1]) {
+            Ok(y) => SkipEqReg { x: register(operands[0])?, y },
+            Err(_) => SkipEqImm { x: register(operands[0])?, nn: byte(operands[1])? },
+        },
+        ("SNE", [_, _]) => match register(operands[1]) {
+            Ok(y) => SkipNeReg { x: register(operands[0])?, y },
+            Err(_) => SkipNeImm { x: register(operands[0])?, nn: byte(operands[1])? },
+        },
+        ("SAVE", [_, _]) => SaveRange { x: register(operands[0])?, y: register(operands[1])? },
+        ("LOAD", [_, _]) => LoadRange { x: register(operands[0])?, y: register(operands[1])? },
+        ("LD", ["I", _]) => match long_address(operands) {
+            Some(long) => LoadIndexLong(address(long, labels, 16)?),
+            None => LoadIndex(addr(operands[1])?),
+        },
+        ("LD", ["DT", _]) => SetDelay { x: register(operands[1])? },
+        ("LD", ["ST", _]) => SetSound { x: register(operands[1])? },
+        ("LD", ["F", _]) => LoadFont { x: register(operands[1])? },
+        ("LD", ["HF", _]) => LoadBigFont { x: register(operands[1])? },
+        ("LD", ["B", _]) => StoreBcd { x: register(operands[1])? },
+        ("LD", ["[I]", _]) => StoreRegs { x: register(operands[1])? },
+        ("LD", ["R", _]) => StoreFlags { x: register(operands[1])? },
+        ("LD", [_, "DT"]) => GetDelay { x: register(operands[0])? },
+        ("LD", [_, "K"]) => WaitKey { x: register(operands[0])? },
+        ("LD", [_, "[I]"]) => LoadRegs { x: register(operands[0])? },
+        ("LD", [_, "R"]) => LoadFlags { x: register(operands[0])? },
+        ("LD", [_, _]) => match register(operands[1]) {
+            Ok(y) => LoadReg { x: register(operands[0])?, y },
+            Err(_) => LoadImm { x: register(operands[0])?, nn: byte(operands[1])? },
+        },
+        ("ADD", ["I", _]) => AddIndex { x: register(operands[1])? },
+        ("ADD", [_, _]) => match register(operands[1]) {
+            Ok(y) => AddReg { x: register(operands[0])?, y },
+            Err(_) => AddImm { x: register(operands[0])?, nn: byte(operands[1])? },
+        },
+        ("OR", [_, _]) => Or { x: register(operands[0])?, y: register(operands[1])? },
+        ("AND", [_, _]) => And { x: register(operands[0])?, y: register(operands[1])? },
+        ("XOR", [_, _]) => Xor { x: register(operands[0])?, y: register(operands[1])? },
+        ("SUB", [_, _]) => Sub { x: register(operands[0])?, y: register(operands[1])? },
+        ("SHR", [_, _]) => ShiftRight { x: register(operands[0])?, y: register(operands[1])? },
+        ("SUBN", [_, _]) => SubReverse { x: register(operands[0])?, y: register(operands[1])? },
+        ("SHL", [_, _]) => ShiftLeft { x: register(operands[0])?, y: register(operands[1])? },
+        ("RND", [_, _]) => Random { x: register(operands[0])?, nn: byte(operands[1])? },
+        ("DRW", [_, _, _]) => Draw {
+            x: register(operands[0])?,
+            y: register(operands[1])?,
+            n: nibble(operands[2])?,
+        },
+        ("SKP", [_]) => SkipKey { x: register(operands[0])? },
+        ("SKNP", [_]) => SkipNotKey { x: register(operands[0])? },
+        ("PLANE", [_]) => SelectPlanes(nibble(operands[0])?),
+        ("AUDIO", []) => LoadAudio,
+        ("PITCH", [_]) => SetPitch { x: register(operands[0])? },
+        _ if MNEMONICS.contains(&mnemonic) => {
+            return Err(format!("invalid operands for `{}`", mnemonic))
+        },
+        _ => return Err(format!("unknown instruction `{}`", mnemonic)),
+    };
+    Ok(instruction)
+}
+
+/// The address operand of `LD I, LONG <addr>`, if `operands` are of one.
+fn long_address<'a>(operands: &[&'a str]) -> Option<&'a str> {
+    match operands {
+        [index, long] if index.eq_ignore_ascii_case("I") => {
+            let (keyword, addr) = long.split_once(char::is_whitespace)?;
+            keyword.eq_ignore_ascii_case("LONG").then(|| addr.trim())
+        },
+        _ => None,
+    }
+}
+
+fn expect_some(operands: &[&str]) -> Result<(), String> {
+    if operands.is_empty() {
+        return Err("expected at least one value".to_string());
+    }
+    Ok(())
+}
+
+/// Parses a register name like `V3` or `vA`.
+fn register(operand: &str) -> Result<u8, String> {
+    let mut chars = operand.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V' | 'v'), Some(digit), None) if digit.is_ascii_hexdigit() => {
+            Ok(digit.to_digit(16).unwrap_or_default() as u8)
+        },
+        _ => Err(format!("expected a register, found `{}`", operand)),
+    }
+}
+
+/// Parses a number literal that fits in `bits` bits.
+fn number(operand: &str, bits: u32) -> Result<u16, String> {
+    let lower = operand.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        u32::from_str_radix(binary, 2)
+    } else {
+        lower.parse()
+    };
+    match parsed {
+        Ok(value) if value < 1 << bits => Ok(value as u16),
+        Ok(value) => Err(format!("{} doesn't fit in {} bits", value, bits)),
+        Err(_) => Err(format!("expected a number, found `{}`", operand)),
+    }
+}
+
+/// Parses a number or a label that fits in `bits` bits.
+fn address(operand: &str, labels: &BTreeMap<&str, usize>, bits: u32) -> Result<u16, String> {
+    if !is_identifier(operand) {
+        return number(operand, bits);
+    }
+    match labels.get(operand) {
+        Some(&addr) if addr < 1 << bits => Ok(addr as u16),
+        Some(addr) => Err(format!("label `{}` at {:#x} doesn't fit in {} bits", operand, addr, bits)),
+        None => Err(format!("undefined label `{}`", operand)),
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::decode;
+    use crate::{Chip8, StepOutcome};
+
+    #[test]
+    fn runs_an_assembled_program() {
+        let source = "
+            ; Adds 1 to V0 five times with a subroutine, then draws the sprite below
+                LD V1, 5
+            loop:
+                CALL increment
+                ADD V1, 0xff       ; count down
+                SE V1, 0
+                JP loop
+                LD I, sprite
+                DRW V2, V2, 2
+            end: JP end
+
+            increment:
+                add v0, 0b1
+                ret
+
+            sprite:
+                db 0xf0, 0x90
+                dw end
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program[..4], [0x61, 0x05, 0x22, 0x10]);
+        assert_eq!(program.len(), 24);
+        assert_eq!(program[20..], [0xf0, 0x90, 0x02, 0x0e]);
+
+        let mut emu = Chip8::new();
+        emu.load(&program).unwrap();
+        for _ in 0..100 {
+            assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        }
+        assert_eq!(emu.register(0), 5);
+        assert_eq!(emu.register(1), 0);
+        assert_eq!(emu.pc(), 0x20e);
+        assert!(emu.stack().is_empty());
+        assert_eq!(emu.get_row(0), 0b1111);
+        assert_eq!(emu.get_row(1), 0b1001);
+    }
+
+    #[test]
+    fn assembles_what_disasm_shows() {
+        for opcode in 0..=0xffff {
+            let text = decode(opcode).to_string();
+            let mut bytes = opcode.to_be_bytes().to_vec();
+            if opcode == 0xf000 {
+                bytes.extend_from_slice(&[0, 0]); // Its address word, 0 from decode
+            }
+            assert_eq!(assemble(&text), Ok(bytes), "{}", text);
+        }
+    }
+
+    #[test]
+    fn assembles_long_index() {
+        let source = "
+                ld i, long data
+                LD I, LONG 0xBEEF
+            data:
+                db 1
+        ";
+        assert_eq!(assemble(source), Ok(vec![0xf0, 0x00, 0x02, 0x08, 0xf0, 0x00, 0xbe, 0xef, 0x01]));
+        assert_eq!(
+            assemble("LD I, LONG 0x10000"),
+            Err(AsmError { line: 1, message: "65536 doesn't fit in 16 bits".to_string() })
+        );
+        assert!(assemble("LD I, LONG").is_err());
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(
+            error("CLS\nJP nowhere"),
+            AsmError { line: 2, message: "undefined label `nowhere`".to_string() }
+        );
+        assert_eq!(error("LD V0, 256").message, "256 doesn't fit in 8 bits");
+        assert_eq!(error("LD VG, 1").message, "expected a register, found `VG`");
+        assert_eq!(error("DRW V0, V1").message, "invalid operands for `DRW`");
+        assert_eq!(error("ADD V0, twelve").message, "expected a number, found `twelve`");
+        assert_eq!(error("JP 0x1000").message, "4096 doesn't fit in 12 bits");
+        assert_eq!(error("\n\nMOVE V0, V1").line, 3);
+        assert_eq!(error("MOVE V0, V1").message, "unknown instruction `MOVE`");
+        assert_eq!(error("a:\na: CLS").message, "label `a` is already defined");
+        assert_eq!(error("db").message, "expected at least one value");
+        assert_eq!(error("JP nowhere").to_string(), "line 1: undefined label `nowhere`");
+    }
+}
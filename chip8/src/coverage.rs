@@ -0,0 +1,72 @@
+//! Marking which memory has been executed, to tell the code of a program from its data.
+
+use alloc::vec;
+
+use crate::Chip8;
+
+impl Chip8 {
+    /// Turns marking the bytes of every executed instruction on or off. It's off by default so
+    /// execution doesn't pay for it, and turning it on starts with nothing marked.
+    pub fn set_coverage_enabled(&mut self, enabled: bool) {
+        if enabled != self.debugger.coverage.is_some() {
+            self.debugger.coverage = enabled.then(|| vec![false; self.memory.len()]);
+        }
+    }
+
+    /// Returns whether each byte of memory was part of an executed instruction, by address.
+    /// Empty while coverage is off.
+    pub fn coverage(&self) -> &[bool] {
+        self.debugger.coverage.as_deref().unwrap_or_default()
+    }
+
+    /// Unmarks all memory, coverage stays on if it was.
+    pub fn clear_coverage(&mut self) {
+        if let Some(coverage) = self.debugger.coverage.as_deref_mut() {
+            coverage.fill(false);
+        }
+    }
+}
+
+/// Marks the `size` bytes of the instruction at `pc`.
+pub(crate) fn mark(coverage: &mut [bool], pc: u16, size: usize) {
+    let pc = pc as usize;
+    if let Some(bytes) = coverage.get_mut(pc..pc + size) {
+        bytes.fill(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn marks_executed_instructions() {
+        let program = assemble("
+                LD V0, 1
+                SE V0, 2       ; never skips
+                JP end
+                LD V1, 1       ; only reached by skipping
+            end:
+                JP end
+        ").unwrap();
+        let mut emu = Chip8::new();
+        emu.load(&program).unwrap();
+        assert!(emu.coverage().is_empty());
+        emu.set_coverage_enabled(true);
+        emu.step_n(10).unwrap();
+
+        let coverage = emu.coverage();
+        assert_eq!(coverage.len(), emu.memory_size());
+        assert_eq!(coverage[0x200..0x20a], [true, true, true, true, true, true, false, false, true, true]);
+        assert_eq!(coverage.iter().filter(|&&covered| covered).count(), 8);
+
+        emu.clear_coverage();
+        assert!(!emu.coverage().contains(&true));
+        emu.step().unwrap();
+        assert_eq!(emu.coverage()[0x208..0x20a], [true, true]);
+
+        emu.set_coverage_enabled(false);
+        assert!(emu.coverage().is_empty());
+    }
+}
@@ -0,0 +1,466 @@
+//! Debugger support, stopping execution at breakpoints and watchpoints, hooks called for each
+//! instruction and protecting memory from the host.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::history::PcRing;
+use crate::stats::Counters;
+use crate::Chip8;
+
+/// The breakpoints, watchpoints, hooks, memory protection, stats, coverage and program counter
+/// history of a machine. They belong to the debugger rather than the machine state, so they're
+/// ignored when comparing machines and aren't saved.
+#[derive(Clone, Default)]
+pub(crate) struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    /// The breakpoint that was just reported, its instruction runs on the next step.
+    resume_from: Option<u16>,
+    read_watchpoints: BTreeSet<u16>,
+    write_watchpoints: BTreeSet<u16>,
+    /// The first watched access of the current instruction.
+    watchpoint_hit: Option<WatchpointHit>,
+    trace_hook: TraceHook,
+    step_hook: StepHook,
+    /// Whether `write_memory` refuses to change the fonts.
+    pub(crate) font_protected: bool,
+    /// The instruction counts, while stats are on.
+    pub(crate) stats: Option<Box<Counters>>,
+    pub(crate) pc_history: PcRing,
+    /// The executed bytes of memory, while coverage is on.
+    pub(crate) coverage: Option<Vec<bool>>,
+}
+
+impl PartialEq for Debugger {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Debugger {}
+
+/// The kind of memory access a watchpoint reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+}
+
+/// A watched memory access by an instruction, as (address, old value, new value, kind). Reads
+/// have the same old and new value.
+pub(crate) type WatchpointHit = (u16, u8, u8, WatchKind);
+
+/// Holds the hook given to `set_trace_hook`. Like the sound callback it isn't carried over to
+/// clones.
+#[derive(Default)]
+struct TraceHook(Option<Box<dyn FnMut(u16, u16)>>);
+
+impl Clone for TraceHook {
+    fn clone(&self) -> Self {
+        TraceHook(None)
+    }
+}
+
+/// The machine as an instruction is about to execute, given to the hook set with `set_step_hook`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo<'a> {
+    /// The address the instruction was fetched from.
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: &'a [u8; 16],
+    pub index_register: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+type StepHookFn = dyn FnMut(&StepInfo);
+
+/// Holds the hook given to `set_step_hook`, not carried over to clones either.
+#[derive(Default)]
+struct StepHook(Option<Box<StepHookFn>>);
+
+impl Clone for StepHook {
+    fn clone(&self) -> Self {
+        StepHook(None)
+    }
+}
+
+impl Debugger {
+    /// Returns whether the instruction at `pc` should stop on a breakpoint instead of running.
+    /// Only the first time is reported, so stepping again runs the instruction.
+    pub(crate) fn check_breakpoint(&mut self, pc: u16) -> bool {
+        if self.breakpoints.is_empty() {
+            return false;
+        }
+        let hit = self.breakpoints.contains(&pc) && self.resume_from != Some(pc);
+        self.resume_from = hit.then_some(pc);
+        hit
+    }
+
+    /// Calls the trace hook, if there is one, for the instruction `opcode` at `pc`.
+    pub(crate) fn trace(&mut self, pc: u16, opcode: u16) {
+        if let Some(hook) = self.trace_hook.0.as_mut() {
+            hook(pc, opcode);
+        }
+    }
+
+    /// Returns whether a step hook is set, so the `StepInfo` is only built when needed.
+    pub(crate) fn has_step_hook(&self) -> bool {
+        self.step_hook.0.is_some()
+    }
+
+    /// Calls the step hook, if there is one, with `info`.
+    pub(crate) fn step_hook(&mut self, info: &StepInfo) {
+        if let Some(hook) = self.step_hook.0.as_mut() {
+            hook(info);
+        }
+    }
+
+    /// Records a write of `new` over `old` at `addr` if it's watched and nothing was recorded
+    /// yet.
+    pub(crate) fn check_write(&mut self, addr: u16, old: u8, new: u8) {
+        if self.watchpoint_hit.is_none() && self.write_watchpoints.contains(&addr) {
+            self.watchpoint_hit = Some((addr, old, new, WatchKind::Write));
+        }
+    }
+
+    /// Records the first watched read of `bytes`, which were read starting at `start`, if nothing
+    /// was recorded yet.
+    pub(crate) fn check_reads(&mut self, start: usize, bytes: &[u8]) {
+        if self.watchpoint_hit.is_some() || self.read_watchpoints.is_empty() {
+            return;
+        }
+        let hit = bytes.iter().enumerate()
+            .map(|(i, byte)| ((start + i) as u16, *byte))
+            .find(|(addr, _)| self.read_watchpoints.contains(addr));
+        self.watchpoint_hit = hit.map(|(addr, byte)| (addr, byte, byte, WatchKind::Read));
+    }
+
+    /// Returns the watched access recorded since the last call, if any.
+    pub(crate) fn take_watchpoint_hit(&mut self) -> Option<WatchpointHit> {
+        self.watchpoint_hit.take()
+    }
+}
+
+impl Chip8 {
+    /// Stops execution before the instruction at `addr` runs, `step` reports it with
+    /// `StepOutcome::BreakpointHit` and the next `step` runs the instruction.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.debugger.breakpoints.insert(addr);
+    }
+
+    /// Removes the breakpoint at `addr`, if there is one.
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.debugger.breakpoints.remove(&addr);
+    }
+
+    /// Returns whether there is a breakpoint at `addr`.
+    pub fn has_breakpoint(&self, addr: u16) -> bool {
+        self.debugger.breakpoints.contains(&addr)
+    }
+
+    /// Removes all the breakpoints.
+    pub fn clear_breakpoints(&mut self) {
+        self.debugger.breakpoints.clear();
+        self.debugger.resume_from = None;
+    }
+
+    /// Sets a hook that is called with the address and opcode of each instruction before `step`
+    /// executes it, e.g. to log a trace with the disassembler.
+    pub fn set_trace_hook(&mut self, hook: impl FnMut(u16, u16) + 'static) {
+        self.debugger.trace_hook = TraceHook(Some(Box::new(hook)));
+    }
+
+    /// Removes the trace hook.
+    pub fn clear_trace_hook(&mut self) {
+        self.debugger.trace_hook = TraceHook(None);
+    }
+
+    /// Sets a hook that is called before `step` executes each instruction, with the instruction
+    /// and the registers, index register and timers at that moment. The hook only gets a view of
+    /// the machine, so it can't change it.
+    pub fn set_step_hook(&mut self, hook: Box<dyn FnMut(&StepInfo)>) {
+        self.debugger.step_hook = StepHook(Some(hook));
+    }
+
+    /// Removes the step hook.
+    pub fn clear_step_hook(&mut self) {
+        self.debugger.step_hook = StepHook(None);
+    }
+
+    /// Reports instructions that write to the addresses in `range`, `step` returns
+    /// `StepOutcome::WatchpointHit` after running them. Writes through `write_memory` aren't
+    /// reported.
+    pub fn watch_write(&mut self, range: Range<u16>) {
+        self.debugger.write_watchpoints.extend(range);
+    }
+
+    /// Reports instructions that read the addresses in `range` as data, like the sprites of DXYN
+    /// or the registers loaded by FX65. Fetching instructions isn't reported.
+    pub fn watch_read(&mut self, range: Range<u16>) {
+        self.debugger.read_watchpoints.extend(range);
+    }
+
+    /// Stops watching reads and writes of the addresses in `range`.
+    pub fn unwatch(&mut self, range: Range<u16>) {
+        for addr in range {
+            self.debugger.read_watchpoints.remove(&addr);
+            self.debugger.write_watchpoints.remove(&addr);
+        }
+    }
+
+    /// Returns whether accesses of `kind` to `addr` are watched.
+    pub fn is_watched(&self, addr: u16, kind: WatchKind) -> bool {
+        match kind {
+            WatchKind::Read => self.debugger.read_watchpoints.contains(&addr),
+            WatchKind::Write => self.debugger.write_watchpoints.contains(&addr),
+        }
+    }
+
+    /// Removes all the watchpoints.
+    pub fn clear_watchpoints(&mut self) {
+        self.debugger.read_watchpoints.clear();
+        self.debugger.write_watchpoints.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::WatchKind;
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::{Chip8, StepOutcome};
+
+    #[test]
+    fn stops_before_breakpoint() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x63, 0x04]).unwrap();
+        emu.add_breakpoint(0x204);
+        assert!(emu.has_breakpoint(0x204));
+        emu.step().unwrap();
+        emu.step().unwrap();
+        let before = emu.clone();
+        assert_eq!(emu.step(), Ok(StepOutcome::BreakpointHit { pc: 0x204 }));
+        assert_eq!(emu, before);
+        assert_eq!(emu.register(2), 0);
+        assert_eq!(emu.pc(), 0x204);
+
+        // Stepping again runs the instruction
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.register(2), 3);
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.register(3), 4);
+        assert_eq!(emu.cycles(), 4);
+    }
+
+    #[test]
+    fn breakpoint_hits_every_time_reached() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x70, 0x01, 0x12, 0x00]).unwrap(); // Count up in V0 forever
+        emu.add_breakpoint(0x202);
+        assert_eq!(emu.step_n(100), Ok(()));
+        assert_eq!(emu.register(0), 1);
+        assert_eq!(emu.pc(), 0x202);
+        emu.step_n(100).unwrap();
+        assert_eq!(emu.register(0), 2);
+        emu.remove_breakpoint(0x202);
+        emu.step_n(100).unwrap();
+        assert_eq!(emu.register(0), 52);
+    }
+
+    #[test]
+    fn breakpoint_on_first_instruction() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x60, 0x01, 0x61, 0x02]).unwrap();
+        emu.add_breakpoint(0x200);
+        let before = emu.clone();
+        assert_eq!(emu.step(), Ok(StepOutcome::BreakpointHit { pc: 0x200 }));
+        assert_eq!(emu.save_state(), before.save_state());
+        assert_eq!(emu.cycles(), 0);
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.register(0), 1);
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.register(1), 2);
+    }
+
+    #[test]
+    fn breakpoint_in_subroutine() {
+        let mut emu = Chip8::new();
+        emu.load(&[
+            0x22, 0x06, // Call 206
+            0x12, 0x02, // Loop forever
+            0x00, 0x00,
+            0x60, 0x05, 0x70, 0x01, 0x00, 0xee, // V0 = 5, V0 += 1, return
+        ]).unwrap();
+        emu.add_breakpoint(0x208);
+        emu.step_n(10).unwrap();
+        assert_eq!(emu.pc(), 0x208);
+        assert_eq!(emu.stack(), [0x202]);
+        assert_eq!(emu.register(0), 5);
+        emu.step_n(2).unwrap();
+        assert_eq!(emu.register(0), 6);
+        assert_eq!(emu.pc(), 0x202);
+        assert!(emu.stack().is_empty());
+    }
+
+    #[test]
+    fn breakpoint_reached_by_skip() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x30, 0x00, 0x60, 0x01, 0x61, 0x02]).unwrap(); // Skip if V0 == 0
+        emu.add_breakpoint(0x202);
+        emu.add_breakpoint(0x204);
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.step(), Ok(StepOutcome::BreakpointHit { pc: 0x204 }));
+        assert_eq!(emu.register(0), 0);
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.register(1), 2);
+    }
+
+    #[test]
+    fn clearing_breakpoints() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        emu.add_breakpoint(0x200);
+        emu.add_breakpoint(0x202);
+        assert_eq!(emu.step(), Ok(StepOutcome::BreakpointHit { pc: 0x200 }));
+        emu.clear_breakpoints();
+        assert!(!emu.has_breakpoint(0x200));
+        assert!(!emu.has_breakpoint(0x202));
+        emu.step_n(10).unwrap();
+        assert_eq!(emu.register(0), 5);
+    }
+
+    #[test]
+    fn traces_executed_instructions() {
+        let mut emu = Chip8::new();
+        emu.load(&[
+            0x22, 0x06, // Call 206
+            0x12, 0x02, // Loop forever
+            0x00, 0x00,
+            0x30, 0x00, 0x00, 0x00, 0x00, 0xee, // Skip if V0 == 0, return
+        ]).unwrap();
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let log = trace.clone();
+        emu.set_trace_hook(move |pc, opcode| log.borrow_mut().push((pc, opcode)));
+        emu.add_breakpoint(0x202);
+        emu.step_n(10).unwrap();
+        assert_eq!(*trace.borrow(), [(0x200, 0x2206), (0x206, 0x3000), (0x20a, 0x00ee)]);
+
+        // The jump back to the breakpoint stops before tracing it again
+        trace.borrow_mut().clear();
+        emu.step_n(2).unwrap();
+        assert_eq!(*trace.borrow(), [(0x202, 0x1202)]);
+
+        emu.clear_breakpoints();
+        emu.step_n(2).unwrap();
+        assert_eq!(*trace.borrow(), [(0x202, 0x1202); 3]);
+        emu.clear_trace_hook();
+        emu.step_n(2).unwrap();
+        assert_eq!(trace.borrow().len(), 3);
+    }
+
+    #[test]
+    fn step_hook_follows_control_flow() {
+        let mut emu = Chip8::new();
+        emu.load(&[
+            0x60, 0x00, // 200: V0 = 0
+            0x22, 0x0c, // 202: Call 20C
+            0x30, 0x05, // 204: Skip if V0 == 5
+            0x12, 0x02, // 206: Jump to 202
+            0x12, 0x08, // 208: Loop forever
+            0x00, 0x00,
+            0x70, 0x01, // 20C: V0 += 1
+            0x00, 0xee, // 20E: Return
+        ]).unwrap();
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let log = steps.clone();
+        emu.set_step_hook(Box::new(move |info| {
+            log.borrow_mut().push((info.pc, info.opcode, info.registers[0], info.index_register));
+        }));
+        emu.step_n(100).unwrap();
+
+        let steps = steps.borrow();
+        assert_eq!(steps.len(), 100);
+        let mut expected = vec![(0x200, 0x6000, 0)];
+        for v0 in 0..5 {
+            expected.extend([(0x202, 0x220c, v0), (0x20c, 0x7001, v0), (0x20e, 0x00ee, v0 + 1), (0x204, 0x3005, v0 + 1)]);
+            if v0 < 4 {
+                expected.push((0x206, 0x1202, v0 + 1));
+            }
+        }
+        expected.resize(100, (0x208, 0x1208, 5));
+        let pcs: Vec<_> = steps.iter().map(|(pc, opcode, v0, _)| (*pc, *opcode, *v0)).collect();
+        assert_eq!(pcs, expected);
+        assert!(steps.iter().all(|step| step.3 == 0));
+
+        emu.clear_step_hook();
+        emu.step_n(10).unwrap();
+        assert_eq!(emu.cycles(), 110);
+    }
+
+    #[test]
+    fn reports_watched_writes() {
+        let mut emu = Chip8::new();
+        emu.load(&[
+            0x60, 0x11, 0x61, 0x22, 0x62, 0x33, // V0 = 11, V1 = 22, V2 = 33
+            0xa2, 0xff, 0xf2, 0x55, // I = 2FF, store V0 - V2
+            0xa3, 0x00, 0xf0, 0x33, // I = 300, store the digits of V0
+            0x70, 0x01, // V0 += 1
+        ]).unwrap();
+        emu.watch_write(0x300..0x301);
+        assert!(emu.is_watched(0x300, WatchKind::Write));
+        assert!(!emu.is_watched(0x300, WatchKind::Read));
+        emu.step_n(4).unwrap();
+        assert_eq!(emu.step(), Ok(StepOutcome::WatchpointHit { addr: 0x300, pc: 0x208, old: 0, new: 0x22, kind: WatchKind::Write }));
+        assert_eq!(emu.read_memory_range(0x2ff, 3), Some(&[0x11, 0x22, 0x33][..]));
+        assert_eq!(emu.index(), 0x2ff);
+        assert_eq!(emu.cycles(), 5);
+
+        // The next step runs on
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.step(), Ok(StepOutcome::WatchpointHit { addr: 0x300, pc: 0x20c, old: 0x22, new: 0, kind: WatchKind::Write }));
+        assert_eq!(emu.read_memory_range(0x300, 3), Some(&[0, 1, 7][..]));
+
+        emu.unwatch(0x300..0x301);
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+
+        // Writing the same value is still reported
+        let mut emu = Chip8::new();
+        emu.load(&[0xa3, 0x00, 0xf2, 0x55, 0xf2, 0x55]).unwrap();
+        emu.watch_write(0x302..0x310);
+        emu.step().unwrap();
+        assert_eq!(emu.step(), Ok(StepOutcome::WatchpointHit { addr: 0x302, pc: 0x202, old: 0, new: 0, kind: WatchKind::Write }));
+    }
+
+    #[test]
+    fn reports_watched_reads() {
+        let mut emu = Chip8::new();
+        emu.load(&[
+            0xa0, 0x50, 0xd0, 0x15, // Draw the 0 of the font
+            0xa0, 0x55, 0xd0, 0x15, // Draw the 1
+            0xa3, 0x00, 0xf3, 0x65, // Load V0 - V3 from 300
+            0x12, 0x0c,
+        ]).unwrap();
+        emu.write_memory_slice(0x300, &[1, 2, 3, 4]).unwrap();
+        emu.watch_read(0x55..0x5a);
+        emu.watch_read(0x303..0x304);
+        emu.watch_write(0x300..0x304);
+        emu.step_n(3).unwrap();
+        assert_eq!(emu.pc(), 0x206);
+        assert_eq!(emu.step(), Ok(StepOutcome::WatchpointHit { addr: 0x55, pc: 0x206, old: 0x20, new: 0x20, kind: WatchKind::Read }));
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.step(), Ok(StepOutcome::WatchpointHit { addr: 0x303, pc: 0x20a, old: 4, new: 4, kind: WatchKind::Read }));
+        assert_eq!(emu.register(3), 4);
+
+        emu.clear_watchpoints();
+        assert!(!emu.is_watched(0x303, WatchKind::Read));
+        emu.step_n(10).unwrap();
+        assert_eq!(emu.cycles(), 16);
+    }
+}
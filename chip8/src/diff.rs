@@ -0,0 +1,169 @@
+//! Comparing two machine states, to find out what an instruction or a quirk changed.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{Chip8, HIRES_HEIGHT};
+
+/// The differences between two machines, each as the value in the first machine followed by the
+/// value in the second. Returned by `Chip8::diff`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    /// The registers that differ, as (register number, first value, second value).
+    pub registers: Vec<(usize, u8, u8)>,
+    pub program_counter: Option<(u16, u16)>,
+    pub index_register: Option<(u16, u16)>,
+    pub delay_timer: Option<(u8, u8)>,
+    pub sound_timer: Option<(u8, u8)>,
+    /// The runs of differing memory bytes as (start address, length). Bytes past the end of the
+    /// smaller memory count as differing.
+    pub memory: Vec<(usize, usize)>,
+    pub stack: Option<(Vec<u16>, Vec<u16>)>,
+    /// The number of pixels with a different color index. Pixels are compared by their position
+    /// in the display buffer, so a different resolution usually differs everywhere.
+    pub display_pixels: usize,
+}
+
+impl StateDiff {
+    /// Returns whether no differences were found.
+    pub fn is_empty(&self) -> bool {
+        *self == StateDiff::default()
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no differences");
+        }
+        for (reg, first, second) in &self.registers {
+            writeln!(f, "V{:X}: {:02X} -> {:02X}", reg, first, second)?;
+        }
+        if let Some((first, second)) = self.program_counter {
+            writeln!(f, "PC: {:03X} -> {:03X}", first, second)?;
+        }
+        if let Some((first, second)) = self.index_register {
+            writeln!(f, "I: {:03X} -> {:03X}", first, second)?;
+        }
+        if let Some((first, second)) = self.delay_timer {
+            writeln!(f, "delay timer: {} -> {}", first, second)?;
+        }
+        if let Some((first, second)) = self.sound_timer {
+            writeln!(f, "sound timer: {} -> {}", first, second)?;
+        }
+        for (start, len) in &self.memory {
+            writeln!(f, "memory: {:03X}..{:03X} ({} bytes)", start, start + len, len)?;
+        }
+        if let Some((first, second)) = &self.stack {
+            writeln!(f, "stack: {:03X?} -> {:03X?}", first, second)?;
+        }
+        if self.display_pixels > 0 {
+            writeln!(f, "display: {} pixels", self.display_pixels)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns `Some((first, second))` if they differ.
+fn changed<T: PartialEq>(first: T, second: T) -> Option<(T, T)> {
+    (first != second).then_some((first, second))
+}
+
+/// Returns the runs of differing bytes as (start, length).
+fn memory_runs(first: &[u8], second: &[u8]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for address in 0..first.len().max(second.len()) {
+        if first.get(address) == second.get(address) {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((start, len)) if *start + *len == address => *len += 1,
+            _ => runs.push((address, 1)),
+        }
+    }
+    runs
+}
+
+impl Chip8 {
+    /// Compares this machine with `other`, reporting the registers, timers, memory, stack and
+    /// display that differ.
+    pub fn diff(&self, other: &Chip8) -> StateDiff {
+        StateDiff {
+            registers: (0..16)
+                .filter(|reg| self.registers[*reg] != other.registers[*reg])
+                .map(|reg| (reg, self.registers[reg], other.registers[reg]))
+                .collect(),
+            program_counter: changed(self.program_counter, other.program_counter),
+            index_register: changed(self.index_register, other.index_register),
+            delay_timer: changed(self.delay_timer, other.delay_timer),
+            sound_timer: changed(self.sound_timer, other.sound_timer),
+            memory: memory_runs(&self.memory, &other.memory),
+            stack: changed(self.stack().to_vec(), other.stack().to_vec()),
+            display_pixels: (0..HIRES_HEIGHT)
+                .map(|y| {
+                    let first = self.plane_rows[y] ^ other.plane_rows[y];
+                    let second = self.plane_rows[HIRES_HEIGHT + y] ^ other.plane_rows[HIRES_HEIGHT + y];
+                    (first | second).count_ones() as usize
+                })
+                .sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_machines() {
+        let emu = Chip8::new();
+        let diff = emu.diff(&emu.clone());
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "no differences\n");
+    }
+
+    #[test]
+    fn small_differences() {
+        let first = Chip8::new();
+        let mut second = first.clone();
+        second.registers[3] = 0x42;
+        second.registers[0xf] = 1;
+        second.program_counter = 0x204;
+        second.sound_timer = 5;
+        second.memory[0x300] = 1;
+        second.memory[0x301] = 2;
+        second.memory[0x310] = 3;
+        second.stack.push(0x202);
+        second.set_pixel(1, 1, true);
+        second.set_pixel(2, 1, true);
+
+        let diff = first.diff(&second);
+        assert_eq!(diff, StateDiff {
+            registers: vec![(3, 0, 0x42), (0xf, 0, 1)],
+            program_counter: Some((0x200, 0x204)),
+            index_register: None,
+            delay_timer: None,
+            sound_timer: Some((0, 5)),
+            memory: vec![(0x300, 2), (0x310, 1)],
+            stack: Some((vec![], vec![0x202])),
+            display_pixels: 2,
+        });
+        assert_eq!(diff.to_string(), "\
+V3: 00 -> 42
+VF: 00 -> 01
+PC: 200 -> 204
+sound timer: 0 -> 5
+memory: 300..302 (2 bytes)
+memory: 310..311 (1 bytes)
+stack: [] -> [202]
+display: 2 pixels
+");
+    }
+
+    #[test]
+    fn different_memory_sizes() {
+        assert_eq!(memory_runs(&[1, 2, 3], &[1, 5, 3, 0, 0]), vec![(1, 1), (3, 2)]);
+        let diff = Chip8::new().diff(&Chip8::with_variant(crate::Variant::XoChip));
+        assert_eq!(diff.memory.last(), Some(&(0x1000, 0xf000)));
+    }
+}
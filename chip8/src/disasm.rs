@@ -0,0 +1,638 @@
+//! Decoding opcodes into instructions and showing them as mnemonics.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A decoded instruction, named after what it does. `x` and `y` are register numbers, `n`, `nn`
+/// and `nnn` are the 4, 8 and 12 bit values of the opcode. Instructions only some platforms have
+/// are decoded whatever the platform, executing them checks it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00E0
+    ClearScreen,
+    /// 00EE
+    Return,
+    /// 00CN, SUPER-CHIP
+    ScrollDown(u8),
+    /// 00DN, XO-CHIP
+    ScrollUp(u8),
+    /// 00FB, SUPER-CHIP
+    ScrollRight,
+    /// 00FC, SUPER-CHIP
+    ScrollLeft,
+    /// 00FD, SUPER-CHIP
+    Exit,
+    /// 00FE, SUPER-CHIP
+    LowRes,
+    /// 00FF, SUPER-CHIP
+    HighRes,
+    /// 0NNN, a machine code routine that isn't emulated
+    System(u16),
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SkipEqImm { x: u8, nn: u8 },
+    /// 4XNN
+    SkipNeImm { x: u8, nn: u8 },
+    /// 5XY0
+    SkipEqReg { x: u8, y: u8 },
+    /// 5XY2, XO-CHIP
+    SaveRange { x: u8, y: u8 },
+    /// 5XY3, XO-CHIP
+    LoadRange { x: u8, y: u8 },
+    /// 6XNN
+    LoadImm { x: u8, nn: u8 },
+    /// 7XNN
+    AddImm { x: u8, nn: u8 },
+    /// 8XY0
+    LoadReg { x: u8, y: u8 },
+    /// 8XY1
+    Or { x: u8, y: u8 },
+    /// 8XY2
+    And { x: u8, y: u8 },
+    /// 8XY3
+    Xor { x: u8, y: u8 },
+    /// 8XY4
+    AddReg { x: u8, y: u8 },
+    /// 8XY5
+    Sub { x: u8, y: u8 },
+    /// 8XY6
+    ShiftRight { x: u8, y: u8 },
+    /// 8XY7
+    SubReverse { x: u8, y: u8 },
+    /// 8XYE
+    ShiftLeft { x: u8, y: u8 },
+    /// 9XY0
+    SkipNeReg { x: u8, y: u8 },
+    /// ANNN
+    LoadIndex(u16),
+    /// BNNN, or BXNN with the jump quirk
+    JumpOffset(u16),
+    /// CXNN
+    Random { x: u8, nn: u8 },
+    /// DXYN
+    Draw { x: u8, y: u8, n: u8 },
+    /// EX9E
+    SkipKey { x: u8 },
+    /// EXA1
+    SkipNotKey { x: u8 },
+    /// F000 NNNN, XO-CHIP, the only 4 byte instruction. The address NNNN is the word after the
+    /// opcode, so `decode` leaves it at 0 and `decode_at` reads it.
+    LoadIndexLong(u16),
+    /// FN01, XO-CHIP
+    SelectPlanes(u8),
+    /// F002, XO-CHIP
+    LoadAudio,
+    /// FX07
+    GetDelay { x: u8 },
+    /// FX0A
+    WaitKey { x: u8 },
+    /// FX15
+    SetDelay { x: u8 },
+    /// FX18
+    SetSound { x: u8 },
+    /// FX1E
+    AddIndex { x: u8 },
+    /// FX29
+    LoadFont { x: u8 },
+    /// FX30, SUPER-CHIP
+    LoadBigFont { x: u8 },
+    /// FX33
+    StoreBcd { x: u8 },
+    /// FX3A, XO-CHIP
+    SetPitch { x: u8 },
+    /// FX55
+    StoreRegs { x: u8 },
+    /// FX65
+    LoadRegs { x: u8 },
+    /// FX75, SUPER-CHIP
+    StoreFlags { x: u8 },
+    /// FX85, SUPER-CHIP
+    LoadFlags { x: u8 },
+    /// An opcode no platform defines.
+    Unknown(u16),
+}
+
+/// Decodes the instruction at `addr` in `mem`, reading the address word of F000 too. `None` if
+/// any of its bytes are past the end of `mem`.
+pub fn decode_at(mem: &[u8], addr: usize) -> Option<Instruction> {
+    let word = |addr: usize| match mem.get(addr..addr.checked_add(2)?) {
+        Some(&[high, low]) => Some(u16::from_be_bytes([high, low])),
+        _ => None,
+    };
+    match decode(word(addr)?) {
+        Instruction::LoadIndexLong(_) => Some(Instruction::LoadIndexLong(word(addr + 2)?)),
+        instruction => Some(instruction),
+    }
+}
+
+/// Decodes `opcode` into its instruction, `Instruction::Unknown` if it isn't one.
+pub fn decode(opcode: u16) -> Instruction {
+    use Instruction::*;
+
+    let nibbles = (
+        (opcode >> 12) as u8,
+        (opcode >> 8 & 0xf) as u8,
+        (opcode >> 4 & 0xf) as u8,
+        (opcode & 0xf) as u8
+    );
+    let nnn = opcode & 0xfff;
+    let nn = (opcode & 0xff) as u8;
+
+    match nibbles {
+        (0x0, 0x0, 0xe, 0x0) => ClearScreen,
+        (0x0, 0x0, 0xe, 0xe) => Return,
+        (0x0, 0x0, 0xc, n) => ScrollDown(n),
+        (0x0, 0x0, 0xd, n) => ScrollUp(n),
+        (0x0, 0x0, 0xf, 0xb) => ScrollRight,
+        (0x0, 0x0, 0xf, 0xc) => ScrollLeft,
+        (0x0, 0x0, 0xf, 0xd) => Exit,
+        (0x0, 0x0, 0xf, 0xe) => LowRes,
+        (0x0, 0x0, 0xf, 0xf) => HighRes,
+        (0x0, _, _, _) => System(nnn),
+        (0x1, _, _, _) => Jump(nnn),
+        (0x2, _, _, _) => Call(nnn),
+        (0x3, x, _, _) => SkipEqImm { x, nn },
+        (0x4, x, _, _) => SkipNeImm { x, nn },
+        (0x5, x, y, 0x0) => SkipEqReg { x, y },
+        (0x5, x, y, 0x2) => SaveRange { x, y },
+        (0x5, x, y, 0x3) => LoadRange { x, y },
+        (0x6, x, _, _) => LoadImm { x, nn },
+        (0x7, x, _, _) => AddImm { x, nn },
+        (0x8, x, y, 0x0) => LoadReg { x, y },
+        (0x8, x, y, 0x1) => Or { x, y },
+        (0x8, x, y, 0x2) => And { x, y },
+        (0x8, x, y, 0x3) => Xor { x, y },
+        (0x8, x, y, 0x4) => AddReg { x, y },
+        (0x8, x, y, 0x5) => Sub { x, y },
+        (0x8, x, y, 0x6) => ShiftRight { x, y },
+        (0x8, x, y, 0x7) => SubReverse { x, y },
+        (0x8, x, y, 0xe) => ShiftLeft { x, y },
+        (0x9, x, y, 0x0) => SkipNeReg { x, y },
+        (0xa, _, _, _) => LoadIndex(nnn),
+        (0xb, _, _, _) => JumpOffset(nnn),
+        (0xc, x, _, _) => Random { x, nn },
+        (0xd, x, y, n) => Draw { x, y, n },
+        (0xe, x, 0x9, 0xe) => SkipKey { x },
+        (0xe, x, 0xa, 0x1) => SkipNotKey { x },
+        (0xf, 0x0, 0x0, 0x0) => LoadIndexLong(0),
+        (0xf, n, 0x0, 0x1) => SelectPlanes(n),
+        (0xf, 0x0, 0x0, 0x2) => LoadAudio,
+        (0xf, x, 0x0, 0x7) => GetDelay { x },
+        (0xf, x, 0x0, 0xa) => WaitKey { x },
+        (0xf, x, 0x1, 0x5) => SetDelay { x },
+        (0xf, x, 0x1, 0x8) => SetSound { x },
+        (0xf, x, 0x1, 0xe) => AddIndex { x },
+        (0xf, x, 0x2, 0x9) => LoadFont { x },
+        (0xf, x, 0x3, 0x0) => LoadBigFont { x },
+        (0xf, x, 0x3, 0x3) => StoreBcd { x },
+        (0xf, x, 0x3, 0xa) => SetPitch { x },
+        (0xf, x, 0x5, 0x5) => StoreRegs { x },
+        (0xf, x, 0x6, 0x5) => LoadRegs { x },
+        (0xf, x, 0x7, 0x5) => StoreFlags { x },
+        (0xf, x, 0x8, 0x5) => LoadFlags { x },
+        (_, _, _, _) => Unknown(opcode),
+    }
+}
+
+impl Instruction {
+    /// Returns the size of the instruction in bytes, 4 for `LoadIndexLong` and 2 for the others.
+    pub fn size(&self) -> usize {
+        if let Instruction::LoadIndexLong(_) = self { 4 } else { 2 }
+    }
+
+    /// Encodes the instruction back into its opcode, the first word of `LoadIndexLong`. Values too
+    /// large for their field are cut to fit, e.g. the register numbers to 4 bits.
+    pub fn opcode(&self) -> u16 {
+        use Instruction::*;
+
+        let xy = |high: u16, x: u8, y: u8, low: u16| high << 12 | (x as u16 & 0xf) << 8 | (y as u16 & 0xf) << 4 | low;
+        let xnn = |high: u16, x: u8, nn: u8| high << 12 | (x as u16 & 0xf) << 8 | nn as u16;
+        let nnn = |high: u16, nnn: u16| high << 12 | nnn & 0xfff;
+        match *self {
+            ClearScreen => 0x00e0,
+            Return => 0x00ee,
+            ScrollDown(n) => 0x00c0 | n as u16 & 0xf,
+            ScrollUp(n) => 0x00d0 | n as u16 & 0xf,
+            ScrollRight => 0x00fb,
+            ScrollLeft => 0x00fc,
+            Exit => 0x00fd,
+            LowRes => 0x00fe,
+            HighRes => 0x00ff,
+            System(addr) => nnn(0x0, addr),
+            Jump(addr) => nnn(0x1, addr),
+            Call(addr) => nnn(0x2, addr),
+            SkipEqImm { x, nn } => xnn(0x3, x, nn),
+            SkipNeImm { x, nn } => xnn(0x4, x, nn),
+            SkipEqReg { x, y } => xy(0x5, x, y, 0x0),
+            SaveRange { x, y } => xy(0x5, x, y, 0x2),
+            LoadRange { x, y } => xy(0x5, x, y, 0x3),
+            LoadImm { x, nn } => xnn(0x6, x, nn),
+            AddImm { x, nn } => xnn(0x7, x, nn),
+            LoadReg { x, y } => xy(0x8, x, y, 0x0),
+            Or { x, y } => xy(0x8, x, y, 0x1),
+            And { x, y } => xy(0x8, x, y, 0x2),
+            Xor { x, y } => xy(0x8, x, y, 0x3),
+            AddReg { x, y } => xy(0x8, x, y, 0x4),
+            Sub { x, y } => xy(0x8, x, y, 0x5),
+            ShiftRight { x, y } => xy(0x8, x, y, 0x6),
+            SubReverse { x, y } => xy(0x8, x, y, 0x7),
+            ShiftLeft { x, y } => xy(0x8, x, y, 0xe),
+            SkipNeReg { x, y } => xy(0x9, x, y, 0x0),
+            LoadIndex(addr) => nnn(0xa, addr),
+            JumpOffset(addr) => nnn(0xb, addr),
+            Random { x, nn } => xnn(0xc, x, nn),
+            Draw { x, y, n } => xy(0xd, x, y, n as u16 & 0xf),
+            SkipKey { x } => xnn(0xe, x, 0x9e),
+            SkipNotKey { x } => xnn(0xe, x, 0xa1),
+            LoadIndexLong(_) => 0xf000,
+            SelectPlanes(n) => xnn(0xf, n, 0x01),
+            LoadAudio => 0xf002,
+            GetDelay { x } => xnn(0xf, x, 0x07),
+            WaitKey { x } => xnn(0xf, x, 0x0a),
+            SetDelay { x } => xnn(0xf, x, 0x15),
+            SetSound { x } => xnn(0xf, x, 0x18),
+            AddIndex { x } => xnn(0xf, x, 0x1e),
+            LoadFont { x } => xnn(0xf, x, 0x29),
+            LoadBigFont { x } => xnn(0xf, x, 0x30),
+            StoreBcd { x } => xnn(0xf, x, 0x33),
+            SetPitch { x } => xnn(0xf, x, 0x3a),
+            StoreRegs { x } => xnn(0xf, x, 0x55),
+            LoadRegs { x } => xnn(0xf, x, 0x65),
+            StoreFlags { x } => xnn(0xf, x, 0x75),
+            LoadFlags { x } => xnn(0xf, x, 0x85),
+            Unknown(opcode) => opcode,
+        }
+    }
+
+    /// Returns which instruction this is, without its operands.
+    pub fn kind(&self) -> InstructionKind {
+        use Instruction::*;
+
+        match *self {
+            ClearScreen => InstructionKind::ClearScreen,
+            Return => InstructionKind::Return,
+            ScrollDown(_) => InstructionKind::ScrollDown,
+            ScrollUp(_) => InstructionKind::ScrollUp,
+            ScrollRight => InstructionKind::ScrollRight,
+            ScrollLeft => InstructionKind::ScrollLeft,
+            Exit => InstructionKind::Exit,
+            LowRes => InstructionKind::LowRes,
+            HighRes => InstructionKind::HighRes,
+            System(_) => InstructionKind::System,
+            Jump(_) => InstructionKind::Jump,
+            Call(_) => InstructionKind::Call,
+            SkipEqImm { .. } => InstructionKind::SkipEqImm,
+            SkipNeImm { .. } => InstructionKind::SkipNeImm,
+            SkipEqReg { .. } => InstructionKind::SkipEqReg,
+            SaveRange { .. } => InstructionKind::SaveRange,
+            LoadRange { .. } => InstructionKind::LoadRange,
+            LoadImm { .. } => InstructionKind::LoadImm,
+            AddImm { .. } => InstructionKind::AddImm,
+            LoadReg { .. } => InstructionKind::LoadReg,
+            Or { .. } => InstructionKind::Or,
+            And { .. } => InstructionKind::And,
+            Xor { .. } => InstructionKind::Xor,
+            AddReg { .. } => InstructionKind::AddReg,
+            Sub { .. } => InstructionKind::Sub,
+            ShiftRight { .. } => InstructionKind::ShiftRight,
+            SubReverse { .. } => InstructionKind::SubReverse,
+            ShiftLeft { .. } => InstructionKind::ShiftLeft,
+            SkipNeReg { .. } => InstructionKind::SkipNeReg,
+            LoadIndex(_) => InstructionKind::LoadIndex,
+            JumpOffset(_) => InstructionKind::JumpOffset,
+            Random { .. } => InstructionKind::Random,
+            Draw { .. } => InstructionKind::Draw,
+            SkipKey { .. } => InstructionKind::SkipKey,
+            SkipNotKey { .. } => InstructionKind::SkipNotKey,
+            LoadIndexLong(_) => InstructionKind::LoadIndexLong,
+            SelectPlanes(_) => InstructionKind::SelectPlanes,
+            LoadAudio => InstructionKind::LoadAudio,
+            GetDelay { .. } => InstructionKind::GetDelay,
+            WaitKey { .. } => InstructionKind::WaitKey,
+            SetDelay { .. } => InstructionKind::SetDelay,
+            SetSound { .. } => InstructionKind::SetSound,
+            AddIndex { .. } => InstructionKind::AddIndex,
+            LoadFont { .. } => InstructionKind::LoadFont,
+            LoadBigFont { .. } => InstructionKind::LoadBigFont,
+            StoreBcd { .. } => InstructionKind::StoreBcd,
+            SetPitch { .. } => InstructionKind::SetPitch,
+            StoreRegs { .. } => InstructionKind::StoreRegs,
+            LoadRegs { .. } => InstructionKind::LoadRegs,
+            StoreFlags { .. } => InstructionKind::StoreFlags,
+            LoadFlags { .. } => InstructionKind::LoadFlags,
+            Unknown(_) => InstructionKind::Unknown,
+        }
+    }
+}
+
+/// The kind of an `Instruction`, a variant without its operands. Usable as an index with
+/// `kind as usize`, from 0 to `InstructionKind::COUNT - 1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InstructionKind {
+    ClearScreen,
+    Return,
+    ScrollDown,
+    ScrollUp,
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
+    System,
+    Jump,
+    Call,
+    SkipEqImm,
+    SkipNeImm,
+    SkipEqReg,
+    SaveRange,
+    LoadRange,
+    LoadImm,
+    AddImm,
+    LoadReg,
+    Or,
+    And,
+    Xor,
+    AddReg,
+    Sub,
+    ShiftRight,
+    SubReverse,
+    ShiftLeft,
+    SkipNeReg,
+    LoadIndex,
+    JumpOffset,
+    Random,
+    Draw,
+    SkipKey,
+    SkipNotKey,
+    LoadIndexLong,
+    SelectPlanes,
+    LoadAudio,
+    GetDelay,
+    WaitKey,
+    SetDelay,
+    SetSound,
+    AddIndex,
+    LoadFont,
+    LoadBigFont,
+    StoreBcd,
+    SetPitch,
+    StoreRegs,
+    LoadRegs,
+    StoreFlags,
+    LoadFlags,
+    Unknown,
+}
+
+impl InstructionKind {
+    /// The number of kinds.
+    pub const COUNT: usize = InstructionKind::ALL.len();
+
+    /// Every kind, in the order of their index.
+    pub const ALL: [InstructionKind; 52] = [
+        InstructionKind::ClearScreen, InstructionKind::Return, InstructionKind::ScrollDown,
+        InstructionKind::ScrollUp, InstructionKind::ScrollRight, InstructionKind::ScrollLeft,
+        InstructionKind::Exit, InstructionKind::LowRes, InstructionKind::HighRes,
+        InstructionKind::System, InstructionKind::Jump, InstructionKind::Call,
+        InstructionKind::SkipEqImm, InstructionKind::SkipNeImm, InstructionKind::SkipEqReg,
+        InstructionKind::SaveRange, InstructionKind::LoadRange, InstructionKind::LoadImm,
+        InstructionKind::AddImm, InstructionKind::LoadReg, InstructionKind::Or,
+        InstructionKind::And, InstructionKind::Xor, InstructionKind::AddReg, InstructionKind::Sub,
+        InstructionKind::ShiftRight, InstructionKind::SubReverse, InstructionKind::ShiftLeft,
+        InstructionKind::SkipNeReg, InstructionKind::LoadIndex, InstructionKind::JumpOffset,
+        InstructionKind::Random, InstructionKind::Draw, InstructionKind::SkipKey,
+        InstructionKind::SkipNotKey, InstructionKind::LoadIndexLong, InstructionKind::SelectPlanes,
+        InstructionKind::LoadAudio, InstructionKind::GetDelay, InstructionKind::WaitKey,
+        InstructionKind::SetDelay, InstructionKind::SetSound, InstructionKind::AddIndex,
+        InstructionKind::LoadFont, InstructionKind::LoadBigFont, InstructionKind::StoreBcd,
+        InstructionKind::SetPitch, InstructionKind::StoreRegs, InstructionKind::LoadRegs,
+        InstructionKind::StoreFlags, InstructionKind::LoadFlags, InstructionKind::Unknown,
+    ];
+}
+
+/// Shows the instruction as its mnemonic, unknown opcodes are shown as `DW 0xNNNN`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            ClearScreen => write!(f, "CLS"),
+            Return => write!(f, "RET"),
+            ScrollDown(n) => write!(f, "SCD {}", n),
+            ScrollUp(n) => write!(f, "SCU {}", n),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            LowRes => write!(f, "LOW"),
+            HighRes => write!(f, "HIGH"),
+            System(addr) => write!(f, "SYS 0x{:03X}", addr),
+            Jump(addr) => write!(f, "JP 0x{:03X}", addr),
+            Call(addr) => write!(f, "CALL 0x{:03X}", addr),
+            SkipEqImm { x, nn } => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            SkipNeImm { x, nn } => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            SkipEqReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            SaveRange { x, y } => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            LoadRange { x, y } => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            LoadImm { x, nn } => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            AddImm { x, nn } => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            LoadReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubReverse { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipNeReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadIndex(addr) => write!(f, "LD I, 0x{:03X}", addr),
+            JumpOffset(addr) => write!(f, "JP V0, 0x{:03X}", addr),
+            Random { x, nn } => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipKey { x } => write!(f, "SKP V{:X}", x),
+            SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            LoadIndexLong(addr) => write!(f, "LD I, LONG 0x{:04X}", addr),
+            SelectPlanes(n) => write!(f, "PLANE {}", n),
+            LoadAudio => write!(f, "AUDIO"),
+            GetDelay { x } => write!(f, "LD V{:X}, DT", x),
+            WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            AddIndex { x } => write!(f, "ADD I, V{:X}", x),
+            LoadFont { x } => write!(f, "LD F, V{:X}", x),
+            LoadBigFont { x } => write!(f, "LD HF, V{:X}", x),
+            StoreBcd { x } => write!(f, "LD B, V{:X}", x),
+            SetPitch { x } => write!(f, "PITCH V{:X}", x),
+            StoreRegs { x } => write!(f, "LD [I], V{:X}", x),
+            LoadRegs { x } => write!(f, "LD V{:X}, [I]", x),
+            StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            Unknown(opcode) => write!(f, "DW 0x{:04X}", opcode),
+        }
+    }
+}
+
+/// Decodes every instruction of `rom`, which is loaded at `base_addr`. A trailing odd byte is
+/// ignored, and an F000 cut off before its address is shown as data. Returns the address of each
+/// instruction along with it.
+pub fn disassemble(rom: &[u8], base_addr: u16) -> Vec<(u16, Instruction)> {
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset + 2 <= rom.len() {
+        let instruction = decode_at(rom, offset)
+            .unwrap_or_else(|| Instruction::Unknown(u16::from_be_bytes([rom[offset], rom[offset + 1]])));
+        instructions.push((base_addr.wrapping_add(offset as u16), instruction));
+        offset += instruction.size();
+    }
+    instructions
+}
+
+/// Decodes `count` instructions from `mem` starting at the address `start`, stopping early at the end of `mem`.
+/// Returns the address of each instruction along with its mnemonic.
+pub fn disassemble_range(mem: &[u8], start: u16, count: usize) -> Vec<(u16, String)> {
+    let code = mem.get(start as usize..).unwrap_or_default();
+    disassemble(code, start)
+        .into_iter()
+        .take(count)
+        .map(|(addr, instruction)| (addr, instruction.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+
+    #[test]
+    fn known_opcodes() {
+        let cases = [
+            (0x00e0, ClearScreen, "CLS"),
+            (0x00ee, Return, "RET"),
+            (0x00c5, ScrollDown(5), "SCD 5"),
+            (0x00d5, ScrollUp(5), "SCU 5"),
+            (0x00fb, ScrollRight, "SCR"),
+            (0x00fc, ScrollLeft, "SCL"),
+            (0x00fd, Exit, "EXIT"),
+            (0x00fe, LowRes, "LOW"),
+            (0x00ff, HighRes, "HIGH"),
+            (0x0123, System(0x123), "SYS 0x123"),
+            (0x1111, Jump(0x111), "JP 0x111"),
+            (0x2050, Call(0x050), "CALL 0x050"),
+            (0x3a2a, SkipEqImm { x: 0xa, nn: 0x2a }, "SE VA, 0x2A"),
+            (0x4b01, SkipNeImm { x: 0xb, nn: 0x01 }, "SNE VB, 0x01"),
+            (0x5120, SkipEqReg { x: 1, y: 2 }, "SE V1, V2"),
+            (0x5122, SaveRange { x: 1, y: 2 }, "SAVE V1 - V2"),
+            (0x5313, LoadRange { x: 3, y: 1 }, "LOAD V3 - V1"),
+            (0x632a, LoadImm { x: 3, nn: 0x2a }, "LD V3, 0x2A"),
+            (0x7eff, AddImm { x: 0xe, nn: 0xff }, "ADD VE, 0xFF"),
+            (0x8120, LoadReg { x: 1, y: 2 }, "LD V1, V2"),
+            (0x8121, Or { x: 1, y: 2 }, "OR V1, V2"),
+            (0x8122, And { x: 1, y: 2 }, "AND V1, V2"),
+            (0x8123, Xor { x: 1, y: 2 }, "XOR V1, V2"),
+            (0x8124, AddReg { x: 1, y: 2 }, "ADD V1, V2"),
+            (0x8125, Sub { x: 1, y: 2 }, "SUB V1, V2"),
+            (0x8126, ShiftRight { x: 1, y: 2 }, "SHR V1, V2"),
+            (0x8127, SubReverse { x: 1, y: 2 }, "SUBN V1, V2"),
+            (0x812e, ShiftLeft { x: 1, y: 2 }, "SHL V1, V2"),
+            (0x9120, SkipNeReg { x: 1, y: 2 }, "SNE V1, V2"),
+            (0xa22a, LoadIndex(0x22a), "LD I, 0x22A"),
+            (0xb300, JumpOffset(0x300), "JP V0, 0x300"),
+            (0xc0ff, Random { x: 0, nn: 0xff }, "RND V0, 0xFF"),
+            (0xd125, Draw { x: 1, y: 2, n: 5 }, "DRW V1, V2, 5"),
+            (0xe59e, SkipKey { x: 5 }, "SKP V5"),
+            (0xe5a1, SkipNotKey { x: 5 }, "SKNP V5"),
+            (0xf000, LoadIndexLong(0), "LD I, LONG 0x0000"),
+            (0xf201, SelectPlanes(2), "PLANE 2"),
+            (0xf002, LoadAudio, "AUDIO"),
+            (0xf307, GetDelay { x: 3 }, "LD V3, DT"),
+            (0xf30a, WaitKey { x: 3 }, "LD V3, K"),
+            (0xf315, SetDelay { x: 3 }, "LD DT, V3"),
+            (0xf318, SetSound { x: 3 }, "LD ST, V3"),
+            (0xf31e, AddIndex { x: 3 }, "ADD I, V3"),
+            (0xf329, LoadFont { x: 3 }, "LD F, V3"),
+            (0xf330, LoadBigFont { x: 3 }, "LD HF, V3"),
+            (0xf333, StoreBcd { x: 3 }, "LD B, V3"),
+            (0xf33a, SetPitch { x: 3 }, "PITCH V3"),
+            (0xf355, StoreRegs { x: 3 }, "LD [I], V3"),
+            (0xf365, LoadRegs { x: 3 }, "LD V3, [I]"),
+            (0xf375, StoreFlags { x: 3 }, "LD R, V3"),
+            (0xf385, LoadFlags { x: 3 }, "LD V3, R"),
+        ];
+        for (opcode, instruction, text) in cases {
+            assert_eq!(decode(opcode), instruction, "opcode {:04X}", opcode);
+            assert_eq!(instruction.opcode(), opcode, "{:?}", instruction);
+            assert_eq!(instruction.to_string(), text, "opcode {:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes() {
+        for opcode in [0xf0ff, 0x5121, 0xe1ff] {
+            assert_eq!(decode(opcode), Unknown(opcode));
+            assert_eq!(decode(opcode).opcode(), opcode);
+        }
+        assert_eq!(decode(0xf0ff).to_string(), "DW 0xF0FF");
+    }
+
+    #[test]
+    fn every_opcode_round_trips() {
+        for opcode in 0..=u16::MAX {
+            assert_eq!(decode(opcode).opcode(), opcode, "opcode {:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn whole_rom() {
+        let rom = [0x00, 0xe0, 0xa2, 0x2a, 0x60, 0x0c, 0x12];
+        assert_eq!(disassemble(&rom, 0x200), vec![
+            (0x200, ClearScreen),
+            (0x202, LoadIndex(0x22a)),
+            (0x204, LoadImm { x: 0, nn: 0x0c }),
+        ]);
+        assert!(disassemble(&[], 0x200).is_empty());
+    }
+
+    #[test]
+    fn long_index_is_one_instruction() {
+        let rom = [0xf0, 0x00, 0x12, 0x34, 0x00, 0xe0, 0xf0, 0x00];
+        assert_eq!(disassemble(&rom, 0x200), vec![
+            (0x200, LoadIndexLong(0x1234)),
+            (0x204, ClearScreen),
+            (0x206, Unknown(0xf000)), // Its address is cut off
+        ]);
+        assert_eq!(disassemble_range(&rom, 0, 2), vec![
+            (0x0, "LD I, LONG 0x1234".to_string()),
+            (0x4, "CLS".to_string()),
+        ]);
+        assert_eq!(decode_at(&rom, 0), Some(LoadIndexLong(0x1234)));
+        assert_eq!(decode_at(&rom, 6), None);
+        assert_eq!(decode_at(&rom, 7), None);
+        assert_eq!(LoadIndexLong(0x1234).size(), 4);
+        assert_eq!(ClearScreen.size(), 2);
+    }
+
+    #[test]
+    fn range() {
+        let mem = [0x00, 0xe0, 0xa2, 0x2a, 0x60, 0x0c, 0x12];
+        assert_eq!(disassemble_range(&mem, 0, 10), vec![
+            (0x0, "CLS".to_string()),
+            (0x2, "LD I, 0x22A".to_string()),
+            (0x4, "LD V0, 0x0C".to_string()),
+        ]);
+        assert_eq!(disassemble_range(&mem, 2, 1), vec![(0x2, "LD I, 0x22A".to_string())]);
+        assert!(disassemble_range(&mem, 100, 1).is_empty());
+    }
+
+    #[test]
+    fn kinds_match_their_index() {
+        for (i, kind) in InstructionKind::ALL.iter().enumerate() {
+            assert_eq!(*kind as usize, i);
+        }
+        assert_eq!(decode(0x7005).kind(), InstructionKind::AddImm);
+        assert_eq!(decode(0x0000).kind(), InstructionKind::System);
+        assert_eq!(decode(0xffff).kind(), InstructionKind::Unknown);
+    }
+}
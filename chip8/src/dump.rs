@@ -0,0 +1,126 @@
+//! A readable dump of the whole machine, for printing when something goes wrong.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+use crate::Chip8;
+
+/// How many bytes of memory are shown around the program counter and the index register.
+const EXCERPT_LEN: usize = 64;
+/// Bytes per line of the memory excerpts.
+const LINE_LEN: usize = 16;
+
+impl Chip8 {
+    /// Describes the whole machine over several lines: the registers, timers, stack,
+    /// configuration and the memory around the program counter and the index register. The
+    /// format is stable, so dumps can be compared.
+    pub fn dump_state(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail
+        let _ = self.write_state(&mut out);
+        out
+    }
+
+    fn write_state(&self, out: &mut String) -> core::fmt::Result {
+        writeln!(
+            out,
+            "PC {:03X}  I {:03X}  DT {:02X}  ST {:02X}  cycles {}",
+            self.program_counter, self.index_register, self.delay_timer, self.sound_timer, self.instructions_executed
+        )?;
+        for (row, values) in self.registers.chunks(8).enumerate() {
+            for (i, value) in values.iter().enumerate() {
+                let separator = if i == 0 { "" } else { "  " };
+                write!(out, "{}V{:X} {:02X}", separator, row * 8 + i, value)?;
+            }
+            writeln!(out)?;
+        }
+
+        let stack = self.stack();
+        write!(out, "stack {}/{}:", stack.len(), self.config.stack_limit.min(crate::STACK_SIZE))?;
+        for addr in stack {
+            write!(out, " {:03X}", addr)?;
+        }
+        writeln!(out)?;
+        let (width, height) = self.dimensions();
+        writeln!(
+            out,
+            "variant {:?}, {} bytes of memory, {}x{} display{}{}",
+            self.config.variant,
+            self.memory.len(),
+            width,
+            height,
+            if self.waiting_for_key { ", waiting for a key" } else { "" },
+            if self.halted { ", halted" } else { "" },
+        )?;
+        writeln!(out, "quirks: {}", self.config.quirks)?;
+
+        writeln!(out, "memory around PC:")?;
+        self.write_excerpt(out, self.program_counter as usize)?;
+        writeln!(out, "memory around I:")?;
+        self.write_excerpt(out, self.index_register as usize)
+    }
+
+    /// Writes the lines of memory around `pointer`, with the byte it points to marked by `>`.
+    fn write_excerpt(&self, out: &mut String, pointer: usize) -> core::fmt::Result {
+        // Starts a line before the pointer's so what led to it shows, without going past the end
+        let start = (pointer / LINE_LEN * LINE_LEN).saturating_sub(LINE_LEN)
+            .min(self.memory.len() - EXCERPT_LEN);
+        for (line, bytes) in self.memory[start..start + EXCERPT_LEN].chunks(LINE_LEN).enumerate() {
+            let line_start = start + line * LINE_LEN;
+            write!(out, "{:04X}", line_start)?;
+            for (i, byte) in bytes.iter().enumerate() {
+                let marker = if line_start + i == pointer { '>' } else { ' ' };
+                write!(out, " {}{:02X}", marker, byte)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_machine() {
+        let emu = Chip8::new();
+        assert_eq!(emu.dump_state(), "\
+            PC 200  I 000  DT 00  ST 00  cycles 0\n\
+            V0 00  V1 00  V2 00  V3 00  V4 00  V5 00  V6 00  V7 00\n\
+            V8 00  V9 00  VA 00  VB 00  VC 00  VD 00  VE 00  VF 00\n\
+            stack 0/16:\n\
+            variant Modern, 4096 bytes of memory, 64x32 display\n\
+            quirks: shift,memoryLeaveIUnchanged,clip\n\
+            memory around PC:\n\
+            01F0  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00\n\
+            0200 >00  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00\n\
+            0210  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00\n\
+            0220  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00\n\
+            memory around I:\n\
+            0000 >00  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00\n\
+            0010  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00\n\
+            0020  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00\n\
+            0030  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00\n\
+        ");
+    }
+
+    #[test]
+    fn after_running_a_program() {
+        let mut emu = Chip8::new();
+        // VA = 0x2B, I = 0xFFA, call a subroutine that sets the delay timer and waits for a key
+        emu.load(&[0x6a, 0x2b, 0xaf, 0xfa, 0x22, 0x08, 0x00, 0x00, 0xfa, 0x15, 0xf1, 0x0a]).unwrap();
+        emu.step_n(5).unwrap();
+        let dump = emu.dump_state();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 16);
+        assert_eq!(lines[0], "PC 20A  I FFA  DT 2B  ST 00  cycles 5");
+        assert_eq!(lines[2], "V8 00  V9 00  VA 2B  VB 00  VC 00  VD 00  VE 00  VF 00");
+        assert_eq!(lines[3], "stack 1/16: 206");
+        assert_eq!(lines[4], "variant Modern, 4096 bytes of memory, 64x32 display, waiting for a key");
+        assert_eq!(lines[8], "0200  6A  2B  AF  FA  22  08  00  00  FA  15 >F1  0A  00  00  00  00");
+        // The excerpt stops at the end of memory
+        assert_eq!(lines[12], "0FC0  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00  00");
+        assert_eq!(lines[15], "0FF0  00  00  00  00  00  00  00  00  00  00 >00  00  00  00  00  00");
+    }
+}
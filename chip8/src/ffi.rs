@@ -0,0 +1,200 @@
+//! A C ABI for embedding the interpreter in other languages, declared in `include/chip8.h`. Build
+//! a shared library with it using
+//! `cargo rustc --release --features ffi --crate-type cdylib`.
+//!
+//! A machine is an opaque `Chip8 *` handle created by `chip8_new`. The caller owns it and must
+//! free it exactly once with `chip8_free`. Every other function borrows the handle for the
+//! duration of the call, and a null handle is ignored or reported as an error rather than
+//! dereferenced.
+
+use alloc::boxed::Box;
+use core::ffi::c_int;
+use core::slice;
+
+use crate::Chip8;
+
+/// Returned by functions that succeeded.
+pub const CHIP8_OK: c_int = 0;
+/// Returned when the handle or a buffer was null.
+pub const CHIP8_NULL: c_int = -1;
+/// Returned when the program couldn't be loaded or the instruction couldn't be executed.
+pub const CHIP8_ERROR: c_int = -2;
+
+/// Creates a machine, to be freed with `chip8_free`.
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8 {
+    Box::into_raw(Box::new(Chip8::new()))
+}
+
+/// Frees a machine created by `chip8_new`, nothing happens if `emu` is null.
+///
+/// # Safety
+/// `emu` must be null or a handle from `chip8_new` that wasn't freed yet. It can't be used after.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(emu: *mut Chip8) {
+    if !emu.is_null() {
+        drop(Box::from_raw(emu));
+    }
+}
+
+/// Loads the `len` byte program at `rom`, which is copied so the caller keeps ownership of it.
+///
+/// # Safety
+/// `emu` must be null or a live handle, and `rom` null or valid for reading `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load(emu: *mut Chip8, rom: *const u8, len: usize) -> c_int {
+    let (Some(emu), false) = (emu.as_mut(), rom.is_null()) else {
+        return CHIP8_NULL;
+    };
+    match emu.load(slice::from_raw_parts(rom, len)) {
+        Ok(()) => CHIP8_OK,
+        Err(_) => CHIP8_ERROR,
+    }
+}
+
+/// Executes a single instruction. On an error the machine stays on the failing instruction.
+///
+/// # Safety
+/// `emu` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(emu: *mut Chip8) -> c_int {
+    let Some(emu) = emu.as_mut() else {
+        return CHIP8_NULL;
+    };
+    match emu.step() {
+        Ok(_) => CHIP8_OK,
+        Err(_) => CHIP8_ERROR,
+    }
+}
+
+/// Decrements the timers, meant to be called 60 times per second.
+///
+/// # Safety
+/// `emu` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_tick_timers(emu: *mut Chip8) {
+    if let Some(emu) = emu.as_mut() {
+        emu.tick_timers();
+    }
+}
+
+/// Presses the key `key`, from 0 to 15.
+///
+/// # Safety
+/// `emu` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_press_key(emu: *mut Chip8, key: u8) {
+    if let Some(emu) = emu.as_mut() {
+        emu.press_key(key);
+    }
+}
+
+/// Releases the key `key`, from 0 to 15.
+///
+/// # Safety
+/// `emu` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_unpress_key(emu: *mut Chip8, key: u8) {
+    if let Some(emu) = emu.as_mut() {
+        emu.unpress_key(key);
+    }
+}
+
+/// Returns the width of the display in pixels, 0 if `emu` is null.
+///
+/// # Safety
+/// `emu` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_display_width(emu: *const Chip8) -> usize {
+    emu.as_ref().map_or(0, Chip8::display_width)
+}
+
+/// Returns the height of the display in pixels, 0 if `emu` is null.
+///
+/// # Safety
+/// `emu` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_display_height(emu: *const Chip8) -> usize {
+    emu.as_ref().map_or(0, Chip8::display_height)
+}
+
+/// Copies the display into `out`, one byte per pixel with 1 for lit and 0 for clear, row by row.
+/// At most `len` bytes are written. Returns the number of pixels of the whole display, so a
+/// smaller buffer can be detected, or 0 if `emu` or `out` is null.
+///
+/// # Safety
+/// `emu` must be null or a live handle, and `out` null or valid for writing `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_get_display(emu: *const Chip8, out: *mut u8, len: usize) -> usize {
+    let (Some(emu), false) = (emu.as_ref(), out.is_null()) else {
+        return 0;
+    };
+    let display = emu.get_display();
+    let out = slice::from_raw_parts_mut(out, len.min(display.len()));
+    for (byte, &pixel) in out.iter_mut().zip(&display) {
+        *byte = pixel as u8;
+    }
+    display.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ptr;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        unsafe {
+            let emu = chip8_new();
+            let rom = [0xa0, 0x50, 0xd0, 0x05, 0xf0, 0x0a]; // Draw the 0 of the font, wait for a key
+            assert_eq!(chip8_load(emu, rom.as_ptr(), rom.len()), CHIP8_OK);
+            for _ in 0..3 {
+                assert_eq!(chip8_step(emu), CHIP8_OK);
+            }
+            chip8_press_key(emu, 5);
+            chip8_unpress_key(emu, 5);
+            assert_eq!(chip8_step(emu), CHIP8_OK);
+            assert_eq!((*emu).register(0), 5);
+            chip8_tick_timers(emu);
+
+            let (width, height) = (chip8_display_width(emu), chip8_display_height(emu));
+            assert_eq!((width, height), (64, 32));
+            let mut display = vec![0xff; width * height];
+            assert_eq!(chip8_get_display(emu, display.as_mut_ptr(), display.len()), 64 * 32);
+            assert_eq!(display[..5], [1, 1, 1, 1, 0]);
+            assert_eq!(display.iter().filter(|&&pixel| pixel == 1).count(), 14);
+
+            // A short buffer gets the start of the display
+            let mut row = [0xff; 4];
+            assert_eq!(chip8_get_display(emu, row.as_mut_ptr(), row.len()), 64 * 32);
+            assert_eq!(row, [1; 4]);
+            chip8_free(emu);
+        }
+    }
+
+    #[test]
+    fn errors_and_null_handles() {
+        unsafe {
+            let emu = chip8_new();
+            assert_eq!(chip8_load(emu, ptr::null(), 0), CHIP8_NULL);
+            assert_eq!(chip8_load(emu, [].as_ptr(), 0), CHIP8_ERROR);
+            let rom = [0xff, 0xff];
+            assert_eq!(chip8_load(emu, rom.as_ptr(), rom.len()), CHIP8_OK);
+            assert_eq!(chip8_step(emu), CHIP8_ERROR);
+            assert_eq!(chip8_get_display(emu, ptr::null_mut(), 10), 0);
+            chip8_free(emu);
+
+            let null = ptr::null_mut();
+            assert_eq!(chip8_load(null, rom.as_ptr(), rom.len()), CHIP8_NULL);
+            assert_eq!(chip8_step(null), CHIP8_NULL);
+            chip8_tick_timers(null);
+            chip8_press_key(null, 1);
+            chip8_unpress_key(null, 1);
+            assert_eq!(chip8_display_width(null), 0);
+            assert_eq!(chip8_display_height(null), 0);
+            assert_eq!(chip8_get_display(null, [0; 4].as_mut_ptr(), 4), 0);
+            chip8_free(null);
+        }
+    }
+}
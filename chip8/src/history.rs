@@ -0,0 +1,169 @@
+//! The program counters of the last executed instructions, to show how execution got to an error.
+
+use core::fmt;
+use core::ops::Deref;
+
+use crate::Chip8;
+
+/// The most program counters that are kept.
+pub const PC_HISTORY_SIZE: usize = 32;
+
+/// The program counters of the last executed instructions, oldest first. Derefs to a slice of
+/// them. It's a fixed array so errors carrying it stay `Copy`.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct PcHistory {
+    pcs: [u16; PC_HISTORY_SIZE],
+    len: usize,
+}
+
+impl PcHistory {
+    /// Creates a history of `pcs`, keeping only the last `PC_HISTORY_SIZE`.
+    pub fn from_slice(pcs: &[u16]) -> Self {
+        let pcs = &pcs[pcs.len().saturating_sub(PC_HISTORY_SIZE)..];
+        let mut history = PcHistory::default();
+        history.pcs[..pcs.len()].copy_from_slice(pcs);
+        history.len = pcs.len();
+        history
+    }
+}
+
+impl Deref for PcHistory {
+    type Target = [u16];
+
+    fn deref(&self) -> &[u16] {
+        &self.pcs[..self.len]
+    }
+}
+
+impl fmt::Debug for PcHistory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PcHistory[{}]", self)
+    }
+}
+
+/// Shows the addresses in hex like the error messages, separated by spaces.
+impl fmt::Display for PcHistory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, pc) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:03X}", pc)?;
+        }
+        Ok(())
+    }
+}
+
+/// Records the program counter of every executed instruction, overwriting the oldest once
+/// `capacity` are kept.
+#[derive(Debug, Clone)]
+pub(crate) struct PcRing {
+    pcs: [u16; PC_HISTORY_SIZE],
+    /// Where the next program counter goes.
+    next: usize,
+    len: usize,
+    capacity: usize,
+}
+
+impl Default for PcRing {
+    fn default() -> Self {
+        PcRing { pcs: [0; PC_HISTORY_SIZE], next: 0, len: 0, capacity: PC_HISTORY_SIZE }
+    }
+}
+
+impl PcRing {
+    pub(crate) fn push(&mut self, pc: u16) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.pcs[self.next] = pc;
+        self.next = if self.next + 1 == self.capacity { 0 } else { self.next + 1 };
+        self.len = (self.len + 1).min(self.capacity);
+    }
+
+    /// Copies the program counters in order.
+    pub(crate) fn history(&self) -> PcHistory {
+        let mut history = PcHistory { len: self.len, ..PcHistory::default() };
+        // The oldest entry is at `next` once the ring is full, and at 0 before
+        let start = if self.len == self.capacity { self.next } else { 0 };
+        for (i, pc) in history.pcs[..self.len].iter_mut().enumerate() {
+            *pc = self.pcs[(start + i) % self.capacity];
+        }
+        history
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = PcRing { capacity: self.capacity, ..PcRing::default() };
+    }
+}
+
+impl Chip8 {
+    /// Returns the program counters of the last executed instructions, oldest first. Errors
+    /// from `step` and `execute` carry a copy, which also has the failing instruction.
+    pub fn recent_pcs(&self) -> PcHistory {
+        self.debugger.pc_history.history()
+    }
+
+    /// Sets how many program counters are kept, up to `PC_HISTORY_SIZE` which is the default.
+    /// 0 turns the history off. The current history is cleared.
+    pub fn set_pc_history_len(&mut self, len: usize) {
+        self.debugger.pc_history = PcRing { capacity: len.min(PC_HISTORY_SIZE), ..PcRing::default() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::Chip8Error;
+
+    #[test]
+    fn ring_keeps_the_latest() {
+        let mut ring = PcRing { capacity: 3, ..PcRing::default() };
+        assert!(ring.history().is_empty());
+        ring.push(1);
+        ring.push(2);
+        assert_eq!(*ring.history(), [1, 2]);
+        ring.push(3);
+        ring.push(4);
+        assert_eq!(*ring.history(), [2, 3, 4]);
+        ring.clear();
+        ring.push(5);
+        assert_eq!(*ring.history(), [5]);
+
+        let mut ring = PcRing { capacity: 0, ..PcRing::default() };
+        ring.push(1);
+        assert!(ring.history().is_empty());
+        assert_eq!(*PcHistory::from_slice(&[0; 40]), [0; PC_HISTORY_SIZE]);
+    }
+
+    #[test]
+    fn errors_carry_the_history() {
+        let program = assemble("
+                CALL first
+            first:
+                CALL second
+            second:
+                LD V0, 1
+                dw 0xffff
+        ").unwrap();
+        let mut emu = Chip8::new();
+        emu.load(&program).unwrap();
+        let err = emu.step_n(10).unwrap_err();
+        let history = PcHistory::from_slice(&[0x200, 0x202, 0x204, 0x206]);
+        assert_eq!(err, Chip8Error::UnknownOpcode { opcode: 0xffff, pc: 0x206, history });
+        assert_eq!(err.history(), &history);
+        assert_eq!(emu.recent_pcs(), history);
+        assert_eq!(err.to_string(), "unknown opcode FFFF at 206, recent program counters: 200 202 204 206");
+
+        emu.set_pc_history_len(2);
+        emu.reset();
+        let err = emu.step_n(10).unwrap_err();
+        assert_eq!(err.history()[..], [0x204, 0x206]);
+
+        emu.set_pc_history_len(0);
+        let err = emu.step().unwrap_err();
+        assert!(err.history().is_empty());
+        assert_eq!(err.to_string(), "unknown opcode FFFF at 206");
+    }
+}
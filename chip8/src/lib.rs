@@ -1,13 +1,486 @@
-use std::io;
-const SCREEN_WIDTH: usize = 64;
-const SCREEN_HEIGHT: usize = 32;
-const MEMORY_SIZE: usize = 4096;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use serde_big_array::BigArray;
+
+pub mod asm;
+mod coverage;
+mod debug;
+mod diff;
+mod dump;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod disasm;
+mod history;
+mod rewind;
+mod rng;
+mod stack;
+mod state;
+mod stats;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use asm::{assemble, AsmError};
+pub use debug::{StepInfo, WatchKind};
+pub use diff::StateDiff;
+pub use disasm::{decode, Instruction, InstructionKind};
+pub use history::{PcHistory, PC_HISTORY_SIZE};
+use debug::Debugger;
+use rewind::RewindBuffer;
+use rng::Rng;
+use stack::Stack;
+pub use stack::STACK_SIZE;
+pub use state::StateError;
+pub use stats::Stats;
+
+/// The width of the display in pixels.
+pub const SCREEN_WIDTH: usize = 64;
+/// The height of the display in pixels.
+pub const SCREEN_HEIGHT: usize = 32;
+/// The width of the display in the SUPER-CHIP high resolution mode.
+pub const HIRES_WIDTH: usize = 128;
+/// The height of the display in the SUPER-CHIP high resolution mode.
+pub const HIRES_HEIGHT: usize = 64;
+/// The bytes of memory.
+pub const MEMORY_SIZE: usize = 4096;
+/// The bytes of XO-CHIP memory, addressed by the whole 16 bits of I.
+pub const EXTENDED_MEMORY_SIZE: usize = 65536;
+// Where the 4x5 font and the SUPER-CHIP 8x10 font are stored, one after the other
+const FONT_ADDRESS: u16 = 0x50;
+const BIG_FONT_ADDRESS: u16 = 0xa0;
+const FONT_END: u16 = BIG_FONT_ADDRESS + 160;
+// The XO-CHIP pitch that plays the audio pattern at 4000 samples per second
+const DEFAULT_AUDIO_PITCH: u8 = 64;
+
+/// Errors that stop the execution of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The opcode at `pc` doesn't decode to any known instruction.
+    UnknownOpcode { opcode: u16, pc: u16, history: PcHistory },
+    /// The subroutine call at `pc` exceeded the maximum stack depth.
+    StackOverflow { pc: u16, history: PcHistory },
+    /// The instruction at `pc` attempted to return from a subroutine with an empty stack.
+    StackUnderflow { pc: u16, history: PcHistory },
+    /// The instruction `opcode` at `pc` accessed `address`, which is past the end of memory.
+    MemoryOutOfBounds { opcode: u16, pc: u16, address: u16, history: PcHistory },
+    /// The program counter `pc` is past the end of memory, so no instruction can be fetched.
+    PcOutOfBounds { pc: u16, history: PcHistory },
+}
+
+impl Chip8Error {
+    /// Returns the program counters of the instructions executed up to the error, oldest first.
+    /// When an instruction failed, it's the last one.
+    pub fn history(&self) -> &PcHistory {
+        match self {
+            Chip8Error::UnknownOpcode { history, .. }
+            | Chip8Error::StackOverflow { history, .. }
+            | Chip8Error::StackUnderflow { history, .. }
+            | Chip8Error::MemoryOutOfBounds { history, .. }
+            | Chip8Error::PcOutOfBounds { history, .. } => history,
+        }
+    }
+
+    fn with_history(mut self, new_history: PcHistory) -> Self {
+        match &mut self {
+            Chip8Error::UnknownOpcode { history, .. }
+            | Chip8Error::StackOverflow { history, .. }
+            | Chip8Error::StackUnderflow { history, .. }
+            | Chip8Error::MemoryOutOfBounds { history, .. }
+            | Chip8Error::PcOutOfBounds { history, .. } => *history = new_history,
+        }
+        self
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { opcode, pc, .. } => {
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, pc)?
+            },
+            Chip8Error::StackOverflow { pc, .. } => {
+                write!(f, "stack overflow calling a subroutine at {:03X}", pc)?
+            },
+            Chip8Error::StackUnderflow { pc, .. } => {
+                write!(f, "return from subroutine with an empty stack at {:03X}", pc)?
+            },
+            Chip8Error::MemoryOutOfBounds { opcode, pc, address, .. } => {
+                write!(f, "opcode {:04X} at {:03X} accessed {:04X}, past the end of memory", opcode, pc, address)?
+            },
+            Chip8Error::PcOutOfBounds { pc, .. } => {
+                write!(f, "program counter {:04X} is past the end of memory", pc)?
+            },
+        }
+        if !self.history().is_empty() {
+            write!(f, ", recent program counters: {}", self.history())?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Chip8Error {}
+
+/// What happened during a successful `step`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The instruction was executed.
+    Executed,
+    /// A DXYN is waiting for the vertical blank, it runs again after the next `tick_timers` or `end_of_frame`.
+    /// Nothing else will execute until then, so the rest of the frame can be skipped.
+    WaitingForVblank,
+    /// The program exited with 00FD, nothing runs until the machine is reset.
+    Halted,
+    /// The instruction at `pc` has a breakpoint and wasn't run, the next `step` runs it.
+    BreakpointHit { pc: u16 },
+    /// The instruction at `pc` was executed and accessed the watched address `addr`, which held
+    /// `old` and now holds `new`. Only the first watched access of the instruction is reported.
+    WatchpointHit { addr: u16, pc: u16, old: u8, new: u8, kind: WatchKind },
+}
+
+/// Errors that prevent a program from being loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// The program has no bytes.
+    Empty,
+    /// The program is `size` bytes long, but only `max` bytes fit in memory.
+    TooLarge { size: usize, max: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Empty => write!(f, "program is empty"),
+            LoadError::TooLarge { size, max } => {
+                write!(f, "program is {} bytes, but only {} bytes fit in memory", size, max)
+            },
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+/// Errors from reading or writing memory through `Chip8::write_memory` and friends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemError {
+    /// `addr` is past the end of memory.
+    OutOfBounds { addr: usize },
+    /// `addr` is in the font, which is write protected with `Chip8::set_font_protection`.
+    Protected { addr: usize },
+}
+
+impl fmt::Display for MemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemError::OutOfBounds { addr } => write!(f, "address {:04X} is past the end of memory", addr),
+            MemError::Protected { addr } => write!(f, "address {:04X} is in the write protected font", addr),
+        }
+    }
+}
+
+impl Error for MemError {}
+
+/// The quirk names understood by `Quirks::from_str_flags`, in the order they are displayed.
+const QUIRK_NAMES: [&str; 8] = [
+    "shift", "memoryIncrementByX", "memoryLeaveIUnchanged", "jump0", "logic", "clip", "vblank", "indexOverflow",
+];
+
+/// Error returned when parsing a quirk name that isn't recognized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuirkParseError {
+    /// The name that couldn't be parsed.
+    pub name: String,
+}
+
+impl fmt::Display for QuirkParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown quirk \"{}\", expected one of: {}", self.name, QUIRK_NAMES.join(", "))
+    }
+}
+
+impl Error for QuirkParseError {}
+
+/// How much FX55/FX65 change I after storing or loading V0..VX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MemoryIncrement {
+    /// I is left unchanged, like SUPER-CHIP.
+    Unchanged,
+    /// I is left pointing past the last register, I + X + 1, like the COSMAC VIP.
+    XPlusOne,
+    /// I is left pointing at the last register, I + X, like CHIP-48.
+    X,
+}
+
+/// Selects between the instruction behaviors that differ across CHIP-8 variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Quirks {
+    /// 8XY6/8XYE copy VY into VX before shifting, instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// How much FX55/FX65 leave I incremented by.
+    pub memory_increment: MemoryIncrement,
+    /// BNNN is read as BXNN and jumps to XNN + VX, instead of NNN + V0.
+    pub jump_with_offset_uses_vx: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0, after the result is stored so this also applies when VF is VX.
+    pub logic_resets_vf: bool,
+    /// Sprites drawn past an edge of the screen wrap around to the other side instead of being clipped.
+    pub sprite_wrapping: bool,
+    /// DXYN waits for the vertical blank, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+    /// FX1E sets VF to 1 when I is pushed past 0xFFF and to 0 otherwise, like the Amiga interpreter.
+    pub index_overflow_sets_vf: bool,
+}
+
+impl Quirks {
+    /// The original interpreter on the COSMAC VIP.
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_with_offset_uses_vx: false,
+            logic_resets_vf: true,
+            sprite_wrapping: false,
+            display_wait: true,
+            index_overflow_sets_vf: false,
+        }
+    }
+
+    /// The CHIP-48 interpreter for the HP-48 calculators.
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::X,
+            jump_with_offset_uses_vx: true,
+            logic_resets_vf: false,
+            sprite_wrapping: false,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        }
+    }
+
+    /// The SUPER-CHIP 1.1 interpreter.
+    pub fn superchip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::Unchanged,
+            jump_with_offset_uses_vx: true,
+            logic_resets_vf: false,
+            sprite_wrapping: false,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        }
+    }
+
+    /// The XO-CHIP extension, as implemented by Octo.
+    pub fn xochip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_with_offset_uses_vx: false,
+            logic_resets_vf: false,
+            sprite_wrapping: true,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        }
+    }
+
+    /// Builds the quirks from the names used by Octo and the community test suites, ignoring case.
+    /// Each name turns on one behavior on top of a baseline that shifts VY, leaves I at I + X + 1,
+    /// jumps to NNN + V0, leaves VF alone after logic opcodes, wraps sprites and doesn't wait for
+    /// the vertical blank:
+    ///
+    /// - `shift`: 8XY6/8XYE shift VX in place
+    /// - `memoryIncrementByX`: FX55/FX65 leave I at I + X
+    /// - `memoryLeaveIUnchanged`: FX55/FX65 leave I unchanged
+    /// - `jump0`: BNNN is read as BXNN
+    /// - `logic`: 8XY1/8XY2/8XY3 reset VF
+    /// - `clip`: sprites are clipped at the edges of the screen
+    /// - `vblank`: DXYN waits for the vertical blank
+    /// - `indexOverflow`: FX1E sets VF when I overflows, not an Octo quirk
+    pub fn from_str_flags(names: &[&str]) -> Result<Self, QuirkParseError> {
+        let mut quirks = Quirks {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_with_offset_uses_vx: false,
+            logic_resets_vf: false,
+            sprite_wrapping: true,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        };
+        for name in names {
+            match name.to_ascii_lowercase().as_str() {
+                "shift" => quirks.shift_uses_vy = false,
+                "memoryincrementbyx" => quirks.memory_increment = MemoryIncrement::X,
+                "memoryleaveiunchanged" => quirks.memory_increment = MemoryIncrement::Unchanged,
+                "jump0" => quirks.jump_with_offset_uses_vx = true,
+                "logic" => quirks.logic_resets_vf = true,
+                "clip" => quirks.sprite_wrapping = false,
+                "vblank" => quirks.display_wait = true,
+                "indexoverflow" => quirks.index_overflow_sets_vf = true,
+                _ => return Err(QuirkParseError { name: name.to_string() }),
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = QuirkParseError;
+
+    /// Parses a list of quirk names separated by commas or whitespace, see `Quirks::from_str_flags`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let names: Vec<&str> = s.split(|c: char| c == ',' || c.is_whitespace()).filter(|name| !name.is_empty()).collect();
+        Self::from_str_flags(&names)
+    }
+}
+
+impl fmt::Display for Quirks {
+    /// Writes the quirk names that are turned on, separated by commas, in the form parsed by `FromStr`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let enabled = [
+            !self.shift_uses_vy,
+            self.memory_increment == MemoryIncrement::X,
+            self.memory_increment == MemoryIncrement::Unchanged,
+            self.jump_with_offset_uses_vx,
+            self.logic_resets_vf,
+            !self.sprite_wrapping,
+            self.display_wait,
+            self.index_overflow_sets_vf,
+        ];
+        let names: Vec<&str> = QUIRK_NAMES.iter().zip(enabled).filter(|(_, on)| *on).map(|(name, _)| *name).collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl Default for Quirks {
+    /// Shifts VX in place, like most modern interpreters.
+    fn default() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::Unchanged,
+            jump_with_offset_uses_vx: false,
+            logic_resets_vf: false,
+            sprite_wrapping: false,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        }
+    }
+}
+
+/// The CHIP-8 platforms that can be emulated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Variant {
+    /// CHIP-8 as most modern interpreters and test ROMs expect it, with the SUPER-CHIP
+    /// instructions. The default, see `Quirks::default` for its quirks.
+    Modern,
+    /// The original interpreter on the COSMAC VIP.
+    Chip8,
+    /// The CHIP-48 interpreter for the HP-48 calculators.
+    Chip48,
+    /// The SUPER-CHIP 1.1 interpreter.
+    SuperChip,
+    /// The XO-CHIP extension, as implemented by Octo.
+    XoChip,
+}
+
+impl Variant {
+    /// Whether the platform has the SUPER-CHIP instructions: scrolling, the high resolution mode,
+    /// the big font, exiting and the RPL flags.
+    pub fn has_superchip_instructions(self) -> bool {
+        matches!(self, Variant::Modern | Variant::SuperChip | Variant::XoChip)
+    }
+
+    /// The instruction behaviors of the platform.
+    pub fn quirks(self) -> Quirks {
+        self.config().quirks
+    }
+
+    /// The machine configuration of the platform.
+    pub fn config(self) -> Chip8Config {
+        match self {
+            Variant::Modern => Chip8Config::modern(),
+            Variant::Chip8 => Chip8Config::cosmac_vip(),
+            Variant::Chip48 => Chip8Config::chip48(),
+            Variant::SuperChip => Chip8Config::superchip(),
+            Variant::XoChip => Chip8Config::xochip(),
+        }
+    }
+}
+
+/// Configures the machine that is emulated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Chip8Config {
+    /// The platform being emulated, the quirks may have been changed from its defaults.
+    pub variant: Variant,
+    /// The instruction behaviors to emulate, can also be changed at runtime with `Chip8::set_quirks`.
+    pub quirks: Quirks,
+    /// The maximum number of nested subroutine calls, 2NNN fails with a stack overflow past it.
+    /// The stack has room for `STACK_SIZE` calls, so larger limits act as `STACK_SIZE`.
+    pub stack_limit: usize,
+    /// Use the 64KB memory of XO-CHIP instead of 4KB.
+    pub extended_memory: bool,
+}
+
+impl Chip8Config {
+    /// CHIP-8 as most modern interpreters and test ROMs expect it, with the SUPER-CHIP instructions.
+    pub fn modern() -> Self {
+        Chip8Config { variant: Variant::Modern, quirks: Quirks::default(), stack_limit: 16, extended_memory: false }
+    }
+
+    /// The original interpreter on the COSMAC VIP.
+    pub fn cosmac_vip() -> Self {
+        Chip8Config { variant: Variant::Chip8, quirks: Quirks::cosmac_vip(), stack_limit: 12, extended_memory: false }
+    }
+
+    /// The CHIP-48 interpreter for the HP-48 calculators.
+    pub fn chip48() -> Self {
+        Chip8Config { variant: Variant::Chip48, quirks: Quirks::chip48(), stack_limit: 16, extended_memory: false }
+    }
+
+    /// The SUPER-CHIP 1.1 interpreter.
+    pub fn superchip() -> Self {
+        Chip8Config { variant: Variant::SuperChip, quirks: Quirks::superchip(), stack_limit: 16, extended_memory: false }
+    }
+
+    /// The XO-CHIP extension, as implemented by Octo.
+    pub fn xochip() -> Self {
+        Chip8Config { variant: Variant::XoChip, quirks: Quirks::xochip(), stack_limit: 16, extended_memory: true }
+    }
+
+    /// The number of bytes of memory.
+    fn memory_size(&self) -> usize {
+        if self.extended_memory { EXTENDED_MEMORY_SIZE } else { MEMORY_SIZE }
+    }
+}
+
+impl Default for Chip8Config {
+    fn default() -> Self {
+        Chip8Config::modern()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Chip8 {
     // Can loop in here or in emulator
     program_counter: u16,
-    /// The memory of the program. The actual program starts at 0x200.
-    memory: [u8; MEMORY_SIZE],
+    /// The memory of the program. The actual program starts at 0x200. Its size is set by the config.
+    memory: Vec<u8>,
     /// The general purpose registers
     registers: [u8; 16],
     /// Whether the display needs to be redrawn.
@@ -18,17 +491,116 @@ pub struct Chip8 {
     delay_timer: u8,
     /// Plays a tone as long as the value is not zero, decremented 60 times/second.
     sound_timer: u8,
-    /// Stores the information of each pixel on the screen.
-    display: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+    /// The pixels of each plane as bitsets, `HIRES_HEIGHT` rows for the first plane followed by
+    /// the second XO-CHIP plane. Bit `x` of a row is set for a lit pixel at `x`, low resolution
+    /// only uses the low 64 bits of the first 32 rows.
+    #[cfg_attr(feature = "serde", serde(with = "BigArray"))]
+    plane_rows: [u128; 2 * HIRES_HEIGHT],
+    /// The display rows changed since the last `was_redrawn`, bit `y` set for row `y`.
+    dirty_rows: u64,
+    /// The XO-CHIP 1-bit audio samples played while the sound timer runs, loaded by F002.
+    audio_pattern: [u8; 16],
+    /// The XO-CHIP playback rate of the audio pattern, set by FX3A.
+    audio_pitch: u8,
+    /// Set by 00FD, stops execution until reset.
+    halted: bool,
+    /// Bit mask of the XO-CHIP planes drawn to, only the first plane unless changed by FN01.
+    selected_planes: u8,
+    /// Whether the SUPER-CHIP 128x64 high resolution mode is active.
+    hires: bool,
     /// Stores the information on the keys that is being pressed.
     keyboard: [bool; 16],
-    /// Program stack, used for recursion and generally has a max length of 16 
-    stack: Vec<u16> 
+    /// Whether execution is parked on an FX0A waiting for a key.
+    waiting_for_key: bool,
+    /// The first key pressed since FX0A started waiting, completes the wait once released.
+    key_pressed_while_waiting: Option<u8>,
+    /// Program stack, used for recursion, its max length is set by the config
+    stack: Stack,
+    /// The variant specific behaviors to emulate.
+    config: Chip8Config,
+    /// The last program loaded, restored into memory on reset.
+    program: Vec<u8>,
+    /// Source of the random numbers for CXNN.
+    rng: Rng,
+    /// The SUPER-CHIP RPL user flags saved by FX75, they survive a reset like on the calculator.
+    rpl_flags: [u8; 16],
+    /// Whether a sprite was drawn since the last vertical blank, used by the display wait quirk.
+    drawn_this_frame: bool,
+    /// The number of instructions executed since the last reset.
+    instructions_executed: u64,
+    /// Called with the new beep state whenever the sound timer starts or stops the tone.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    sound_callback: SoundCallback,
+    /// The states to go back to with `rewind`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    rewind: RewindBuffer,
+    /// The breakpoints and hooks set by a debugger.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    debugger: Debugger,
+}
+
+/// Holds the callback given to `set_sound_callback`. It belongs to the frontend rather than the
+/// machine state, so it's ignored when comparing machines and not carried over to clones.
+#[derive(Default)]
+struct SoundCallback(Option<Box<dyn FnMut(bool)>>);
+
+impl Clone for SoundCallback {
+    fn clone(&self) -> Self {
+        SoundCallback(None)
+    }
+}
+
+impl PartialEq for SoundCallback {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SoundCallback {}
+
+/// Summarizes a block of bytes by its length and an FNV-1a hash, for `Debug` output.
+struct Summary<'a>(&'a [u8]);
+
+impl fmt::Debug for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hash = self.0.iter().fold(0x811c_9dc5u32, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x0100_0193));
+        write!(f, "[{} bytes, hash {:08x}]", self.0.len(), hash)
+    }
+}
+
+impl fmt::Debug for Chip8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chip8")
+            .field("program_counter", &format_args!("{:#05x}", self.program_counter))
+            .field("index_register", &format_args!("{:#05x}", self.index_register))
+            .field("registers", &self.registers)
+            .field("delay_timer", &self.delay_timer)
+            .field("sound_timer", &self.sound_timer)
+            .field("stack", &self.stack())
+            .field("memory", &Summary(&self.memory))
+            .field("program", &Summary(&self.program))
+            .field("display", &Summary(&self.get_display_indexed()))
+            .field("hires", &self.hires)
+            .field("selected_planes", &self.selected_planes)
+            .field("keyboard", &self.keyboard)
+            .field("waiting_for_key", &self.waiting_for_key)
+            .field("halted", &self.halted)
+            .field("instructions_executed", &self.instructions_executed)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chip8 {
-    /// Load the font into memory starting at byte 0x50 (by convention).
-    fn initialize_font(memory: &mut [u8; MEMORY_SIZE]) {
+    /// Load the font into memory starting at byte 0x50 (by convention), followed by the SUPER-CHIP
+    /// big font at 0xA0.
+    fn initialize_font(memory: &mut [u8]) {
         // Source: https://tobiasvl.github.io/blog/write-a-chip-8-emulator/#display
         let font: [u8; 80] = [0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
             0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -48,13 +620,58 @@ impl Chip8 {
             0xF0, 0x80, 0xF0, 0x80, 0x80  // F
         ];
         for (i, byte) in font.iter().enumerate() {
-            memory[0x50 + i] = *byte;
+            memory[FONT_ADDRESS as usize + i] = *byte;
+        }
+        // SUPER-CHIP only has the digits 0-9, the letters A-F are the ones from Octo
+        let big_font: [u8; 160] = [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+        ];
+        for (i, byte) in big_font.iter().enumerate() {
+            memory[BIG_FONT_ADDRESS as usize + i] = *byte;
         }
     }
 
-    /// Initializes the Chip8 Interpreter.
+    /// Initializes the Chip8 Interpreter, its random numbers are seeded differently on every run
+    /// with the `rand` feature.
     pub fn new() -> Self {
-        let mut memory: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
+        Self::with_config(Chip8Config::default())
+    }
+
+    /// Initializes the Chip8 Interpreter, emulating the instruction behaviors selected in `quirks`.
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        Self::with_config(Chip8Config { quirks, ..Chip8Config::default() })
+    }
+
+    /// Initializes the Chip8 Interpreter with its random numbers generated from `seed`, so runs can be
+    /// reproduced exactly.
+    pub fn with_rng(seed: u64) -> Self {
+        let mut chip8 = Self::new();
+        chip8.rng = Rng::new(seed);
+        chip8
+    }
+
+    /// Initializes the Chip8 Interpreter, emulating `variant` with its usual quirks.
+    pub fn with_variant(variant: Variant) -> Self {
+        Self::with_config(variant.config())
+    }
+
+    /// Initializes the Chip8 Interpreter, emulating the machine described by `config`.
+    pub fn with_config(config: Chip8Config) -> Self {
+        let mut memory = vec![0; config.memory_size()];
         Self::initialize_font(&mut memory);
         Chip8 {
             program_counter: 0x200, // start of the program
@@ -62,116 +679,469 @@ impl Chip8 {
             registers: [0; 16],
             needs_redraw: false,
             index_register: 0,
-            delay_timer: 60, // 60hz 
-            sound_timer: 60,
-            display: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+            delay_timer: 0,
+            sound_timer: 0,
+            plane_rows: [0; 2 * HIRES_HEIGHT],
+            dirty_rows: u64::MAX,
+            audio_pattern: [0; 16],
+            audio_pitch: DEFAULT_AUDIO_PITCH,
+            halted: false,
+            selected_planes: 1,
+            hires: false,
             keyboard: [false; 16],
-            stack: Vec::new() // Unbounded stack for convenience 
+            waiting_for_key: false,
+            key_pressed_while_waiting: None,
+            stack: Stack::default(),
+            config,
+            program: Vec::new(),
+            rng: Rng::new(Self::random_seed()),
+            rpl_flags: [0; 16],
+            drawn_this_frame: false,
+            instructions_executed: 0,
+            sound_callback: SoundCallback::default(),
+            rewind: RewindBuffer::default(),
+            debugger: Debugger::default(),
         }
     }
     
+    /// Returns a seed for the random numbers that changes on every run. Without the `rand` feature
+    /// it's always the same, so CXNN gives the same numbers every run unless `with_rng` is used.
+    fn random_seed() -> u64 {
+        #[cfg(feature = "rand")]
+        return rand::random();
+        #[cfg(not(feature = "rand"))]
+        return 0;
+    }
+
     /// Loads a chip8 program into memory.
-    pub fn load(&mut self, data: &Vec<u8>) {
-        if data.len() > (MEMORY_SIZE - 0x200) {
-            panic!("Program too large to fit into memory.");
+    pub fn load(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        let max = self.memory.len() - 0x200;
+        if data.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        if data.len() > max {
+            return Err(LoadError::TooLarge { size: data.len(), max });
         }
         for (i, byte) in data.iter().enumerate() {
             self.memory[0x200 + i] = *byte;
         }
+        self.program = data.to_vec();
+        Ok(())
+    }
+
+    /// Reads a chip8 program from `r` and loads it into memory. A program that can't be loaded is
+    /// reported as an `InvalidData` error wrapping the `LoadError`.
+    #[cfg(feature = "std")]
+    pub fn load_from_reader<R: Read>(&mut self, r: &mut R) -> io::Result<()> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data)?;
+        self.load(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Serializes the whole machine state, to be restored later with `restore`.
+    #[cfg(feature = "serde")]
+    pub fn snapshot(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Chip8 state always serializes")
+    }
+
+    /// Restores a machine state created by `snapshot`, leaving the machine untouched if it's invalid.
+    /// The sound callback, rewind points and breakpoints are kept.
+    #[cfg(feature = "serde")]
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), bincode::Error> {
+        let restored: Chip8 = bincode::deserialize(bytes)?;
+        if let Some(field) = restored.invalid_field() {
+            return Err(Box::new(bincode::ErrorKind::Custom(format!("snapshot has an invalid {}", field))));
+        }
+        let was_beeping = self.is_beeping();
+        let sound_callback = core::mem::take(&mut self.sound_callback);
+        let rewind = core::mem::take(&mut self.rewind);
+        let debugger = core::mem::take(&mut self.debugger);
+        *self = restored;
+        self.sound_callback = sound_callback;
+        self.rewind = rewind;
+        self.debugger = debugger;
+        self.needs_redraw = true;
+        self.dirty_rows = u64::MAX;
+        self.notify_beep_change(was_beeping);
+        Ok(())
+    }
+
+    /// Returns the first field out of range for the config, like `load_state` checks, so a
+    /// corrupted snapshot can't leave the machine in a state it panics on.
+    #[cfg(feature = "serde")]
+    fn invalid_field(&self) -> Option<&'static str> {
+        if self.stack.len() > self.config.stack_limit.min(STACK_SIZE) {
+            Some("stack depth")
+        } else if self.memory.len() != self.config.memory_size() {
+            Some("memory size")
+        } else if self.program.len() > self.memory.len() - 0x200 {
+            Some("program")
+        } else if !self.hires && self.plane_rows.iter().any(|row| row >> 64 != 0) {
+            Some("display")
+        } else if self.selected_planes > 3 {
+            Some("plane selection")
+        } else if self.key_pressed_while_waiting.is_some_and(|key| key > 0xf) {
+            Some("key wait")
+        } else if Rng::from_state(self.rng.state()).is_none() {
+            Some("random state")
+        } else {
+            None
+        }
+    }
+
+    /// Returns the display, `display_width()` pixels per row for `display_height()` rows.
+    pub fn get_display(&self) -> Vec<bool> {
+        self.get_display_indexed().into_iter().map(|color| color != 0).collect()
+    }
+
+    /// Returns the display as color indexes 0-3, bit 0 set for pixels lit in the first plane and
+    /// bit 1 for the second XO-CHIP plane. Laid out like `get_display`.
+    pub fn get_display_indexed(&self) -> Vec<u8> {
+        let (width, height) = self.dimensions();
+        let mut display = Vec::with_capacity(width * height);
+        for y in 0..height {
+            display.extend((0..width).map(|x| self.color(x, y)));
+        }
+        display
+    }
+
+    /// Returns the color index of the pixel at (`x`, `y`), which must be on the display.
+    fn color(&self, x: usize, y: usize) -> u8 {
+        (self.plane_rows[y] >> x & 1) as u8 | ((self.plane_rows[HIRES_HEIGHT + y] >> x & 1) as u8) << 1
+    }
+
+    /// Returns the pixels of row `y` lit in any plane, with bit `x` set for a lit pixel at `x`.
+    /// Only the low 64 bits are used in low resolution, rows past the bottom edge are empty.
+    pub fn get_row(&self, y: usize) -> u128 {
+        if y >= self.display_height() {
+            return 0;
+        }
+        self.plane_rows[y] | self.plane_rows[HIRES_HEIGHT + y]
+    }
+
+    /// Returns the 64x32 display packed into one `u64` per row, with bit 63 set for a lit pixel at
+    /// x = 0 and bit 0 for x = 63, the way a sprite byte is laid out. In high resolution this is the
+    /// top left quarter of the display, use `get_row` to get all of it.
+    pub fn get_display_rows(&self) -> [u64; SCREEN_HEIGHT] {
+        core::array::from_fn(|y| (self.get_row(y) as u64).reverse_bits())
+    }
+
+    /// Returns whether the pixel at (`x`, `y`) is lit in any plane, `None` if it's off the display
+    /// at the active resolution.
+    pub fn get_pixel(&self, x: usize, y: usize) -> Option<bool> {
+        let (width, height) = self.dimensions();
+        (x < width && y < height).then(|| self.get_row(y) >> x & 1 == 1)
+    }
+
+    /// Lights the pixel at (`x`, `y`) in the first plane, or clears it in every plane. Coordinates
+    /// off the display are ignored.
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        let (width, height) = self.dimensions();
+        if x >= width || y >= height {
+            return;
+        }
+        let bit = 1 << x;
+        if on {
+            self.plane_rows[y] |= bit;
+        } else {
+            self.plane_rows[y] &= !bit;
+            self.plane_rows[HIRES_HEIGHT + y] &= !bit;
+        }
+        self.dirty_rows |= 1 << y;
+        self.needs_redraw = true;
+    }
+
+    /// Returns the width of the display at the active resolution.
+    pub fn display_width(&self) -> usize {
+        if self.hires { HIRES_WIDTH } else { SCREEN_WIDTH }
+    }
+
+    /// Returns the height of the display at the active resolution.
+    pub fn display_height(&self) -> usize {
+        if self.hires { HIRES_HEIGHT } else { SCREEN_HEIGHT }
     }
 
-    /// Returns the display.
-    pub fn get_display(&self) -> &[bool] {
-        return &self.display;
+    /// Returns the width and height of the display at the active resolution, 64x32 or 128x64.
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.display_width(), self.display_height())
     }
 
-    /// Resets the execution
+    /// Returns whether the SUPER-CHIP 128x64 high resolution mode is active.
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Returns the display as text, one line per row with `lit` for lit pixels and `clear` for clear
+    /// ones. `('█', ' ')` looks like the screen, `('#', '.')` keeps pictures lined up in test failures.
+    pub fn render_ascii(&self, lit: char, clear: char) -> String {
+        self.get_display()
+            .chunks(self.display_width())
+            .map(|row| row.iter().map(|&pixel| if pixel { lit } else { clear }).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Returns the display scaled up `scale` times, with lit pixels in the `on` color and clear ones in
+    /// the `off` color. Colors are given as 0xRRGGBB.
+    #[cfg(feature = "image")]
+    pub fn to_image(&self, scale: usize, on: u32, off: u32) -> image::RgbImage {
+        let [_, on @ ..] = on.to_be_bytes();
+        let [_, off @ ..] = off.to_be_bytes();
+        let scale = scale.max(1);
+        let (width, height) = (self.display_width(), self.display_height());
+        image::RgbImage::from_fn((width * scale) as u32, (height * scale) as u32, |x, y| {
+            let lit = self.get_row(y as usize / scale) >> (x as usize / scale) & 1 == 1;
+            image::Rgb(if lit { on } else { off })
+        })
+    }
+
+    /// Writes the display to `w` as a binary PPM image, with lit pixels in white and clear ones in black.
+    #[cfg(feature = "std")]
+    pub fn write_ppm<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "P6\n{} {}\n255\n", self.display_width(), self.display_height())?;
+        for pixel in self.get_display() {
+            let value = if pixel { 0xff } else { 0x00 };
+            w.write_all(&[value; 3])?;
+        }
+        Ok(())
+    }
+
+    /// Resets the execution, the loaded program is kept and starts over.
     pub fn reset(&mut self) {
+        let was_beeping = self.is_beeping();
         self.program_counter = 0x200;
-        self.display = [false; SCREEN_HEIGHT * SCREEN_WIDTH];
-        let mut memory = [0; MEMORY_SIZE];
+        self.hires = false;
+        self.clear_screen();
+        self.selected_planes = 1;
+        self.halted = false;
+        self.audio_pattern = [0; 16];
+        self.audio_pitch = DEFAULT_AUDIO_PITCH;
+        let mut memory = vec![0; self.config.memory_size()];
         Self::initialize_font(&mut memory);
+        memory[0x200..0x200 + self.program.len()].copy_from_slice(&self.program);
         self.memory = memory;
         self.registers = [0; 16];
         self.needs_redraw = false;
         self.index_register = 0;
-        self.delay_timer = 60; // 60hz 
-        self.sound_timer = 60;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
         self.keyboard = [false; 16];
-        self.stack = Vec::new(); // Unbounded stack for convenience 
+        self.waiting_for_key = false;
+        self.key_pressed_while_waiting = None;
+        self.stack = Stack::default();
+        self.drawn_this_frame = false;
+        self.instructions_executed = 0;
+        self.debugger.pc_history.clear();
         self.needs_redraw = true;
+        self.notify_beep_change(was_beeping);
     }
 
-    /// Goes through the fetch, decode, execute cycle once.
-    pub fn step(&mut self) {
-        let byte1 = self.memory[self.program_counter as usize];
-        let byte2 = self.memory[(self.program_counter as usize) + 1];
-        self.program_counter += 2;
+    /// Goes through the fetch, decode, execute cycle once, stopping first on a breakpoint.
+    pub fn step(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if self.halted {
+            return Ok(StepOutcome::Halted);
+        }
+        if self.debugger.check_breakpoint(self.program_counter) {
+            return Ok(StepOutcome::BreakpointHit { pc: self.program_counter });
+        }
+        let opcode = self.fetch()?;
+        let pc = self.program_counter;
+        self.debugger.trace(pc, opcode);
+        if self.debugger.has_step_hook() {
+            self.debugger.step_hook(&StepInfo {
+                pc,
+                opcode,
+                registers: &self.registers,
+                index_register: self.index_register,
+                delay_timer: self.delay_timer,
+                sound_timer: self.sound_timer,
+            });
+        }
+
+        // Only an F000 cut off by the end of memory doesn't decode, executing it reports that
+        let instruction = disasm::decode_at(&self.memory, pc as usize).unwrap_or_else(|| decode(opcode));
+        let result = self.execute(instruction);
+        let watchpoint_hit = self.debugger.take_watchpoint_hit();
+        if let (Ok(StepOutcome::Executed), Some((addr, old, new, kind))) = (&result, watchpoint_hit) {
+            return Ok(StepOutcome::WatchpointHit { addr, pc, old, new, kind });
+        }
+        result
+    }
 
-        let instruction = (
-            byte1 >> 4,
-            byte1 & 0xf,
-            byte2 >> 4,
-            byte2 & 0xf
-        );
+    /// Returns the opcode at the program counter, the instruction `step` would run next.
+    pub fn fetch(&self) -> Result<u16, Chip8Error> {
+        let pc = self.program_counter as usize;
+        match self.memory.get(pc..pc + 2) {
+            Some(&[byte1, byte2]) => Ok(u16::from_be_bytes([byte1, byte2])),
+            _ => Err(Chip8Error::PcOutOfBounds { pc: self.program_counter, history: self.recent_pcs() }),
+        }
+    }
+
+    /// Steps up to `n` times, stopping early on an error. It also stops once a draw is waiting for
+    /// the vertical blank or the program exited, since nothing more can run until then.
+    pub fn step_n(&mut self, n: usize) -> Result<(), Chip8Error> {
+        for _ in 0..n {
+            if self.step()? != StepOutcome::Executed {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Steps until the display needs to be redrawn, at most `max_steps` times, and returns the
+    /// number of instructions executed. Like `step_n` it stops early on an error, a draw waiting
+    /// for the vertical blank or an exited program. Nothing runs if a redraw is already needed,
+    /// so call `was_redrawn` first.
+    pub fn run_until_redraw(&mut self, max_steps: usize) -> Result<usize, Chip8Error> {
+        for steps in 0..max_steps {
+            if self.needs_redraw || self.step()? != StepOutcome::Executed {
+                return Ok(steps);
+            }
+        }
+        Ok(max_steps)
+    }
+
+    /// Executes `instruction` as if it had just been fetched from the program counter, which is
+    /// moved past it first. This is the execute part of `step`, without the breakpoints and hooks,
+    /// so an instruction can be run on a machine set up by hand. On an error the program counter
+    /// is left on the instruction.
+    pub fn execute(&mut self, instruction: Instruction) -> Result<StepOutcome, Chip8Error> {
+        let pc = self.program_counter;
+        self.debugger.pc_history.push(pc);
+        if let Some(coverage) = self.debugger.coverage.as_deref_mut() {
+            coverage::mark(coverage, pc, instruction.size());
+        }
+        // Wraps around after the last instruction of extended memory, the next step reports it
+        self.program_counter = self.program_counter.wrapping_add(2);
+        let result = self.execute_at(instruction, pc);
+        match result {
+            Ok(StepOutcome::Executed) => {
+                self.instructions_executed += 1;
+                if let Some(stats) = self.debugger.stats.as_deref_mut() {
+                    stats.record(instruction.kind());
+                }
+            },
+            Ok(_) => (),
+            // Leave the machine on the failing instruction so it can be inspected
+            Err(err) => {
+                self.program_counter = pc;
+                return Err(err.with_history(self.recent_pcs()));
+            },
+        }
+        result
+    }
+
+    /// Executes `instruction`, `pc` is the address it was fetched from.
+    fn execute_at(&mut self, instruction: Instruction, pc: u16) -> Result<StepOutcome, Chip8Error> {
+        use Instruction::*;
 
+        let opcode = instruction.opcode();
+        let superchip = self.config.variant.has_superchip_instructions();
         match instruction {
-            (0x0, 0x0, 0xE, 0x0) => { // 00E0
-                self.clear_screen();
+            ClearScreen => { // 00E0 = Clear the selected planes
+                self.clear_selected_planes();
                 self.needs_redraw = true;
             },
-            (0x1, nib1, nib2, nib3) => { // 1NNN = Unconditional jump
-                self.program_counter = Self::combine_nibbles(nib1, nib2, nib3);
+            Jump(addr) => { // 1NNN = Unconditional jump
+                self.program_counter = addr;
             }, 
-            (0x2, nib1, nib2, nib3) => { // 2NNN = Enter a subroutine
-                self.stack.push(self.program_counter);
-                self.program_counter = Self::combine_nibbles(nib1, nib2, nib3);
+            Call(addr) => { // 2NNN = Enter a subroutine
+                if self.stack.len() >= self.config.stack_limit || !self.stack.push(self.program_counter) {
+                    return Err(Chip8Error::StackOverflow { pc, history: PcHistory::default() });
+                }
+                self.program_counter = addr;
             },
-            (0x0, 0x0, 0xE, 0xE) => { // 00EE = Return from subroutine
-                self.program_counter = self.stack.pop().expect("Attempted to return from subroutine on empty stack.");
+            Return => { // 00EE = Return from subroutine
+                self.program_counter = self.stack.pop().ok_or(Chip8Error::StackUnderflow { pc, history: PcHistory::default() })?;
             }, 
-            (0x3, reg, _, _) => { // 3XNN = Skip inst. if reg == byte2 
-                if self.registers[reg as usize] == byte2 {
-                    self.program_counter += 2;
+            ScrollDown(rows) if superchip => { // 00CN = Scroll the display down by N rows
+                self.scroll(0, rows as usize as isize);
+            },
+            ScrollUp(rows) if self.config.variant == Variant::XoChip => { // 00DN = Scroll the display up by N rows
+                self.scroll(0, -(rows as isize));
+            },
+            ScrollRight if superchip => { // 00FB = Scroll the display right by 4 pixels
+                self.scroll(4, 0);
+            },
+            ScrollLeft if superchip => { // 00FC = Scroll the display left by 4 pixels
+                self.scroll(-4, 0);
+            },
+            Exit if superchip => { // 00FD = Exit the interpreter, stays on this instruction
+                self.program_counter = pc;
+                self.halted = true;
+                return Ok(StepOutcome::Halted);
+            },
+            LowRes if superchip => { // 00FE = Switch to low resolution, clears the screen
+                self.set_hires(false);
+            },
+            HighRes if superchip => { // 00FF = Switch to high resolution, clears the screen
+                self.set_hires(true);
+            },
+            SkipEqImm { x, nn } => { // 3XNN = Skip inst. if reg == byte2
+                if self.registers[x as usize] == nn {
+                    self.skip_instruction();
                 }
             },
-            (0x4, reg, _, _) => { // 4XNN = Skip isnt. if reg != byte2 
-                if self.registers[reg as usize] != byte2 {
-                    self.program_counter += 2;
+            SkipNeImm { x, nn } => { // 4XNN = Skip isnt. if reg != byte2
+                if self.registers[x as usize] != nn {
+                    self.skip_instruction();
                 }
             },
-            (0x5, reg1, reg2, 0x0) => { // 5XY0 = Skip inst. if reg1 == reg2
+            SkipEqReg { x: reg1, y: reg2 } => { // 5XY0 = Skip inst. if reg1 == reg2
                 if self.registers[reg1 as usize] == self.registers[reg2 as usize] {
-                    self.program_counter += 2;
+                    self.skip_instruction();
+                }
+            },
+            SaveRange { x: reg1, y: reg2 } if self.config.variant == Variant::XoChip => { // 5XY2 = Store VX to VY in memory at I
+                let registers = Self::register_range(reg1, reg2);
+                self.check_memory_range(opcode, pc, self.index_register, registers.len())?;
+                let start = self.index_register as usize;
+                for (i, reg) in registers.enumerate() {
+                    self.store(start + i, self.registers[reg]);
                 }
             },
-            (0x9, reg1, reg2, 0x0) => { // 9XY0 = Skip inst. if reg1 != reg2 
+            LoadRange { x: reg1, y: reg2 } if self.config.variant == Variant::XoChip => { // 5XY3 = Load VX to VY from memory at I
+                let registers = Self::register_range(reg1, reg2);
+                self.check_memory_range(opcode, pc, self.index_register, registers.len())?;
+                let start = self.index_register as usize;
+                self.watch_reads(start, registers.len());
+                for (i, reg) in registers.enumerate() {
+                    self.registers[reg] = self.memory[start + i];
+                }
+            },
+            SkipNeReg { x: reg1, y: reg2 } => { // 9XY0 = Skip inst. if reg1 != reg2
                 if self.registers[reg1 as usize] != self.registers[reg2 as usize] {
-                    self.program_counter += 2;
+                    self.skip_instruction();
                 }
             },
-            (0x6, reg, _, _) => { // 6XNN = Set reg to byte2
-                self.registers[reg as usize] = byte2;
+            LoadImm { x, nn } => { // 6XNN = Set reg to byte2
+                self.registers[x as usize] = nn;
             },
-            (0x7, reg, _, _) => { // 7XNN = Add byte2 to reg 
-                self.registers[reg as usize] = self.registers[reg as usize].wrapping_add(byte2);
+            AddImm { x, nn } => { // 7XNN = Add byte2 to reg
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(nn);
             },
-            (0x8, reg1, reg2, 0x0) => { // 8XY0 = Set reg1 to reg2 
+            LoadReg { x: reg1, y: reg2 } => { // 8XY0 = Set reg1 to reg2
                 self.registers[reg1 as usize] = self.registers[reg2 as usize];
             },
-            (0x8, reg1, reg2, 0x1) => { // 8XY1 = reg1 = reg1 | reg2
+            Or { x: reg1, y: reg2 } => { // 8XY1 = reg1 = reg1 | reg2
                 self.registers[reg1 as usize] |= self.registers[reg2 as usize];
-                self.registers[0xf] = 0;
+                if self.config.quirks.logic_resets_vf {
+                    self.registers[0xf] = 0;
+                }
             },
-            (0x8, reg1, reg2, 0x2) => { // 8XY2 = reg1 = reg1 & reg2
+            And { x: reg1, y: reg2 } => { // 8XY2 = reg1 = reg1 & reg2
                 self.registers[reg1 as usize] &= self.registers[reg2 as usize];
-                self.registers[0xf] = 0;
+                if self.config.quirks.logic_resets_vf {
+                    self.registers[0xf] = 0;
+                }
             },
-            (0x8, reg1, reg2, 0x3) => { // 8XY3 = reg1 = reg1 ^ reg2
+            Xor { x: reg1, y: reg2 } => { // 8XY3 = reg1 = reg1 ^ reg2
                 self.registers[reg1 as usize] ^= self.registers[reg2 as usize];
-                self.registers[0xf] = 0;
+                if self.config.quirks.logic_resets_vf {
+                    self.registers[0xf] = 0;
+                }
             },
-            (0x8, reg1, reg2, 0x4) => { // 8XY4 = reg1 = reg1 + reg2
+            AddReg { x: reg1, y: reg2 } => { // 8XY4 = reg1 = reg1 + reg2
                 let val1 = self.registers[reg1 as usize];
                 let val2 = self.registers[reg2 as usize];
                 let (value, did_overflow) = val1.overflowing_add(val2);
@@ -183,7 +1153,7 @@ impl Chip8 {
                     self.registers[0xf] = 0;
                 }
             },
-            (0x8, reg1, reg2, 0x5) => { // 8XY5 = reg1 = reg1 - reg2, VF = reg1 > reg2
+            Sub { x: reg1, y: reg2 } => { // 8XY5 = reg1 = reg1 - reg2, VF = reg1 > reg2
                 let val1 = self.registers[reg1 as usize];
                 let val2 = self.registers[reg2 as usize];
                 let (value, did_underflow) = val1.overflowing_sub(val2);
@@ -195,12 +1165,12 @@ impl Chip8 {
                     self.registers[0xf] = 0;
                 }
             },
-            (0x8, reg1, _, 0x6) => { // 8XY6 = reg1 = reg1 >> 1, VF = reg1 & 1
-                let value = self.registers[reg1 as usize];
+            ShiftRight { x: reg1, y: reg2 } => { // 8XY6 = reg1 = reg1 >> 1 (or reg2 >> 1), VF = shifted out bit
+                let value = self.registers[self.shift_source(reg1, reg2)];
                 self.registers[reg1 as usize] = value >> 1;
                 self.registers[0xf] = value & 1;
             },
-            (0x8, reg1, reg2, 0x7) => { // 8XY7 = reg1 = reg2 - reg1, VF = reg2 > reg1
+            SubReverse { x: reg1, y: reg2 } => { // 8XY7 = reg1 = reg2 - reg1, VF = reg2 > reg1
                  // 8XY5 = reg1 = reg1 - reg2, VF = reg1 > reg2
                 let val1 = self.registers[reg1 as usize];
                 let val2 = self.registers[reg2 as usize];
@@ -213,137 +1183,315 @@ impl Chip8 {
                     self.registers[0xf] = 0;
                 }
             },
-            (0x8, reg1, _, 0xe) => { // 8XYE = reg1 = reg1 << 1, VF = reg1 & (1 << 7)
-                let value = self.registers[reg1 as usize];
+            ShiftLeft { x: reg1, y: reg2 } => { // 8XYE = reg1 = reg1 << 1 (or reg2 << 1), VF = shifted out bit
+                let value = self.registers[self.shift_source(reg1, reg2)];
                 self.registers[reg1 as usize] = value << 1;
                 self.registers[0xf] = (value & (1 << 7)) >> 7;
             },
-            (0xa, nib1, nib2, nib3) => { //  ANNN = IndexRegister = NNN
-                self.index_register = Self::combine_nibbles(nib1, nib2, nib3);
+            LoadIndex(addr) => { //  ANNN = IndexRegister = NNN
+                self.index_register = addr;
             },
-            (0xb, nib1, nib2, nib3) => { // BXNN =  Jump to XNN + vX
-                self.program_counter = Self::combine_nibbles(nib1, nib2, nib3) + self.registers[nib1 as usize] as u16;
+            JumpOffset(addr) => { // BNNN = Jump to NNN + v0, or BXNN = Jump to XNN + vX
+                let offset_reg = if self.config.quirks.jump_with_offset_uses_vx { addr >> 8 } else { 0 };
+                // The address bus is 12 bits wide, so a target past the end of memory wraps around
+                let target = addr + self.registers[offset_reg as usize] as u16;
+                self.program_counter = target & 0xfff;
             },
-            (0xc, reg, _, _) => { // reg = rand & byte2
-                let rand_value: u8 = rand::random::<u8>();
-                self.registers[reg as usize] = rand_value & byte2;
+            Random { x, nn } => { // CXNN = reg = rand & byte2
+                let rand_value = self.rng.next_u8();
+                self.registers[x as usize] = rand_value & nn;
             },
-            (0xd, reg1, reg2, num_bytes) => { // DXYN = Changes the display
+            // DXYN = Changes the display. DXY0 draws a 16x16 sprite, except in the SUPER-CHIP low
+            // resolution mode, where it draws 8x16 like SUPER-CHIP 1.1, and on CHIP-8 and CHIP-48,
+            // where it draws nothing. VF is 1 if a lit pixel was turned off, except in the
+            // SUPER-CHIP high resolution mode, where it's the number of rows that turned a pixel
+            // off or were clipped at the bottom
+            Draw { x: reg1, y: reg2, n: num_bytes } => {
+                let (sprite_width, rows) = match (num_bytes, self.config.variant) {
+                    (0, Variant::Chip8 | Variant::Chip48) => (8, 0),
+                    (0, Variant::SuperChip) if !self.hires => (8, 16),
+                    (0, _) => (16, 16),
+                    _ => (8, num_bytes as usize),
+                };
+                let row_bytes = sprite_width / 8;
+                // Each selected plane gets its own sprite, stored one after the other
+                let sprite_bytes = rows * row_bytes;
+                let plane_count = self.selected_planes.count_ones() as usize;
+                self.check_memory_range(opcode, pc, self.index_register, sprite_bytes * plane_count)?;
+                if self.config.quirks.display_wait {
+                    if self.drawn_this_frame {
+                        self.program_counter = pc; // run the draw again once the frame has ended
+                        return Ok(StepOutcome::WaitingForVblank);
+                    }
+                    self.drawn_this_frame = true;
+                }
+                self.watch_reads(self.index_register as usize, sprite_bytes * plane_count);
                 self.needs_redraw = true;
-                let x_pos: u8 = self.registers[reg1 as usize] % (SCREEN_WIDTH as u8);
-                let y_pos: u8 = self.registers[reg2 as usize] % (SCREEN_HEIGHT as u8);
-                let mut flipped = false; // Check if any pixel was flipped
-
-                'draw_loop: for row_num in 0..num_bytes {
-                    let pixels = self.memory[(self.index_register + row_num as u16) as usize];
-                    for sprite_pos in 0..8 {
-                        // stop writing when reaching edge of screen
-                        let sprite_pixel = (pixels & (0b10000000 >> sprite_pos)) != 0;
-                        let index = ((x_pos + sprite_pos) as usize) + ((y_pos + row_num) as usize) * SCREEN_WIDTH;
-                        if index >= self.display.len() {
-                            break 'draw_loop;
+                // The starting position wraps, but the sprite itself is clipped at the edges
+                let (width, height) = (self.display_width(), self.display_height());
+                let x_pos = self.registers[reg1 as usize] as usize % width;
+                let y_pos = self.registers[reg2 as usize] as usize % height;
+                let mut collided_rows = 0; // The rows that turned a lit pixel off
+                let mut clipped_rows = 0;
+
+                let planes = (0..2).filter(|plane| self.selected_planes & (1 << plane) != 0);
+                for (plane_num, plane) in planes.enumerate() {
+                    let sprite_start = self.index_register as usize + plane_num * sprite_bytes;
+                    for row_num in 0..rows {
+                        let mut y = y_pos + row_num;
+                        if self.config.quirks.sprite_wrapping {
+                            y %= height;
+                        } else if y >= height { // stop drawing rows past the bottom edge
+                            clipped_rows += rows - row_num;
+                            break;
+                        }
+                        // The row's pixels, with the leftmost one in the top bit
+                        let row_start = sprite_start + row_num * row_bytes;
+                        let pixels = self.memory[row_start..row_start + row_bytes].iter()
+                            .fold(0u16, |row, byte| row << 8 | *byte as u16) << (16 - sprite_width);
+                        let bits = self.sprite_row_bits(pixels, x_pos);
+                        let row = &mut self.plane_rows[plane * HIRES_HEIGHT + y];
+                        if *row & bits != 0 {
+                            collided_rows += 1;
+                        }
+                        *row ^= bits;
+                        if bits != 0 {
+                            self.dirty_rows |= 1 << y;
                         }
-                        flipped |= self.display[index as usize] != sprite_pixel;
-                        self.display[index as usize] ^= sprite_pixel;
                     }
                 }
-                if flipped {
-                    self.registers[0xf] = 1;
+                self.registers[0xf] = if self.config.variant == Variant::SuperChip && self.hires {
+                    (collided_rows + clipped_rows) as u8
                 } else {
-                    self.registers[0xf] = 0;
-                }
+                    (collided_rows > 0) as u8
+                };
             }, 
-            (0xe, reg, 0x9, 0xe) => { // EX9E = Skip if key in reg is pressed 
-                if self.keyboard[self.registers[reg as usize] as usize] {
-                    self.program_counter += 2;
+            SkipKey { x: reg } => { // EX9E = Skip if key in reg is pressed, only its low nibble is used
+                if self.keyboard[(self.registers[reg as usize] & 0xf) as usize] {
+                    self.skip_instruction();
                 }
             }, 
-            (0xe, reg, 0xa, 0x1) => { // EXA1 = Skip is key in reg is not pressed
-                if !self.keyboard[self.registers[reg as usize] as usize] {
-                    self.program_counter += 2;
+            SkipNotKey { x: reg } => { // EXA1 = Skip is key in reg is not pressed, only its low nibble is used
+                if !self.keyboard[(self.registers[reg as usize] & 0xf) as usize] {
+                    self.skip_instruction();
                 }
             },
-            (0xf, reg, 0x0, 0x7) => { // FX07 = Sets the reg to delay timer
+            GetDelay { x: reg } => { // FX07 = Sets the reg to delay timer
                 self.registers[reg as usize] = self.delay_timer;
             },
-            (0xf, reg, 0x1, 0x5) => { // FX15
+            SetDelay { x: reg } => { // FX15
                 self.delay_timer = self.registers[reg as usize];
             },
-            (0xf, reg, 0x1, 0x8) => { // FX18
+            SetSound { x: reg } => { // FX18
+                let was_beeping = self.is_beeping();
                 self.sound_timer = self.registers[reg as usize];
+                self.notify_beep_change(was_beeping);
             },
-            (0xf, reg, 0x1, 0xe) => { // FX1E
-                self.index_register = self.index_register.wrapping_add(self.registers[reg as usize] as u16);
-            },
-            (0xf, reg, 0x0, 0xa) => { // FX0A
-                let mut any_pressed = false;
-                for (i, key) in self.keyboard.iter().enumerate() {
-                    if *key {
-                        self.registers[reg as usize] = i as u8;
-                        any_pressed = true;
-                    }
+            AddIndex { x: reg } => { // FX1E = Add reg to I
+                let sum = self.index_register as usize + self.registers[reg as usize] as usize;
+                let last_address = self.memory.len() - 1;
+                self.index_register = (sum & last_address) as u16;
+                if self.config.quirks.index_overflow_sets_vf {
+                    self.registers[0xf] = (sum > last_address) as u8;
                 }
-                if !any_pressed { // loop until key is pressed
-                    self.program_counter -= 2;
+            },
+            WaitKey { x: reg } => { // FX0A = Wait for a key to be pressed and released, store it in reg
+                self.waiting_for_key = true;
+                match self.key_pressed_while_waiting {
+                    Some(key) if !self.keyboard[key as usize] => {
+                        self.registers[reg as usize] = key;
+                        self.waiting_for_key = false;
+                        self.key_pressed_while_waiting = None;
+                    },
+                    _ => self.program_counter = pc, // loop until a key is pressed and released
                 }
             },
-            (0xf, reg, 0x2, 0x9) => { // Fx29 = Sets I reg to the font in vx
-                let x = reg as usize;
-                let c = self.registers[x] as u16;
-                self.index_register = c * 5;
+            LoadFont { x: reg } => { // Fx29 = Sets I reg to the font in vx, only the low nibble is used
+                let c = (self.registers[reg as usize] & 0xf) as u16;
+                self.index_register = FONT_ADDRESS + c * 5;
             },
-            (0xf, reg, 0x3, 0x3) => { // FX33 = Stores the digits of num in reg at the address in I
+            LoadBigFont { x: reg } if superchip => { // FX30 = Sets I reg to the big font in vx, only the low nibble is used
+                let c = (self.registers[reg as usize] & 0xf) as u16;
+                self.index_register = BIG_FONT_ADDRESS + c * 10;
+            },
+            StoreBcd { x: reg } => { // FX33 = Stores the digits of num in reg at the address in I
+                self.check_memory_range(opcode, pc, self.index_register, 3)?;
                 let num = self.registers[reg as usize];
-                self.memory[self.index_register as usize] = num / 100;
-                self.memory[(self.index_register + 1) as usize] = (num / 10) % 10;
-                self.memory[(self.index_register + 2) as usize] = num % 10;
+                let start = self.index_register as usize;
+                self.store(start, num / 100);
+                self.store(start + 1, (num / 10) % 10);
+                self.store(start + 2, num % 10);
             },
-            (0xf, reg, 0x5, 0x5) => { // Fx55 = Load into memory from reg at address I
+            StoreRegs { x: reg } => { // Fx55 = Load into memory from reg at address I
+                self.check_memory_range(opcode, pc, self.index_register, reg as usize + 1)?;
                 let i_reg_value = self.index_register as usize;
                 let x = reg as usize;
                 for i in 0..=x {
-                    self.memory[i_reg_value + i] = self.registers[i];
+                    self.store(i_reg_value + i, self.registers[i]);
                 }
+                self.increment_index_after_load_store(reg);
             },
-            (0xf, reg, 0x6, 0x5) => { // FX65 = Load into reg from memory at address I
+            LoadRegs { x: reg } => { // FX65 = Load into reg from memory at address I
+                self.check_memory_range(opcode, pc, self.index_register, reg as usize + 1)?;
                 let i_reg_value = self.index_register as usize;
                 let x = reg as usize;
+                self.watch_reads(i_reg_value, x + 1);
                 for i in 0..=x {
                     self.registers[i] = self.memory[i_reg_value + i];
                 }
+                self.increment_index_after_load_store(reg);
             }
-            (0x0, _, _, _) => {}, // Do nothing, for compatibility.
-            (_, _, _, _) => unimplemented!("ERROR: Instruction {:?} not implemented.", instruction),
+            StoreFlags { x: reg } if superchip => { // FX75 = Save V0 to reg into the RPL flags
+                let last = (reg as usize).min(self.rpl_flag_count() - 1);
+                self.rpl_flags[..=last].copy_from_slice(&self.registers[..=last]);
+            },
+            LoadFlags { x: reg } if superchip => { // FX85 = Load V0 to reg from the RPL flags
+                let last = (reg as usize).min(self.rpl_flag_count() - 1);
+                self.registers[..=last].copy_from_slice(&self.rpl_flags[..=last]);
+            },
+            LoadIndexLong(addr) if self.config.variant == Variant::XoChip => { // F000 NNNN = Set I to NNNN
+                // The address word still has to be there to step over
+                self.check_memory_range(opcode, pc, pc.saturating_add(2), 2)?;
+                self.index_register = addr;
+                self.program_counter = self.program_counter.wrapping_add(2);
+            },
+            LoadAudio if self.config.variant == Variant::XoChip => { // F002 = Load the audio pattern from I
+                self.check_memory_range(opcode, pc, self.index_register, 16)?;
+                let start = self.index_register as usize;
+                self.watch_reads(start, 16);
+                self.audio_pattern.copy_from_slice(&self.memory[start..start + 16]);
+            },
+            SetPitch { x: reg } if self.config.variant == Variant::XoChip => { // FX3A = Set the audio pitch to reg
+                self.audio_pitch = self.registers[reg as usize];
+            },
+            SelectPlanes(planes) if self.config.variant == Variant::XoChip => { // FN01 = Select the planes to draw to
+                self.selected_planes = planes & 0b11;
+            },
+            System(_) | ScrollUp(_) => {}, // Do nothing, for compatibility.
+            _ => return Err(Chip8Error::UnknownOpcode { opcode, pc, history: PcHistory::default() }),
         }
+        Ok(StepOutcome::Executed)
     }
 
     /// Decrements both the delay and the sound timers. Does not reset after they reach 0, that is
     /// the responsibility of the program. 
     pub fn tick_timers(&mut self) {
+        self.end_of_frame();
+        let was_beeping = self.is_beeping();
         if self.sound_timer > 0 {
             self.sound_timer -= 1;
         }
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
+        self.notify_beep_change(was_beeping);
     }
 
-    /// Sets all the display pixels to 0. 
-    fn clear_screen(&mut self) {
-        for i in 0..self.display.len() {
-            self.display[i] = false;
-        }
+    /// Marks the vertical blank, letting a DXYN waiting on the display wait quirk run. Called by
+    /// `tick_timers`, only needed when the timers are driven separately from the frames.
+    pub fn end_of_frame(&mut self) {
+        self.drawn_this_frame = false;
     }
 
-    /// A number 0-15 that marks the position on the control grid. Allows the frontend to choose the key mappings.
-    pub fn press_key(&mut self, key_num: u8) {
-        if key_num > 0xf { // Invalid key entered, ignore
-            return; 
-        }
-        self.keyboard[key_num as usize] = true;
+    /// Returns whether a tone should be playing, that is while the sound timer is not zero.
+    pub fn is_beeping(&self) -> bool {
+        self.sound_timer > 0
     }
 
-    /// Unpresses the specified key.
+    /// Sets a callback that is called with the new beep state each time the tone starts or stops,
+    /// so a frontend can drive its audio output without polling `is_beeping`.
+    pub fn set_sound_callback(&mut self, cb: Box<dyn FnMut(bool)>) {
+        self.sound_callback = SoundCallback(Some(cb));
+    }
+
+    /// Calls the sound callback if the beep state is no longer `was_beeping`.
+    fn notify_beep_change(&mut self, was_beeping: bool) {
+        let beeping = self.is_beeping();
+        if beeping != was_beeping {
+            if let Some(callback) = self.sound_callback.0.as_mut() {
+                callback(beeping);
+            }
+        }
+    }
+
+    /// Switches between the low and high resolution modes, clearing the screen since the pixels
+    /// are laid out differently in each.
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.clear_screen();
+        self.needs_redraw = true;
+    }
+
+    /// Returns the bits a sprite row covers when drawn at `x`, given its pixels with the leftmost
+    /// one in the top bit. Pixels past the right edge wrap around or are clipped depending on the
+    /// sprite wrapping quirk.
+    fn sprite_row_bits(&self, pixels: u16, x: usize) -> u128 {
+        let pixels = pixels.reverse_bits() as u128;
+        let width = self.display_width();
+        if width == HIRES_WIDTH {
+            return if self.config.quirks.sprite_wrapping { pixels.rotate_left(x as u32) } else { pixels << x };
+        }
+        // A sprite starting within the 64 pixels of a row still fits in the u128
+        let shifted = pixels << x;
+        let wrapped = if self.config.quirks.sprite_wrapping { shifted >> width } else { 0 };
+        (shifted | wrapped) & Self::row_mask(width)
+    }
+
+    /// Returns the bits of a row that hold pixels at the given display width.
+    fn row_mask(width: usize) -> u128 {
+        u128::MAX >> (HIRES_WIDTH - width)
+    }
+
+    /// Moves the contents of the selected planes `dx` pixels right and `dy` pixels down, in pixels of
+    /// the active resolution. Pixels moved past an edge are lost and the vacated ones are cleared.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = self.dimensions();
+        let mask = Self::row_mask(width);
+        for plane in (0..2).filter(|plane| self.selected_planes & (1 << plane) != 0) {
+            let rows = &mut self.plane_rows[plane * HIRES_HEIGHT..][..height];
+            let old = rows.to_vec();
+            for (y, row) in rows.iter_mut().enumerate() {
+                let source_y = y as isize - dy;
+                *row = if (0..height as isize).contains(&source_y) {
+                    let source = old[source_y as usize];
+                    let moved = if dx >= 0 { source << dx } else { source >> -dx };
+                    moved & mask
+                } else {
+                    0
+                };
+            }
+        }
+        self.dirty_rows = u64::MAX;
+        self.needs_redraw = true;
+    }
+
+    /// Clears the pixels of the selected planes, leaving the others.
+    fn clear_selected_planes(&mut self) {
+        for plane in (0..2).filter(|plane| self.selected_planes & (1 << plane) != 0) {
+            self.plane_rows[plane * HIRES_HEIGHT..][..HIRES_HEIGHT].fill(0);
+        }
+        self.dirty_rows = u64::MAX;
+    }
+
+    /// Sets all the display pixels to 0, in every plane.
+    fn clear_screen(&mut self) {
+        self.plane_rows = [0; 2 * HIRES_HEIGHT];
+        self.dirty_rows = u64::MAX;
+    }
+
+    /// A number 0-15 that marks the position on the control grid. Allows the frontend to choose the key mappings.
+    pub fn press_key(&mut self, key_num: u8) {
+        if key_num > 0xf { // Invalid key entered, ignore
+            return; 
+        }
+        // Keys already held when FX0A started waiting don't count, only new presses do
+        if self.waiting_for_key && !self.keyboard[key_num as usize] && self.key_pressed_while_waiting.is_none() {
+            self.key_pressed_while_waiting = Some(key_num);
+        }
+        self.keyboard[key_num as usize] = true;
+    }
+
+    /// Unpresses the specified key.
     pub fn unpress_key(&mut self, key_num: u8) {
         if key_num > 0xf {
             return;
@@ -351,32 +1499,293 @@ impl Chip8 {
         self.keyboard[key_num as usize] = false;
     }
 
-    /// Sets the needs_redraw flag to false.
+    /// Sets the needs_redraw flag to false and clears the dirty rows.
     pub fn was_redrawn(&mut self) {
         self.needs_redraw = false;
+        self.dirty_rows = 0;
+    }
+
+    /// Returns the display rows changed since the last `was_redrawn` in order, and clears them
+    /// like `was_redrawn` does, so each change is only drawn once.
+    pub fn take_dirty_rows(&mut self) -> impl Iterator<Item = usize> {
+        let rows = self.dirty_rows;
+        self.was_redrawn();
+        (0..self.display_height()).filter(move |y| rows & (1 << y) != 0)
     }
     
     pub fn needs_redraw(&self) -> bool {
-        return self.needs_redraw;
+        self.needs_redraw
+    }
+
+    /// Returns the value of the general purpose register VX, or 0 if `i` isn't a register (0-15).
+    pub fn register(&self, i: usize) -> u8 {
+        if i > 0xf {
+            return 0;
+        }
+        self.registers[i]
+    }
+
+    /// Returns the general purpose registers V0-VF.
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    /// Sets the general purpose register VX, ignored if `i` isn't a register (0-15).
+    pub fn set_register(&mut self, i: usize, v: u8) {
+        if i > 0xf {
+            return;
+        }
+        self.registers[i] = v;
+    }
+
+    /// Returns the address of the next instruction to execute.
+    pub fn pc(&self) -> u16 {
+        self.program_counter
+    }
+
+    /// Moves execution to `pc`.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.program_counter = pc;
+    }
+
+    /// Returns the value of the index register I.
+    pub fn index(&self) -> u16 {
+        self.index_register
+    }
+
+    /// Returns the delay timer, counting down to 0 at 60 Hz.
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    /// Returns the sound timer, the tone plays while it counts down to 0 at 60 Hz.
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Returns the return addresses of the subroutines being run, the innermost one last.
+    pub fn stack(&self) -> &[u16] {
+        self.stack.as_slice()
+    }
+
+    /// Returns the number of bytes of memory, 4KB or 64KB with `extended_memory`.
+    pub fn memory_size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Returns the byte at `addr`, or `None` if it's past the end of memory.
+    pub fn read_memory(&self, addr: u16) -> Option<u8> {
+        self.memory.get(addr as usize).copied()
+    }
+
+    /// Returns the `len` bytes starting at `addr`, or `None` if any of them are past the end of memory.
+    pub fn read_memory_range(&self, addr: u16, len: usize) -> Option<&[u8]> {
+        (addr as usize).checked_add(len).and_then(|end| self.memory.get(addr as usize..end))
+    }
+
+    /// Writes `value` to `addr`. Fails if it's past the end of memory or in the font while it's
+    /// write protected.
+    pub fn write_memory(&mut self, addr: u16, value: u8) -> Result<(), MemError> {
+        self.write_memory_slice(addr, &[value])
+    }
+
+    /// Writes `bytes` starting at `addr`. Nothing is written if any of them would be past the end
+    /// of memory or in the font while it's write protected.
+    pub fn write_memory_slice(&mut self, addr: u16, bytes: &[u8]) -> Result<(), MemError> {
+        let range = addr as usize..addr as usize + bytes.len();
+        if range.end > self.memory.len() {
+            return Err(MemError::OutOfBounds { addr: range.start.max(self.memory.len()) });
+        }
+        if self.debugger.font_protected && range.start < FONT_END as usize && range.end > FONT_ADDRESS as usize {
+            return Err(MemError::Protected { addr: range.start.max(FONT_ADDRESS as usize) });
+        }
+        self.memory[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Makes `write_memory` and `write_memory_slice` refuse to change the fonts. The font is just
+    /// memory, so this is off by default and the program itself can always write to it.
+    pub fn set_font_protection(&mut self, protected: bool) {
+        self.debugger.font_protected = protected;
+    }
+
+    /// Returns the instruction behaviors being emulated.
+    pub fn quirks(&self) -> &Quirks {
+        &self.config.quirks
+    }
+
+    /// Returns the platform being emulated.
+    pub fn variant(&self) -> Variant {
+        self.config.variant
+    }
+
+    /// Changes the instruction behaviors being emulated, takes effect from the next instruction.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.config.quirks = quirks;
+    }
+
+    /// Returns the number of instructions executed since the last reset. Draws waiting for the
+    /// vertical blank and steps that failed or found the machine halted aren't counted.
+    pub fn cycles(&self) -> u64 {
+        self.instructions_executed
+    }
+
+    /// Returns whether the program exited with 00FD.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Returns the XO-CHIP audio pattern, 128 1-bit samples with the first one in the top bit of the first byte.
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    /// Returns the XO-CHIP audio pitch, the pattern plays at 4000 * 2^((pitch - 64) / 48) samples per second.
+    pub fn audio_pitch(&self) -> u8 {
+        self.audio_pitch
+    }
+
+    /// Returns how many pattern samples play per second at the current audio pitch. It needs `std`
+    /// for the floating point math, like `fill_audio_samples`.
+    #[cfg(feature = "std")]
+    pub fn audio_playback_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.audio_pitch as f64 - 64.0) / 48.0)
+    }
+
+    /// Fills `out` with the audio pattern resampled to `sample_rate`, 1.0 for set bits and -1.0 for
+    /// clear ones, or silence while the sound timer isn't running. `position` is the place in the
+    /// 128 sample pattern to start from and is advanced past the written samples, so keep it
+    /// between calls for the pattern to play on seamlessly.
+    #[cfg(feature = "std")]
+    pub fn fill_audio_samples(&self, out: &mut [f32], sample_rate: u32, position: &mut f64) {
+        if !self.is_beeping() {
+            out.fill(0.0);
+            return;
+        }
+        let step = self.audio_playback_rate() / sample_rate as f64;
+        for sample in out.iter_mut() {
+            let bit = *position as usize;
+            let set = self.audio_pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            *sample = if set { 1.0 } else { -1.0 };
+            *position = (*position + step) % 128.0;
+        }
+    }
+
+    /// Returns the RPL user flags, only the first 8 are used except on XO-CHIP.
+    pub fn get_rpl_flags(&self) -> &[u8; 16] {
+        &self.rpl_flags
     }
 
-    /// Combines 3 nibbles into one u16, top 4 bits empty.
-    fn combine_nibbles(nib1: u8, nib2: u8, nib3: u8) -> u16 {
-        let mut res: u16 = 0;
-        res |= ((nib1 & 0xf) as u16) << 8;
-        res |= ((nib2 & 0xf) as u16) << 4;
-        res |= (nib3 & 0xf) as u16;
-        res
+    /// Sets the RPL user flags, e.g. to restore the ones saved by a previous run. Extra values past
+    /// the 16 flags are ignored.
+    pub fn set_rpl_flags(&mut self, flags: &[u8]) {
+        let len = flags.len().min(self.rpl_flags.len());
+        self.rpl_flags[..len].copy_from_slice(&flags[..len]);
+    }
+
+    /// Returns how many RPL flags FX75/FX85 can reach, 8 on SUPER-CHIP and 16 on XO-CHIP.
+    fn rpl_flag_count(&self) -> usize {
+        if self.config.variant == Variant::XoChip { 16 } else { 8 }
+    }
+
+    /// Returns the number of subroutine calls that haven't returned yet.
+    pub fn stack_depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Whether execution is blocked on an FX0A until a key is pressed and released.
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key
+    }
+
+    /// Checks that the `len` bytes starting at `start` are all inside memory, for the instruction `opcode` at `pc`.
+    fn check_memory_range(&self, opcode: u16, pc: u16, start: u16, len: usize) -> Result<(), Chip8Error> {
+        if start as usize + len > self.memory.len() {
+            // The first address past the end, or the last address of the full 64KB
+            let address = (start as usize).max(self.memory.len()).min(u16::MAX as usize) as u16;
+            return Err(Chip8Error::MemoryOutOfBounds { opcode, pc, address, history: PcHistory::default() });
+        }
+        Ok(())
+    }
+
+    /// Writes `value` to `addr` for an instruction, which should have checked the address with
+    /// `check_memory_range`. Every memory write of an instruction goes through here so watchpoints
+    /// see it.
+    fn store(&mut self, addr: usize, value: u8) {
+        let old = core::mem::replace(&mut self.memory[addr], value);
+        self.debugger.check_write(addr as u16, old, value);
+    }
+
+    /// Lets the read watchpoints see an instruction reading `len` bytes of data from `start`,
+    /// which should have been checked with `check_memory_range`.
+    fn watch_reads(&mut self, start: usize, len: usize) {
+        self.debugger.check_reads(start, &self.memory[start..start + len]);
+    }
+
+    /// Skips over the next instruction, which is 4 bytes long for the XO-CHIP F000 NNNN.
+    fn skip_instruction(&mut self) {
+        let next = self.program_counter as usize;
+        let long = self.config.variant == Variant::XoChip
+            && self.memory.get(next..next + 2) == Some(&[0xf0, 0x00]);
+        self.program_counter = self.program_counter.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    /// Advances I after FX55/FX65 handled V0..VX, as selected by the memory increment quirk.
+    fn increment_index_after_load_store(&mut self, x: u8) {
+        let increment = match self.config.quirks.memory_increment {
+            MemoryIncrement::Unchanged => 0,
+            MemoryIncrement::XPlusOne => x as u16 + 1,
+            MemoryIncrement::X => x as u16,
+        };
+        self.index_register = self.index_register.wrapping_add(increment);
+    }
+
+    /// Returns the registers from `first` to `last` inclusive for 5XY2/5XY3, counting down if
+    /// `first` is the larger one.
+    fn register_range(first: u8, last: u8) -> impl ExactSizeIterator<Item = usize> {
+        let (first, last) = (first as usize, last as usize);
+        (0..first.abs_diff(last) + 1).map(move |i| if first <= last { first + i } else { first - i })
+    }
+
+    /// Returns the register 8XY6/8XYE shift, VY if configured to copy it into VX first.
+    fn shift_source(&self, reg1: u8, reg2: u8) -> usize {
+        if self.config.quirks.shift_uses_vy {
+            reg2 as usize
+        } else {
+            reg1 as usize
+        }
     }
 }
 
-fn pause() {
-    io::stdin().read_line(&mut String::new()).unwrap();
+/// Steps per timer tick in `run_headless`, the same default speed as the emulator frontend.
+const HEADLESS_STEPS_PER_FRAME: usize = 10;
+
+/// Runs `rom` for `steps` instructions without a window, ticking the timers every few steps as if
+/// running at 60 frames per second, and returns the final display. The random numbers are seeded
+/// so runs are reproducible. Execution stops early if an instruction fails, returning the display
+/// as it was at that point.
+pub fn run_headless(rom: &[u8], steps: usize) -> Result<Vec<bool>, LoadError> {
+    let mut chip8 = Chip8::with_rng(0);
+    chip8.load(rom)?;
+    for step in 1..=steps {
+        if chip8.step().is_err() {
+            break;
+        }
+        if step % HEADLESS_STEPS_PER_FRAME == 0 {
+            chip8.tick_timers();
+        }
+    }
+    Ok(chip8.get_display())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::ops::Range;
+
+    fn history(pcs: &[u16]) -> PcHistory {
+        PcHistory::from_slice(pcs)
+    }
 
     #[test]
     fn test_font_init() {
@@ -406,48 +1815,2072 @@ mod tests {
     fn load_program() {
         let mut emu = Chip8::new();
         let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
-        emu.load(&data);
+        emu.load(&data).unwrap();
         assert_eq!(emu.memory[0x200..=0x200+data.len()-1], data);
     }
 
     #[test]
-    #[should_panic]
     fn too_large_program() {
         let mut emu = Chip8::new();
         let data = vec![0; 10000];
-        emu.load(&data);
+        assert_eq!(emu.load(&data), Err(LoadError::TooLarge { size: 10000, max: 3584 }));
+        assert!(emu.load(&[0; 3584]).is_ok());
+    }
+
+    #[test]
+    fn empty_program() {
+        let mut emu = Chip8::new();
+        assert_eq!(emu.load(&[]), Err(LoadError::Empty));
     }
 
     #[test]
     fn clear_screen() {
         let mut emu = Chip8::new();
-        emu.display = [true; SCREEN_HEIGHT * SCREEN_WIDTH];
+        emu.plane_rows = [u128::MAX; 2 * HIRES_HEIGHT];
         emu.clear_screen();
-        assert_eq!(emu.display, [false; SCREEN_HEIGHT * SCREEN_WIDTH]);
+        assert_eq!(emu.plane_rows, [0; 2 * HIRES_HEIGHT]);
+        assert!(emu.get_display_indexed().iter().all(|&color| color == 0));
     }
 
     #[test]
     fn jump() {
         let mut emu = Chip8::new();
         let data = vec![0x11, 0x11]; // Jump to 111
-        emu.load(&data);
-        emu.step();
+        emu.load(&data).unwrap();
+        emu.step().unwrap();
         assert_eq!(emu.program_counter, 0x111);
     }
 
+    /// Draws `sprite` at (x, y) by running a single DXYN instruction.
+    fn draw_at(emu: &mut Chip8, x: u8, y: u8, sprite: &[u8]) {
+        for (i, byte) in sprite.iter().enumerate() {
+            emu.memory[0x300 + i] = *byte;
+        }
+        emu.index_register = 0x300;
+        emu.registers[0] = x;
+        emu.registers[1] = y;
+        emu.memory[0x200] = 0xd0;
+        emu.memory[0x201] = 0x10 | sprite.len() as u8;
+        emu.program_counter = 0x200;
+        emu.step().unwrap();
+    }
+
+    /// Returns the coordinates of every lit pixel.
+    fn lit_pixels(emu: &Chip8) -> Vec<(usize, usize)> {
+        emu.get_display_indexed().iter().enumerate()
+            .filter(|(_, pixel)| **pixel != 0)
+            .map(|(i, _)| (i % emu.display_width(), i / emu.display_width()))
+            .collect()
+    }
+
+    /// Sets the pixel at (`x`, `y`) to the color index `color`, bypassing the drawing instructions.
+    fn light(emu: &mut Chip8, x: usize, y: usize, color: u8) {
+        for plane in 0..2 {
+            let row = &mut emu.plane_rows[plane * HIRES_HEIGHT + y];
+            *row = *row & !(1 << x) | ((color >> plane & 1) as u128) << x;
+        }
+    }
+
+    /// Columns `x` of rows `y` of the display drawn by `render_ascii` with `#` and `.`.
+    fn crop(emu: &Chip8, x: Range<usize>, y: Range<usize>) -> Vec<String> {
+        let text = emu.render_ascii('#', '.');
+        text.lines().skip(y.start).take(y.len()).map(|line| line[x.clone()].to_string()).collect()
+    }
+
     #[test]
     fn draw_sprite() {
-        unimplemented!();
+        let mut emu = Chip8::new();
+        let zero = emu.memory[0x50..0x55].to_vec();
+        draw_at(&mut emu, 10, 5, &zero);
+        assert_eq!(crop(&emu, 8..16, 4..11), [
+            "........",
+            "..####..",
+            "..#..#..",
+            "..#..#..",
+            "..#..#..",
+            "..####..",
+            "........",
+        ]);
+        assert_eq!(emu.render_ascii('#', '.').matches('#').count(), 14);
+        assert!(emu.needs_redraw());
+    }
+
+    #[test]
+    fn clear_screen_blanks_the_picture() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 10, 5, &[0xff, 0xff]);
+        draw_at(&mut emu, 60, 30, &[0xff, 0xff]);
+        emu.memory[0x200..0x202].copy_from_slice(&[0x00, 0xe0]);
+        emu.program_counter = 0x200;
+        emu.step().unwrap();
+        assert_eq!(emu.render_ascii('#', '.'), vec![".".repeat(SCREEN_WIDTH); SCREEN_HEIGHT].join("\n"));
+    }
+
+    #[test]
+    fn draw_sprite_clipped_at_the_corner() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 60, 30, &[0xff, 0xff, 0xff]);
+        assert_eq!(crop(&emu, 56..64, 28..32), [
+            "........",
+            "........",
+            "....####",
+            "....####",
+        ]);
+        // Nothing wraps around to the other edges
+        assert_eq!(emu.render_ascii('#', '.').matches('#').count(), 8);
+    }
+
+    #[test]
+    fn draw_sprite_collision() {
+        let mut emu = Chip8::new();
+        // The 0 font sprite drawn straight from the font at (2, 1), then again 2 pixels lower
+        emu.load(&[0x00, 0xe0, 0xa0, 0x50, 0x60, 0x02, 0x61, 0x01, 0xd0, 0x15, 0x61, 0x03, 0xd0, 0x15, 0xd0, 0x15]).unwrap();
+        for _ in 0..5 {
+            emu.step().unwrap();
+        }
+        let zero = [(2, 1), (3, 1), (4, 1), (5, 1), (2, 2), (5, 2), (2, 3), (5, 3), (2, 4), (5, 4), (2, 5), (3, 5), (4, 5), (5, 5)];
+        assert_eq!(lit_pixels(&emu), zero);
+        assert_eq!(emu.registers[0xf], 0);
+
+        // Overlapping pixels are turned off and set VF
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0xf], 1);
+        assert_eq!(lit_pixels(&emu), [(2, 1), (3, 1), (4, 1), (5, 1), (2, 2), (5, 2), (3, 3), (4, 3), (3, 5), (4, 5), (2, 6), (5, 6), (2, 7), (3, 7), (4, 7), (5, 7)]);
+
+        // Drawing the same sprite again erases it, leaving the first one
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0xf], 1);
+        assert_eq!(lit_pixels(&emu), zero);
+    }
+
+    #[test]
+    fn draw_sprite_clips_right_edge() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 60, 0, &[0xff]);
+        assert_eq!(lit_pixels(&emu), vec![(60, 0), (61, 0), (62, 0), (63, 0)]);
+    }
+
+    #[test]
+    fn draw_sprite_clips_bottom_edge() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 0, 30, &[0x80, 0x80, 0x80, 0x80]);
+        assert_eq!(lit_pixels(&emu), vec![(0, 30), (0, 31)]);
+    }
+
+    #[test]
+    fn draw_sprite_left_and_top_edges() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 0, 0, &[0x81]);
+        assert_eq!(lit_pixels(&emu), vec![(0, 0), (7, 0)]);
+    }
+
+    #[test]
+    fn draw_sprite_start_position_wraps() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 64 + 2, 32 + 3, &[0x80]);
+        assert_eq!(lit_pixels(&emu), vec![(2, 3)]);
+    }
+
+    #[test]
+    fn draw_sprite_bottom_right_corner() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 63, 31, &[0xff, 0xff]);
+        assert_eq!(lit_pixels(&emu), vec![(63, 31)]);
     }
 
     #[test]
     fn load_from_memory() {
-        unimplemented!();
+        let mut emu = Chip8::new();
+        emu.memory[0x300..0x306].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        emu.index_register = 0x300;
+        emu.load(&[0xf5, 0x65]).unwrap(); // Load V0..V5 from memory at I
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0..6], [1, 2, 3, 4, 5, 6]);
+        assert_eq!(emu.registers[6], 0);
     }
 
     #[test]
     fn load_to_memory() {
-        unimplemented!();
+        let mut emu = Chip8::new();
+        emu.registers[0..6].copy_from_slice(&[1, 2, 3, 4, 5, 6]);
+        emu.registers[6] = 7;
+        emu.index_register = 0x300;
+        emu.load(&[0xf5, 0x55]).unwrap(); // Store V0..V5 into memory at I
+        emu.step().unwrap();
+        assert_eq!(emu.memory[0x300..0x307], [1, 2, 3, 4, 5, 6, 0]);
+    }
+
+    #[test]
+    fn store_and_load_round_trip() {
+        // Store V0..V5 at 0x300, clear them, then load them back from 0x300
+        let mut program = vec![0xa3, 0x00, 0xf5, 0x55];
+        for reg in 0..6 {
+            program.extend_from_slice(&[0x60 | reg, 0x00]);
+        }
+        program.extend_from_slice(&[0xa3, 0x00, 0xf5, 0x65]);
+        for (quirks, index) in [(Quirks::default(), 0x300), (Quirks::cosmac_vip(), 0x306)] {
+            let mut emu = Chip8::with_quirks(quirks);
+            emu.load(&program).unwrap();
+            emu.registers[0..6].copy_from_slice(&[0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+            emu.step().unwrap();
+            emu.step().unwrap();
+            assert_eq!(emu.memory[0x300..0x307], [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0]);
+            assert_eq!(emu.index_register, index);
+            for _ in 0..6 {
+                emu.step().unwrap();
+            }
+            assert_eq!(emu.registers[0..6], [0; 6]);
+            emu.step().unwrap();
+            emu.step().unwrap();
+            assert_eq!(emu.registers[0..6], [0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+            assert_eq!(emu.index_register, index);
+        }
+    }
+
+    #[test]
+    fn skip_key_uses_the_low_nibble() {
+        // EX9E then EXA1 on V0, each followed by a skippable instruction
+        let program = [0xe0, 0x9e, 0x00, 0x00, 0xe0, 0xa1, 0x00, 0x00];
+        for value in [0x10, 0xff] {
+            for pressed in [false, true] {
+                let mut emu = Chip8::new();
+                emu.load(&program).unwrap();
+                emu.registers[0] = value;
+                if pressed {
+                    emu.press_key(value & 0xf);
+                }
+                emu.step().unwrap();
+                assert_eq!(emu.program_counter, if pressed { 0x204 } else { 0x202 }, "EX9E {:#x}", value);
+                emu.program_counter = 0x204;
+                emu.step().unwrap();
+                assert_eq!(emu.program_counter, if pressed { 0x206 } else { 0x208 }, "EXA1 {:#x}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn wait_for_key_completes_on_release() {
+        let mut emu = Chip8::new();
+        emu.load(&[0xf3, 0x0a]).unwrap(); // Wait for a key, store it in V3
+        emu.step().unwrap();
+        assert!(emu.is_waiting_for_key());
+        emu.press_key(0x7);
+        emu.step().unwrap();
+        assert!(emu.is_waiting_for_key());
+        assert_eq!(emu.program_counter, 0x200);
+        emu.unpress_key(0x7);
+        emu.step().unwrap();
+        assert!(!emu.is_waiting_for_key());
+        assert_eq!(emu.registers[3], 0x7);
+        assert_eq!(emu.program_counter, 0x202);
+    }
+
+    #[test]
+    fn wait_for_key_ignores_key_held_at_entry() {
+        let mut emu = Chip8::new();
+        emu.load(&[0xf3, 0x0a]).unwrap();
+        emu.press_key(0x5);
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert!(emu.is_waiting_for_key());
+        // Releasing the key that was already held doesn't satisfy the wait
+        emu.unpress_key(0x5);
+        emu.step().unwrap();
+        assert!(emu.is_waiting_for_key());
+        assert_eq!(emu.program_counter, 0x200);
+        emu.press_key(0x5);
+        emu.unpress_key(0x5);
+        emu.step().unwrap();
+        assert!(!emu.is_waiting_for_key());
+        assert_eq!(emu.registers[3], 0x5);
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x00, 0xe0, 0xf0, 0xff]).unwrap();
+        emu.step().unwrap();
+        let err = emu.step().unwrap_err();
+        assert_eq!(err, Chip8Error::UnknownOpcode { opcode: 0xf0ff, pc: 0x202, history: history(&[0x200, 0x202]) });
+        assert_eq!(err.to_string(), "unknown opcode F0FF at 202, recent program counters: 200 202");
+    }
+
+    #[test]
+    fn return_on_empty_stack() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x61, 0x2a, 0x00, 0xee]).unwrap(); // V1 = 0x2A, then return
+        emu.step().unwrap();
+        let err = emu.step().unwrap_err();
+        assert_eq!(err, Chip8Error::StackUnderflow { pc: 0x202, history: history(&[0x200, 0x202]) });
+        assert_eq!(err.to_string(), "return from subroutine with an empty stack at 202, recent program counters: 200 202");
+        // The machine is left as it was before the failing instruction
+        assert_eq!(emu.program_counter, 0x202);
+        assert_eq!(emu.registers[1], 0x2a);
+        assert!(emu.stack().is_empty());
+    }
+
+    #[test]
+    fn shift_source_follows_config() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x81, 0x26]).unwrap(); // V1 = V1 >> 1
+        emu.registers[1] = 0b1000;
+        emu.registers[2] = 0b0011;
+        emu.step().unwrap();
+        assert_eq!(emu.registers[1], 0b0100);
+        assert_eq!(emu.registers[0xf], 0);
+
+        let mut emu = Chip8::with_quirks(Quirks { shift_uses_vy: true, ..Quirks::default() });
+        emu.load(&[0x81, 0x26]).unwrap(); // V1 = V2 >> 1
+        emu.registers[1] = 0b1000;
+        emu.registers[2] = 0b0011;
+        emu.step().unwrap();
+        assert_eq!(emu.registers[1], 0b0001);
+        assert_eq!(emu.registers[0xf], 1);
+    }
+
+    #[test]
+    fn set_quirks_at_runtime() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x81, 0x26, 0x81, 0x26]).unwrap(); // V1 >>= 1 twice
+        emu.registers[1] = 0b1000;
+        emu.registers[2] = 0b0100;
+        emu.step().unwrap();
+        assert_eq!(emu.registers[1], 0b0100);
+        emu.set_quirks(Quirks { shift_uses_vy: true, ..*emu.quirks() });
+        emu.step().unwrap();
+        assert_eq!(emu.registers[1], 0b0010);
+        assert!(emu.quirks().shift_uses_vy);
+    }
+
+    #[test]
+    fn shift_left_source_follows_config() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x81, 0x2e]).unwrap(); // V1 = V1 << 1
+        emu.registers[1] = 0b0100_0001;
+        emu.registers[2] = 0b1000_0011;
+        emu.step().unwrap();
+        assert_eq!(emu.registers[1], 0b1000_0010);
+        assert_eq!(emu.registers[2], 0b1000_0011);
+        assert_eq!(emu.registers[0xf], 0);
+
+        let mut emu = Chip8::with_quirks(Quirks { shift_uses_vy: true, ..Quirks::default() });
+        emu.load(&[0x81, 0x2e]).unwrap(); // V1 = V2 << 1
+        emu.registers[1] = 0b0100_0001;
+        emu.registers[2] = 0b1000_0011;
+        emu.step().unwrap();
+        assert_eq!(emu.registers[1], 0b0000_0110);
+        assert_eq!(emu.registers[2], 0b1000_0011);
+        assert_eq!(emu.registers[0xf], 1);
+    }
+
+    #[test]
+    fn jump_with_offset_follows_config() {
+        let mut emu = Chip8::new();
+        emu.load(&[0xb3, 0x00]).unwrap(); // Jump to 0x300 + V0
+        emu.registers[0] = 0x10;
+        emu.registers[3] = 0x20;
+        emu.step().unwrap();
+        assert_eq!(emu.program_counter, 0x310);
+
+        let mut emu = Chip8::with_quirks(Quirks { jump_with_offset_uses_vx: true, ..Quirks::default() });
+        emu.load(&[0xb3, 0x00]).unwrap(); // Jump to 0x300 + V3
+        emu.registers[0] = 0x10;
+        emu.registers[3] = 0x20;
+        emu.step().unwrap();
+        assert_eq!(emu.program_counter, 0x320);
+    }
+
+    #[test]
+    fn jump_with_offset_past_end_of_memory() {
+        let mut emu = Chip8::new();
+        emu.load(&[0xbf, 0xff]).unwrap(); // Jump to 0xFFF + V0
+        emu.registers[0] = 0xff;
+        emu.registers[0xf] = 0x01;
+        emu.step().unwrap();
+        assert_eq!(emu.program_counter, 0x0fe);
+
+        let mut emu = Chip8::with_quirks(Quirks { jump_with_offset_uses_vx: true, ..Quirks::default() });
+        emu.load(&[0xbf, 0xff]).unwrap(); // Jump to 0xFFF + VF
+        emu.registers[0] = 0xff;
+        emu.registers[0xf] = 0x01;
+        emu.step().unwrap();
+        assert_eq!(emu.program_counter, 0x000);
+    }
+
+    #[test]
+    fn logic_vf_reset_follows_config() {
+        assert!(!Quirks::default().logic_resets_vf);
+        // (opcode, expected V1)
+        let cases = [(0x8121, 0b0111), (0x8122, 0b0001), (0x8123, 0b0110)];
+        for logic_resets_vf in [false, true] {
+            for (opcode, result) in cases {
+                let mut emu = Chip8::with_quirks(Quirks { logic_resets_vf, ..Quirks::default() });
+                emu.load(&u16::to_be_bytes(opcode)).unwrap();
+                emu.registers[1] = 0b0101;
+                emu.registers[2] = 0b0011;
+                emu.registers[0xf] = 1;
+                emu.step().unwrap();
+                assert_eq!(emu.registers[1], result, "opcode {:04X}", opcode);
+                assert_eq!(emu.registers[0xf], if logic_resets_vf { 0 } else { 1 }, "opcode {:04X}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn logic_vf_reset_with_vf_as_destination() {
+        for logic_resets_vf in [false, true] {
+            let mut emu = Chip8::with_quirks(Quirks { logic_resets_vf, ..Quirks::default() });
+            emu.load(&[0x8f, 0x11]).unwrap(); // VF = VF | V1
+            emu.registers[1] = 0b0100;
+            emu.registers[0xf] = 0b0001;
+            emu.step().unwrap();
+            assert_eq!(emu.registers[0xf], if logic_resets_vf { 0 } else { 0b0101 });
+        }
+    }
+
+    #[test]
+    fn sprite_wrapping_follows_config() {
+        let mut emu = Chip8::with_quirks(Quirks { sprite_wrapping: true, ..Quirks::default() });
+        draw_at(&mut emu, 62, 31, &[0xf0, 0x80]);
+        assert_eq!(lit_pixels(&emu), vec![(62, 0), (0, 31), (1, 31), (62, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn hires_sprite_wrapping_follows_config() {
+        for wrapping in [false, true] {
+            let mut emu = Chip8::with_quirks(Quirks { sprite_wrapping: wrapping, ..Quirks::default() });
+            emu.set_hires(true);
+            draw_at(&mut emu, 126, 0, &[0xf0]);
+            let expected = if wrapping { vec![(0, 0), (1, 0), (126, 0), (127, 0)] } else { vec![(126, 0), (127, 0)] };
+            assert_eq!(lit_pixels(&emu), expected);
+        }
+    }
+
+    #[test]
+    fn timers_start_at_zero() {
+        let mut emu = Chip8::new();
+        assert_eq!(emu.delay_timer, 0);
+        assert_eq!(emu.sound_timer, 0);
+        emu.tick_timers();
+        assert_eq!(emu.delay_timer, 0);
+        assert_eq!(emu.sound_timer, 0);
+
+        emu.delay_timer = 5;
+        emu.reset();
+        assert_eq!(emu.delay_timer, 0);
+    }
+
+    #[test]
+    fn index_register_out_of_bounds() {
+        for opcode in [0xf233u16, 0xf255, 0xf265, 0xd013] {
+            let mut emu = Chip8::new();
+            emu.load(&opcode.to_be_bytes()).unwrap();
+            emu.index_register = 0xffe;
+            assert_eq!(emu.step(), Err(Chip8Error::MemoryOutOfBounds { opcode, pc: 0x200, address: 0x1000, history: history(&[0x200]) }));
+        }
+    }
+
+    #[test]
+    fn index_register_at_end_of_memory() {
+        let mut emu = Chip8::new();
+        emu.load(&[0xf1, 0x55]).unwrap(); // Store V0 and V1 in the last two bytes
+        emu.registers[0] = 0xab;
+        emu.registers[1] = 0xcd;
+        emu.index_register = 0xffe;
+        emu.step().unwrap();
+        assert_eq!(emu.memory[0xffe..], [0xab, 0xcd]);
+    }
+
+    #[test]
+    fn load_store_index_follows_config() {
+        // Store V0..V2 at I, clear them, then load them back from wherever I was left
+        let program = [
+            0xa3, 0x00, 0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xf2, 0x55,
+            0x60, 0x00, 0x61, 0x00, 0x62, 0x00, 0xf2, 0x65
+        ];
+        let cases = [
+            (MemoryIncrement::Unchanged, [1, 2, 3], 0x300),
+            (MemoryIncrement::XPlusOne, [0, 0, 0], 0x306),
+            (MemoryIncrement::X, [3, 0, 0], 0x304),
+        ];
+        for (memory_increment, registers, index) in cases {
+            let mut emu = Chip8::with_quirks(Quirks { memory_increment, ..Quirks::default() });
+            emu.load(&program).unwrap();
+            for _ in 0..9 {
+                emu.step().unwrap();
+            }
+            assert_eq!(emu.memory[0x300..0x304], [1, 2, 3, 0], "{:?}", memory_increment);
+            assert_eq!(emu.registers[0..3], registers, "{:?}", memory_increment);
+            assert_eq!(emu.index_register, index, "{:?}", memory_increment);
+        }
+    }
+
+    #[test]
+    fn register_accessors() {
+        let mut emu = Chip8::new();
+        emu.set_register(0x3, 0x2a);
+        emu.set_register(0x10, 0xff); // Not a register, ignored
+        assert_eq!(emu.register(0x3), 0x2a);
+        assert_eq!(emu.register(0x10), 0);
+        assert_eq!(emu.registers, [0, 0, 0, 0x2a, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        emu.load(&[0xa1, 0x23, 0x12, 0x00]).unwrap(); // I = 0x123, jump to 0x200
+        emu.set_pc(0x202);
+        emu.step().unwrap();
+        assert_eq!(emu.pc(), 0x200);
+        emu.step().unwrap();
+        assert_eq!(emu.index(), 0x123);
+    }
+
+    #[test]
+    fn reset_keeps_program() {
+        let rom = include_bytes!("../../roms/IBM Logo.ch8");
+        let mut emu = Chip8::new();
+        emu.load(rom).unwrap();
+        for _ in 0..300 {
+            emu.step().unwrap();
+        }
+        emu.memory[0x300] = 0xff;
+        emu.reset();
+        assert_eq!(emu.program_counter, 0x200);
+        assert_eq!(emu.memory[0x200..0x200 + rom.len()], rom[..]);
+        assert_eq!(emu.memory[0x300], 0);
+        assert_eq!(emu.memory[0x50..0x55], [0xf0, 0x90, 0x90, 0x90, 0xf0]);
+        assert_eq!(emu.plane_rows, [0; 2 * HIRES_HEIGHT]);
+    }
+
+    #[test]
+    fn memory_accessors() {
+        let mut emu = Chip8::new();
+        assert_eq!(emu.write_memory(0x200, 0x2a), Ok(()));
+        assert_eq!(emu.read_memory(0x200), Some(0x2a));
+        assert_eq!(emu.read_memory(0xfff), Some(0));
+        assert_eq!(emu.read_memory(5000), None);
+        assert_eq!(emu.write_memory(5000, 0xff), Err(MemError::OutOfBounds { addr: 5000 }));
+
+        assert_eq!(emu.read_memory_range(0x50, 5), Some(&[0xf0, 0x90, 0x90, 0x90, 0xf0][..]));
+        assert_eq!(emu.read_memory_range(0xffe, 2), Some(&[0, 0][..]));
+        assert_eq!(emu.read_memory_range(0xffe, 4), None);
+        assert_eq!(emu.read_memory_range(5000, 10), None);
+    }
+
+    #[test]
+    fn program_counter_out_of_bounds() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x61, 0x2a]).unwrap(); // V1 = 0x2A, then falls through the empty memory
+        let err = loop {
+            if let Err(err) = emu.step() {
+                break err;
+            }
+        };
+        assert_eq!(err, Chip8Error::PcOutOfBounds { pc: 0x1000, history: history(&(0xfc0..0x1000).step_by(2).collect::<Vec<_>>()) });
+        assert_eq!(emu.registers[1], 0x2a);
+
+        emu.program_counter = 0xfff;
+        assert_eq!(emu.step(), Err(Chip8Error::PcOutOfBounds { pc: 0xfff, history: *err.history() }));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x22, 0x00]).unwrap(); // Calls itself forever
+        for _ in 0..16 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.stack_depth(), 16);
+        assert_eq!(emu.step(), Err(Chip8Error::StackOverflow { pc: 0x200, history: history(&[0x200; 17]) }));
+        assert_eq!(emu.stack_depth(), 16);
+    }
+
+    #[test]
+    fn stack_limit_follows_config() {
+        let mut emu = Chip8::with_config(Chip8Config { stack_limit: 4, ..Chip8Config::default() });
+        emu.load(&[0x22, 0x00]).unwrap();
+        for _ in 0..4 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.step(), Err(Chip8Error::StackOverflow { pc: 0x200, history: history(&[0x200; 5]) }));
+        assert_eq!(emu.stack_depth(), 4);
+
+        // The stack can't grow past its fixed size
+        let mut emu = Chip8::with_config(Chip8Config { stack_limit: 32, ..Chip8Config::default() });
+        emu.load(&[0x22, 0x00]).unwrap();
+        for _ in 0..STACK_SIZE {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.step(), Err(Chip8Error::StackOverflow { pc: 0x200, history: history(&[0x200; STACK_SIZE + 1]) }));
+        assert_eq!(emu.stack_depth(), STACK_SIZE);
+    }
+
+    #[test]
+    fn executes_decoded_instructions() {
+        let mut emu = Chip8::new();
+        emu.set_pc(0x300);
+        emu.set_register(3, 0xf0);
+        assert_eq!(emu.execute(Instruction::AddImm { x: 3, nn: 0x20 }), Ok(StepOutcome::Executed));
+        assert_eq!(emu.register(3), 0x10); // No carry for 7XNN
+        assert_eq!(emu.register(0xf), 0);
+        assert_eq!(emu.pc(), 0x302);
+
+        emu.set_register(4, 0xf0);
+        emu.execute(Instruction::AddReg { x: 3, y: 4 }).unwrap();
+        assert_eq!((emu.register(3), emu.register(0xf)), (0x00, 1));
+
+        emu.execute(Instruction::LoadIndex(0x50)).unwrap();
+        emu.execute(Instruction::Draw { x: 0, y: 0, n: 5 }).unwrap();
+        assert_eq!(emu.get_row(0), 0b1111);
+        assert_eq!(emu.cycles(), 4);
+    }
+
+    #[test]
+    fn executes_calls_and_returns() {
+        let mut emu = Chip8::new();
+        emu.set_pc(0x200);
+        emu.execute(Instruction::Call(0x400)).unwrap();
+        assert_eq!((emu.pc(), emu.stack()), (0x400, &[0x202][..]));
+        emu.execute(Instruction::Return).unwrap();
+        assert_eq!(emu.pc(), 0x202);
+        assert!(emu.stack().is_empty());
+
+        // A failing instruction leaves the machine on it
+        assert_eq!(emu.execute(Instruction::Return), Err(Chip8Error::StackUnderflow { pc: 0x202, history: history(&[0x200, 0x400, 0x202]) }));
+        assert_eq!(emu.pc(), 0x202);
+        assert_eq!(emu.cycles(), 2);
+    }
+
+    #[test]
+    fn execute_checks_the_variant() {
+        let mut emu = Chip8::new();
+        emu.set_pc(0x200);
+        assert_eq!(
+            emu.execute(Instruction::SelectPlanes(2)),
+            Err(Chip8Error::UnknownOpcode { opcode: 0xf201, pc: 0x200, history: history(&[0x200]) })
+        );
+        assert_eq!(
+            emu.execute(Instruction::Unknown(0xe000)),
+            Err(Chip8Error::UnknownOpcode { opcode: 0xe000, pc: 0x200, history: history(&[0x200, 0x200]) })
+        );
+        // 00DN is a machine code call outside XO-CHIP, which is ignored
+        assert_eq!(emu.execute(Instruction::ScrollUp(4)), Ok(StepOutcome::Executed));
+
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.execute(Instruction::SelectPlanes(2)).unwrap();
+
+        use Instruction::*;
+        let superchip = [
+            ScrollDown(2), ScrollRight, ScrollLeft, LowRes, HighRes, LoadBigFont { x: 0 },
+            StoreFlags { x: 1 }, LoadFlags { x: 1 }, Exit,
+        ];
+        for variant in [Variant::Chip8, Variant::Chip48] {
+            let mut emu = Chip8::with_variant(variant);
+            for instruction in superchip {
+                let opcode = instruction.opcode();
+                assert!(
+                    matches!(emu.execute(instruction), Err(Chip8Error::UnknownOpcode { opcode: unknown, .. }) if unknown == opcode),
+                    "{:?} {}", variant, instruction
+                );
+            }
+        }
+        for variant in [Variant::Modern, Variant::SuperChip, Variant::XoChip] {
+            let mut emu = Chip8::with_variant(variant);
+            for instruction in superchip {
+                assert!(emu.execute(instruction).is_ok(), "{:?} {}", variant, instruction);
+            }
+            assert!(emu.is_halted());
+        }
+    }
+
+    #[test]
+    fn fetch_reads_the_next_opcode() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x12, 0x34]).unwrap();
+        assert_eq!(emu.fetch(), Ok(0x1234));
+        assert_eq!(decode(emu.fetch().unwrap()), Instruction::Jump(0x234));
+        emu.set_pc(emu.memory_size() as u16 - 1);
+        assert_eq!(emu.fetch(), Err(Chip8Error::PcOutOfBounds { pc: 0xfff, history: history(&[]) }));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn snapshot_round_trip() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x70, 0x01, 0x12, 0x00]).unwrap(); // Count up in V0 forever
+        for _ in 0..100 {
+            emu.step().unwrap();
+        }
+        let snapshot = emu.snapshot();
+        for _ in 0..100 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.registers[0], 100);
+        emu.restore(&snapshot).unwrap();
+        assert_eq!(emu.registers[0], 50);
+        assert_eq!(emu.program_counter, 0x200);
+        assert!(emu.restore(&snapshot[..10]).is_err());
+        assert_eq!(emu.registers[0], 50);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn restore_checks_the_snapshot() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        emu.step().unwrap();
+        let corrupt = |change: fn(&mut Chip8)| {
+            let mut bad = Chip8::new();
+            change(&mut bad);
+            bincode::serialize(&bad).unwrap()
+        };
+
+        for snapshot in [
+            corrupt(|bad| {
+                bad.config.stack_limit = 2;
+                bad.stack = Stack::from_slice(&[0x200; 3]).unwrap();
+            }),
+            corrupt(|bad| bad.memory.truncate(0x800)),
+            corrupt(|bad| bad.config.extended_memory = true),
+            corrupt(|bad| bad.plane_rows[0] = 1 << 100),
+            corrupt(|bad| bad.selected_planes = 4),
+            corrupt(|bad| bad.key_pressed_while_waiting = Some(16)),
+        ] {
+            assert!(emu.restore(&snapshot).is_err());
+            assert_eq!(emu.registers[0], 1);
+        }
+
+        // The display is read from the restored planes
+        let mut drawn = Chip8::new();
+        drawn.plane_rows[3] = 1 << 5;
+        emu.restore(&bincode::serialize(&drawn).unwrap()).unwrap();
+        assert!(emu.get_display()[5 + 3 * SCREEN_WIDTH]);
+        assert_eq!(emu.get_display_indexed()[5 + 3 * SCREEN_WIDTH], 1);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        let mut emu = Chip8::with_rng(7);
+        emu.load(include_bytes!("../../roms/Stars [Sergey Naydenov, 2010].ch8")).unwrap();
+        emu.press_key(0x5);
+        for step in 0..5000 {
+            emu.step().unwrap();
+            if step % HEADLESS_STEPS_PER_FRAME == 0 {
+                emu.tick_timers();
+            }
+        }
+        emu.set_rpl_flags(&[1, 2, 3]);
+
+        let json = serde_json::to_string(&emu).unwrap();
+        let from_json: Chip8 = serde_json::from_str(&json).unwrap();
+        assert_eq!(from_json, emu);
+
+        let bytes = bincode::serialize(&emu).unwrap();
+        let mut from_bincode: Chip8 = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(from_bincode, emu);
+
+        // Both keep running the same way
+        for _ in 0..100 {
+            emu.step().unwrap();
+            from_bincode.step().unwrap();
+        }
+        assert_eq!(from_bincode, emu);
+        assert_ne!(from_bincode, from_json);
+    }
+
+    #[test]
+    fn seeded_random() {
+        let mut emu = Chip8::with_rng(42);
+        emu.load(&[0xc0, 0xff, 0xc1, 0xff, 0xc2, 0x0f]).unwrap();
+        for _ in 0..3 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.registers[0..3], [0x56, 0xc8, 0x0a]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "rand"))]
+    fn fixed_seed_without_rand() {
+        let run = || {
+            let mut emu = Chip8::new();
+            emu.load(&[0xc0, 0xff, 0xc1, 0xff, 0xc2, 0xff]).unwrap();
+            emu.step_n(3).unwrap();
+            emu.registers
+        };
+        let registers = run();
+        assert_eq!(registers, run());
+        assert_ne!(registers[0..3], [0, 0, 0]);
+        assert_ne!(registers[0], registers[1]);
+    }
+
+    #[test]
+    fn sound_timer_beeps() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut emu = Chip8::new();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded = events.clone();
+        emu.set_sound_callback(Box::new(move |beeping| recorded.borrow_mut().push(beeping)));
+        emu.load(&[0x60, 0x03, 0xf0, 0x18]).unwrap();
+        assert!(!emu.is_beeping());
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert!(emu.is_beeping());
+        emu.tick_timers();
+        emu.tick_timers();
+        assert!(emu.is_beeping());
+        emu.tick_timers();
+        assert!(!emu.is_beeping());
+        emu.tick_timers();
+        assert_eq!(*events.borrow(), [true, false]);
+    }
+
+    #[test]
+    fn display_wait_limits_draws_per_frame() {
+        let mut emu = Chip8::with_quirks(Quirks { display_wait: true, ..Quirks::default() });
+        emu.load(&[0xd0, 0x01, 0xd0, 0x01]).unwrap(); // Two back-to-back draws
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.step(), Ok(StepOutcome::WaitingForVblank));
+        assert_eq!(emu.program_counter, 0x202);
+        assert_eq!(emu.step(), Ok(StepOutcome::WaitingForVblank));
+        emu.tick_timers();
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.program_counter, 0x204);
+
+        let mut emu = Chip8::new();
+        emu.load(&[0xd0, 0x01, 0xd0, 0x01]).unwrap();
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+    }
+
+    #[test]
+    fn end_of_frame_releases_display_wait() {
+        let mut emu = Chip8::with_quirks(Quirks { display_wait: true, ..Quirks::default() });
+        emu.load(&[0xd0, 0x01, 0xd0, 0x01]).unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.step(), Ok(StepOutcome::WaitingForVblank));
+        emu.end_of_frame();
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+    }
+
+    #[test]
+    fn index_overflow_follows_config() {
+        for index_overflow_sets_vf in [false, true] {
+            let mut emu = Chip8::with_quirks(Quirks { index_overflow_sets_vf, ..Quirks::default() });
+            emu.load(&[0xf1, 0x1e, 0xf1, 0x1e]).unwrap(); // I = I + V1, twice
+            emu.index_register = 0xf80;
+            emu.registers[1] = 0x40;
+            emu.registers[0xf] = 0x05;
+            emu.step().unwrap();
+            assert_eq!(emu.index_register, 0xfc0);
+            assert_eq!(emu.registers[0xf], if index_overflow_sets_vf { 0 } else { 0x05 });
+            emu.step().unwrap();
+            assert_eq!(emu.index_register, 0x000);
+            assert_eq!(emu.registers[0xf], if index_overflow_sets_vf { 1 } else { 0x05 });
+        }
+    }
+
+    #[test]
+    fn headless_ibm_logo() {
+        let display = run_headless(include_bytes!("../../roms/IBM Logo.ch8"), 1000).unwrap();
+        for (x, y) in [(12, 8), (19, 8), (21, 8), (14, 12), (42, 22), (51, 22)] {
+            assert!(display[x + y * SCREEN_WIDTH], "({}, {}) should be lit", x, y);
+        }
+        for (x, y) in [(0, 0), (20, 8), (12, 9), (42, 21), (63, 31)] {
+            assert!(!display[x + y * SCREEN_WIDTH], "({}, {}) should be clear", x, y);
+        }
+        assert_eq!(run_headless(&[], 10), Err(LoadError::Empty));
+    }
+
+    #[test]
+    fn render_ascii_font_zero() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x00, 0xe0, 0xa0, 0x50, 0xd0, 0x05]).unwrap(); // Clear, I = font 0, draw it at (0, 0)
+        for _ in 0..3 {
+            emu.step().unwrap();
+        }
+        let text = emu.render_ascii('█', ' ');
+        let rows: Vec<&str> = text.split('\n').collect();
+        assert_eq!(rows.len(), SCREEN_HEIGHT);
+        assert!(rows.iter().all(|row| row.chars().count() == SCREEN_WIDTH));
+        let glyph = ["████", "█  █", "█  █", "█  █", "████"];
+        for (row, expected) in rows.iter().zip(glyph) {
+            assert_eq!(row.trim_end(), expected);
+        }
+        assert!(rows[5..].iter().all(|row| row.trim().is_empty()));
+    }
+
+    #[test]
+    fn render_ascii_follows_the_resolution() {
+        let mut emu = Chip8::new();
+        let text = emu.render_ascii('#', '.');
+        assert_eq!(text.len(), (SCREEN_WIDTH + 1) * SCREEN_HEIGHT - 1);
+        assert!(text.lines().all(|line| line.len() == SCREEN_WIDTH));
+
+        emu.set_hires(true);
+        emu.set_pixel(127, 63, true);
+        let text = emu.render_ascii('#', '.');
+        assert_eq!(text.lines().count(), HIRES_HEIGHT);
+        assert!(text.lines().all(|line| line.len() == HIRES_WIDTH));
+        assert!(text.ends_with(".#"));
+    }
+
+    #[test]
+    fn variant_presets() {
+        let emu = Chip8::new();
+        assert_eq!(emu.variant(), Variant::Modern);
+        assert_eq!(emu.config, Variant::Modern.config());
+        assert_eq!(emu.config.stack_limit, 16);
+        assert_eq!(*emu.quirks(), Quirks::default());
+
+        let emu = Chip8::with_variant(Variant::Chip8);
+        assert_eq!(emu.variant(), Variant::Chip8);
+        assert_eq!(emu.config.stack_limit, 12);
+        assert_eq!(*emu.quirks(), Quirks {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_with_offset_uses_vx: false,
+            logic_resets_vf: true,
+            sprite_wrapping: false,
+            display_wait: true,
+            index_overflow_sets_vf: false,
+        });
+
+        let emu = Chip8::with_variant(Variant::Chip48);
+        assert_eq!(emu.variant(), Variant::Chip48);
+        assert_eq!(*emu.quirks(), Quirks {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::X,
+            jump_with_offset_uses_vx: true,
+            logic_resets_vf: false,
+            sprite_wrapping: false,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        });
+
+        let emu = Chip8::with_variant(Variant::SuperChip);
+        assert_eq!(emu.variant(), Variant::SuperChip);
+        assert_eq!(*emu.quirks(), Quirks {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::Unchanged,
+            jump_with_offset_uses_vx: true,
+            logic_resets_vf: false,
+            sprite_wrapping: false,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        });
+
+        let emu = Chip8::with_variant(Variant::XoChip);
+        assert_eq!(emu.variant(), Variant::XoChip);
+        assert_eq!(*emu.quirks(), Quirks {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_with_offset_uses_vx: false,
+            logic_resets_vf: false,
+            sprite_wrapping: true,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        });
+    }
+
+    #[test]
+    fn variant_with_override() {
+        let config = Chip8Config {
+            quirks: Quirks { display_wait: true, ..Variant::SuperChip.quirks() },
+            ..Variant::SuperChip.config()
+        };
+        let emu = Chip8::with_config(config);
+        assert_eq!(emu.variant(), Variant::SuperChip);
+        assert!(emu.quirks().display_wait);
+        assert!(emu.quirks().jump_with_offset_uses_vx);
+        assert_eq!(emu.quirks().memory_increment, MemoryIncrement::Unchanged);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn write_ppm_pixels() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 1, 0, &[0x80]);
+        let mut ppm = Vec::new();
+        emu.write_ppm(&mut ppm).unwrap();
+        let header = b"P6\n64 32\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        let pixels = &ppm[header.len()..];
+        assert_eq!(pixels.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+        assert_eq!(pixels[0..3], [0x00; 3]);
+        assert_eq!(pixels[3..6], [0xff; 3]);
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn to_image_pixels() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 1, 0, &[0x80]);
+        let image = emu.to_image(3, 0x3a3b3c, 0xb0b3b8);
+        assert_eq!(image.dimensions(), (192, 96));
+        assert_eq!(image.get_pixel(0, 0).0, [0xb0, 0xb3, 0xb8]);
+        assert_eq!(image.get_pixel(3, 0).0, [0x3a, 0x3b, 0x3c]);
+        assert_eq!(image.get_pixel(5, 2).0, [0x3a, 0x3b, 0x3c]);
+        assert_eq!(image.get_pixel(6, 0).0, [0xb0, 0xb3, 0xb8]);
+    }
+
+    #[test]
+    fn quirks_from_names() {
+        let baseline = Quirks::from_str_flags(&[]).unwrap();
+        assert_eq!(baseline, Quirks {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::XPlusOne,
+            jump_with_offset_uses_vx: false,
+            logic_resets_vf: false,
+            sprite_wrapping: true,
+            display_wait: false,
+            index_overflow_sets_vf: false,
+        });
+        let cases = [
+            ("shift", Quirks { shift_uses_vy: false, ..baseline }),
+            ("memoryIncrementByX", Quirks { memory_increment: MemoryIncrement::X, ..baseline }),
+            ("memoryLeaveIUnchanged", Quirks { memory_increment: MemoryIncrement::Unchanged, ..baseline }),
+            ("jump0", Quirks { jump_with_offset_uses_vx: true, ..baseline }),
+            ("logic", Quirks { logic_resets_vf: true, ..baseline }),
+            ("clip", Quirks { sprite_wrapping: false, ..baseline }),
+            ("vblank", Quirks { display_wait: true, ..baseline }),
+            ("indexOverflow", Quirks { index_overflow_sets_vf: true, ..baseline }),
+        ];
+        for (name, quirks) in cases {
+            assert_eq!(Quirks::from_str_flags(&[name]), Ok(quirks), "{}", name);
+            assert_eq!(Quirks::from_str_flags(&[&name.to_uppercase()]), Ok(quirks), "{}", name);
+            assert_eq!(quirks.to_string(), name);
+        }
+        assert_eq!(Quirks::from_str_flags(&["shift", "CLIP"]), Ok(Quirks { shift_uses_vy: false, sprite_wrapping: false, ..baseline }));
+    }
+
+    #[test]
+    fn quirks_parse_round_trip() {
+        assert_eq!("shift, clip vblank".parse(), Ok(Quirks { shift_uses_vy: false, sprite_wrapping: false, display_wait: true, ..Quirks::from_str_flags(&[]).unwrap() }));
+        for quirks in [Quirks::default(), Quirks::cosmac_vip(), Quirks::chip48(), Quirks::superchip(), Quirks::xochip()] {
+            assert_eq!(quirks.to_string().parse(), Ok(quirks));
+        }
+        assert_eq!(Quirks::superchip().to_string(), "shift,memoryLeaveIUnchanged,jump0,clip");
+    }
+
+    #[test]
+    fn quirks_unknown_name() {
+        let err = "shift,wrap".parse::<Quirks>().unwrap_err();
+        assert_eq!(err, QuirkParseError { name: "wrap".to_string() });
+        assert_eq!(err.to_string(), "unknown quirk \"wrap\", expected one of: shift, memoryIncrementByX, \
+            memoryLeaveIUnchanged, jump0, logic, clip, vblank, indexOverflow");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn load_from_reader() {
+        let mut emu = Chip8::new();
+        let mut rom: &[u8] = &[0x00, 0xe0, 0x12, 0x00];
+        emu.load_from_reader(&mut rom).unwrap();
+        assert_eq!(emu.memory[0x200..0x204], [0x00, 0xe0, 0x12, 0x00]);
+
+        let err = emu.load_from_reader(&mut io::empty()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), LoadError::Empty.to_string());
+
+        let mut rom = io::repeat(0).take(MEMORY_SIZE as u64);
+        let err = emu.load_from_reader(&mut rom).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn hires_mode_toggles() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x00, 0xff, 0x00, 0xfe]).unwrap(); // Enter hires, then return to lores
+        assert_eq!((emu.display_width(), emu.display_height()), (64, 32));
+        assert_eq!(emu.get_display().len(), 64 * 32);
+
+        light(&mut emu, 5, 0, 1);
+        emu.step().unwrap();
+        assert!(emu.is_hires());
+        assert_eq!((emu.display_width(), emu.display_height()), (128, 64));
+        assert_eq!(emu.get_display().len(), 128 * 64);
+        assert!(lit_pixels(&emu).is_empty());
+
+        light(&mut emu, 5, 0, 1);
+        emu.step().unwrap();
+        assert!(!emu.is_hires());
+        assert_eq!(emu.get_display().len(), 64 * 32);
+        assert!(lit_pixels(&emu).is_empty());
+    }
+
+    #[test]
+    fn hires_draw_clips_at_active_size() {
+        let mut emu = Chip8::new();
+        emu.set_hires(true);
+        draw_at(&mut emu, 124, 63, &[0xff, 0xff]);
+        assert_eq!(lit_pixels(&emu), [(124, 63), (125, 63), (126, 63), (127, 63)]);
+        assert_eq!(emu.render_ascii('#', '.').lines().count(), 64);
+
+        emu.set_hires(false);
+        draw_at(&mut emu, 60, 31, &[0xff, 0xff]);
+        assert_eq!(lit_pixels(&emu), [(60, 31), (61, 31), (62, 31), (63, 31)]);
+    }
+
+    #[test]
+    fn hires_draw_past_lores_width() {
+        let mut emu = Chip8::new();
+        // Enter hires, V0 = 100, V1 = 10, I = font 0, draw it at (V0, V1)
+        emu.load(&[0x00, 0xff, 0x60, 100, 0x61, 10, 0xa0, 0x50, 0xd0, 0x15]).unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.dimensions(), (128, 64));
+        for _ in 0..4 {
+            emu.step().unwrap();
+        }
+        let lit = lit_pixels(&emu);
+        assert_eq!(lit.len(), 14);
+        assert!(lit.contains(&(100, 10)));
+        assert!(lit.contains(&(103, 14)));
+    }
+
+    /// Lights a diagonal line from the top left corner, along with the pixel in the bottom right.
+    fn scroll_pattern(emu: &mut Chip8) {
+        let (width, height) = emu.dimensions();
+        for i in 0..8 {
+            light(emu, i, i, 1);
+        }
+        light(emu, width - 1, height - 1, 1);
+    }
+
+    #[test]
+    fn scroll_down() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x00, 0xc3, 0x00, 0xcf, 0x00, 0xcf, 0x00, 0xcf]).unwrap(); // Down 3, then down 15 three times
+        scroll_pattern(&mut emu);
+        emu.needs_redraw = false;
+        emu.step().unwrap();
+        assert!(emu.needs_redraw);
+        assert_eq!(lit_pixels(&emu), (0..8).map(|i| (i, i + 3)).collect::<Vec<_>>());
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu), (0..8).map(|i| (i, i + 18)).collect::<Vec<_>>());
+        emu.step().unwrap();
+        emu.step().unwrap(); // 48 rows in total, more than the screen height
+        assert!(lit_pixels(&emu).is_empty());
+    }
+
+    #[test]
+    fn scroll_up() {
+        for hires in [false, true] {
+            let mut emu = Chip8::with_variant(Variant::XoChip);
+            emu.set_hires(hires);
+            // Up 0, up 3, then up 15 five times for more than the screen height
+            emu.load(&[0x00, 0xd0, 0x00, 0xd3, 0x00, 0xdf, 0x00, 0xdf, 0x00, 0xdf, 0x00, 0xdf, 0x00, 0xdf]).unwrap();
+            let (width, height) = emu.dimensions();
+            scroll_pattern(&mut emu);
+            light(&mut emu, 5, 0, 2); // Not in the selected plane, stays put
+            let start = emu.get_display_indexed().to_vec();
+
+            emu.needs_redraw = false;
+            emu.step().unwrap();
+            assert!(emu.needs_redraw);
+            assert_eq!(emu.get_display_indexed(), start);
+
+            emu.step().unwrap();
+            let mut expected = vec![0; width * height];
+            for i in 3..8 {
+                expected[i + (i - 3) * width] = 1;
+            }
+            expected[width - 1 + (height - 4) * width] = 1;
+            expected[5] = 2;
+            assert_eq!(emu.get_display_indexed(), expected);
+
+            for _ in 0..5 {
+                emu.step().unwrap();
+            }
+            let mut expected = vec![0; width * height];
+            expected[5] = 2;
+            assert_eq!(emu.get_display_indexed(), expected);
+        }
+
+        // Other variants treat it as a 0NNN machine code call and ignore it
+        let mut emu = Chip8::new();
+        emu.load(&[0x00, 0xd1]).unwrap();
+        scroll_pattern(&mut emu);
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu), (0..8).map(|i| (i, i)).chain([(63, 31)]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn scroll_left_and_right() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x00, 0xfc, 0x00, 0xfb, 0x00, 0xfb, 0x00, 0xfc, 0x00, 0xfc]).unwrap(); // Left 4, right 4 twice, left 4 twice
+        scroll_pattern(&mut emu);
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu), (4..8).map(|i| (i - 4, i)).chain([(59, 31)]).collect::<Vec<_>>());
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu), (4..8).map(|i| (i, i)).chain([(63, 31)]).collect::<Vec<_>>());
+        scroll_pattern(&mut emu);
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu), (0..8).map(|i| (i + 4, i)).collect::<Vec<_>>());
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu), (0..8).map(|i| (i, i)).collect::<Vec<_>>());
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu), (4..8).map(|i| (i - 4, i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn scroll_in_hires() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x00, 0xff, 0x00, 0xc2, 0x00, 0xfc]).unwrap(); // Enter hires, down 2, left 4
+        emu.step().unwrap();
+        scroll_pattern(&mut emu);
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu), (0..8).map(|i| (i, i + 2)).collect::<Vec<_>>());
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu), (4..8).map(|i| (i - 4, i + 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn draw_large_sprite() {
+        // A 16x16 checkerboard of 4x4 squares
+        let sprite: Vec<u8> = (0..16).flat_map(|row| if row / 4 % 2 == 0 { [0xf0, 0xf0] } else { [0x0f, 0x0f] }).collect();
+        for hires in [false, true] {
+            let mut emu = Chip8::new();
+            emu.set_hires(hires);
+            emu.memory[0x300..0x320].copy_from_slice(&sprite);
+            emu.memory[0x200..0x204].copy_from_slice(&[0xd0, 0x10, 0xd0, 0x10]); // Draw the sprite twice
+            emu.index_register = 0x300;
+            emu.registers[0] = 8;
+            emu.registers[1] = 4;
+            emu.step().unwrap();
+            for row in 0..16 {
+                for col in 0..16 {
+                    let expected = (row / 4 + col / 4) % 2 == 0;
+                    assert_eq!(emu.get_display()[(8 + col) + (4 + row) * emu.display_width()], expected, "({}, {})", col, row);
+                }
+            }
+            assert_eq!(lit_pixels(&emu).len(), 128);
+            assert_eq!(emu.registers[0xf], 0);
+            emu.step().unwrap();
+            assert!(lit_pixels(&emu).is_empty());
+            assert_eq!(emu.registers[0xf], 1);
+        }
+    }
+
+    #[test]
+    fn draw_large_sprite_per_variant() {
+        // (variant, hires, lit pixels)
+        for (variant, hires, lit) in [
+            (Variant::Chip8, false, 0),
+            (Variant::Chip48, false, 0),
+            (Variant::SuperChip, false, 8 * 16),
+            (Variant::SuperChip, true, 16 * 16),
+            (Variant::Modern, false, 16 * 16),
+            (Variant::Modern, true, 16 * 16),
+            (Variant::XoChip, false, 16 * 16),
+            (Variant::XoChip, true, 16 * 16),
+        ] {
+            let mut emu = Chip8::with_variant(variant);
+            emu.set_hires(hires);
+            emu.memory[0x300..0x320].copy_from_slice(&[0xff; 32]);
+            emu.index_register = 0x300;
+            emu.memory[0x200..0x204].copy_from_slice(&[0xd0, 0x10, 0xd0, 0x10]); // Draw the sprite twice
+            emu.step().unwrap();
+            assert_eq!(lit_pixels(&emu).len(), lit, "{:?} hires {}", variant, hires);
+            assert!(lit_pixels(&emu).iter().all(|&(x, y)| x < 16 && y < 16));
+            assert_eq!(emu.registers[0xf], 0);
+            emu.end_of_frame(); // The COSMAC VIP waits for the display between draws
+            emu.step().unwrap();
+            assert!(lit_pixels(&emu).is_empty());
+            // Collided rows are counted in SUPER-CHIP high resolution
+            let collided = match (variant, hires) {
+                (Variant::Chip8 | Variant::Chip48, _) => 0,
+                (Variant::SuperChip, true) => 16,
+                _ => 1,
+            };
+            assert_eq!(emu.registers[0xf], collided, "{:?} hires {}", variant, hires);
+        }
+    }
+
+    #[test]
+    fn draw_large_sprite_clips() {
+        let mut emu = Chip8::new();
+        emu.set_hires(true);
+        emu.memory[0x300..0x320].copy_from_slice(&[0xff; 32]);
+        emu.index_register = 0x300;
+        emu.registers[0] = 120;
+        emu.registers[1] = 60;
+        emu.memory[0x200..0x202].copy_from_slice(&[0xd0, 0x10]);
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu).len(), 8 * 4);
+        assert!(lit_pixels(&emu).iter().all(|&(x, y)| x >= 120 && y >= 60));
+    }
+
+    #[test]
+    fn scroll_right_moves_column() {
+        for hires in [false, true] {
+            let mut emu = Chip8::new();
+            emu.set_hires(hires);
+            let (width, height) = emu.dimensions();
+            for y in 0..height {
+                light(&mut emu, 0, y, 1);
+            }
+            emu.memory[0x200..0x202].copy_from_slice(&[0x00, 0xfb]);
+            emu.step().unwrap();
+            assert_eq!(lit_pixels(&emu), (0..height).map(|y| (4, y)).collect::<Vec<_>>());
+            assert!((0..height).all(|y| (0..4).all(|x| !emu.get_display()[x + y * width])));
+        }
+    }
+
+    #[test]
+    fn draw_large_sprite_collision() {
+        let mut emu = Chip8::new();
+        emu.set_hires(true);
+        // A 16 pixel wide horizontal bar on the last row of the sprite
+        emu.memory[0x31e..0x320].copy_from_slice(&[0xff, 0xff]);
+        emu.index_register = 0x300;
+        emu.registers[0] = 100;
+        emu.registers[1] = 40;
+        light(&mut emu, 115, 55, 1); // Under the last pixel of the bar
+        light(&mut emu, 116, 55, 1); // Just right of the sprite
+        emu.memory[0x200..0x202].copy_from_slice(&[0xd0, 0x10]);
+        emu.step().unwrap();
+        let mut expected: Vec<_> = (100..115).map(|x| (x, 55)).collect();
+        expected.push((116, 55));
+        assert_eq!(lit_pixels(&emu), expected);
+        assert_eq!(emu.registers[0xf], 1);
+    }
+
+    #[test]
+    fn superchip_hires_draw_counts_rows() {
+        for variant in [Variant::Modern, Variant::SuperChip] {
+            let mut emu = Chip8::with_variant(variant);
+            emu.set_hires(true);
+            // Rows 2 and 5 of the sprite are solid, it starts 4 rows above the bottom
+            emu.memory[0x304..0x306].copy_from_slice(&[0xff, 0xff]);
+            emu.memory[0x30a..0x30c].copy_from_slice(&[0xff, 0xff]);
+            emu.index_register = 0x300;
+            emu.registers[0] = 10;
+            emu.registers[1] = 60;
+            emu.memory[0x200..0x204].copy_from_slice(&[0xd0, 0x10, 0xd0, 0x10]); // Draw the sprite twice
+            emu.step().unwrap();
+            let clipped = if variant == Variant::SuperChip { 12 } else { 0 };
+            assert_eq!(emu.registers[0xf], clipped, "{:?}", variant);
+            emu.step().unwrap();
+            // Only row 2 was on screen to collide
+            let collided = if variant == Variant::SuperChip { 1 + 12 } else { 1 };
+            assert_eq!(emu.registers[0xf], collided, "{:?}", variant);
+        }
+
+        // Low resolution keeps the 0 or 1 of CHIP-8
+        let mut emu = Chip8::with_variant(Variant::SuperChip);
+        emu.memory[0x300..0x320].copy_from_slice(&[0xff; 32]);
+        emu.index_register = 0x300;
+        emu.registers[1] = 24;
+        emu.memory[0x200..0x204].copy_from_slice(&[0xd0, 0x10, 0xd0, 0x10]);
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0xf], 0);
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0xf], 1);
+    }
+
+    #[test]
+    fn font_character() {
+        let mut emu = Chip8::new();
+        emu.load(&[0xf0, 0x29, 0xf0, 0x29]).unwrap();
+        emu.registers[0] = 0x7;
+        emu.step().unwrap();
+        assert_eq!(emu.index_register, 0x50 + 7 * 5);
+        emu.registers[0] = 0x1b;
+        emu.step().unwrap();
+        assert_eq!(emu.index_register, 0x50 + 0xb * 5);
+    }
+
+    #[test]
+    fn big_font_characters() {
+        for digit in 0..16u8 {
+            let mut emu = Chip8::new();
+            emu.set_hires(true);
+            emu.load(&[0xf0, 0x30, 0xd1, 0x1a]).unwrap(); // I = big digit V0, draw 10 rows at (V1, V1)
+            emu.registers[0] = digit;
+            emu.step().unwrap();
+            let address = 0xa0 + digit as usize * 10;
+            assert_eq!(emu.index_register as usize, address);
+            emu.step().unwrap();
+            for row in 0..10 {
+                let byte = emu.memory[address + row];
+                for col in 0..8 {
+                    let expected = byte & (0x80 >> col) != 0;
+                    assert_eq!(emu.get_display()[col + row * HIRES_WIDTH], expected, "digit {:X} ({}, {})", digit, col, row);
+                }
+            }
+        }
+        let emu = Chip8::new();
+        // The big font starts right after the small one and ends before the program
+        assert_eq!(emu.memory[0x9b..0xa0], [0xf0, 0x80, 0xf0, 0x80, 0x80]);
+        assert_eq!(emu.memory[0xa0..0xaa], [0xff, 0xff, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xc3, 0xff, 0xff]);
+        assert_eq!(emu.memory[0x136..0x140], [0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xc0, 0xc0]);
+    }
+
+    #[test]
+    fn rpl_flags_round_trip() {
+        let mut emu = Chip8::new();
+        emu.load(&[0xf3, 0x75, 0xf3, 0x85]).unwrap(); // Save V0-V3, then load them back
+        emu.registers[0..4].copy_from_slice(&[1, 2, 3, 4]);
+        emu.step().unwrap();
+        assert_eq!(emu.get_rpl_flags()[..5], [1, 2, 3, 4, 0]);
+        emu.registers = [0; 16];
+        emu.step().unwrap();
+        assert_eq!(emu.registers[..5], [1, 2, 3, 4, 0]);
+
+        emu.set_rpl_flags(&[9, 8, 7]);
+        emu.reset();
+        assert_eq!(emu.get_rpl_flags()[..4], [9, 8, 7, 4]);
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.registers[..4], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rpl_flags_clamp_register() {
+        let mut emu = Chip8::new();
+        emu.load(&[0xff, 0x75]).unwrap(); // Save V0-VF
+        emu.registers = [0xaa; 16];
+        emu.step().unwrap();
+        assert_eq!(emu.get_rpl_flags(), &[0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&[0xff, 0x75]).unwrap();
+        emu.registers = [0xaa; 16];
+        emu.step().unwrap();
+        assert_eq!(emu.get_rpl_flags(), &[0xaa; 16]);
+    }
+
+    #[test]
+    fn select_planes() {
+        let mut emu = Chip8::new();
+        emu.load(&[0xf2, 0x01]).unwrap();
+        assert_eq!(emu.step(), Err(Chip8Error::UnknownOpcode { opcode: 0xf201, pc: 0x200, history: history(&[0x200]) }));
+
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&[0xf2, 0x01, 0xf3, 0x01, 0xf0, 0x01]).unwrap();
+        assert_eq!(emu.selected_planes, 1);
+        for planes in [2, 3, 0] {
+            emu.step().unwrap();
+            assert_eq!(emu.selected_planes, planes);
+        }
+    }
+
+    #[test]
+    fn draw_into_each_plane() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        // Draw into plane 0 at (0, 0), then into plane 1 at (4, 0)
+        emu.load(&[0xd0, 0x11, 0xf2, 0x01, 0x60, 0x04, 0xd0, 0x11]).unwrap();
+        emu.memory[0x300] = 0xff;
+        emu.index_register = 0x300;
+        for _ in 0..4 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.get_display_indexed()[..14], [1, 1, 1, 1, 3, 3, 3, 3, 2, 2, 2, 2, 0, 0]);
+        assert_eq!(emu.get_display()[..14], [true, true, true, true, true, true, true, true, true, true, true, true, false, false]);
+        // Drawing into plane 1 doesn't collide with plane 0
+        assert_eq!(emu.registers[0xf], 0);
+    }
+
+    #[test]
+    fn draw_into_both_planes() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&[0xf3, 0x01, 0xd0, 0x01, 0xd0, 0x01]).unwrap(); // Both planes, draw twice
+        emu.memory[0x300..0x302].copy_from_slice(&[0xf0, 0x3c]); // Plane 0 sprite, then plane 1
+        emu.index_register = 0x300;
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.get_display_indexed()[..8], [1, 1, 3, 3, 2, 2, 0, 0]);
+        assert_eq!(emu.registers[0xf], 0);
+        emu.step().unwrap();
+        assert!(emu.get_display_indexed().iter().all(|pixel| *pixel == 0));
+        assert_eq!(emu.registers[0xf], 1);
+    }
+
+    #[test]
+    fn exit_halts() {
+        let mut emu = Chip8::with_rng(1);
+        emu.load(&[0x60, 0x01, 0x00, 0xfd, 0x60, 0x02]).unwrap();
+        emu.step().unwrap();
+        assert!(!emu.is_halted());
+        assert_eq!(emu.step(), Ok(StepOutcome::Halted));
+        assert!(emu.is_halted());
+        assert_eq!(emu.program_counter, 0x202);
+
+        let registers = emu.registers;
+        for _ in 0..10 {
+            assert_eq!(emu.step(), Ok(StepOutcome::Halted));
+        }
+        assert_eq!(emu.program_counter, 0x202);
+        assert_eq!(emu.registers, registers);
+        assert_eq!(emu.index_register, 0);
+
+        emu.reset();
+        assert!(!emu.is_halted());
+        assert_eq!(emu.step(), Ok(StepOutcome::Executed));
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&[0xf0, 0x02, 0x65, 0x70, 0xf5, 0x3a]).unwrap(); // Load the pattern, V5 = 0x70, pitch = V5
+        let pattern: Vec<u8> = (0..16).map(|i| i * 17).collect();
+        emu.memory[0x300..0x310].copy_from_slice(&pattern);
+        emu.index_register = 0x300;
+        assert_eq!(emu.audio_pattern(), &[0; 16]);
+        assert_eq!(emu.audio_pitch(), 64);
+        for _ in 0..3 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.audio_pattern()[..], pattern[..]);
+        assert_eq!(emu.audio_pitch(), 0x70);
+        assert_eq!(emu.index_register, 0x300);
+        #[cfg(feature = "std")]
+        assert_eq!(emu.audio_playback_rate(), 8000.0); // 48 steps up an octave
+
+        let mut emu = Chip8::new();
+        emu.load(&[0xf0, 0x02]).unwrap();
+        assert_eq!(emu.step(), Err(Chip8Error::UnknownOpcode { opcode: 0xf002, pc: 0x200, history: history(&[0x200]) }));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn audio_samples_follow_pattern() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.audio_pattern[0] = 0b1010_0000;
+        emu.audio_pattern[15] = 0b0000_0001;
+        let mut out = [0.5; 4];
+        let mut position = 0.0;
+        emu.fill_audio_samples(&mut out, 8000, &mut position);
+        assert_eq!(out, [0.0; 4]); // Silent without the sound timer
+
+        emu.sound_timer = 2;
+        emu.fill_audio_samples(&mut out, 8000, &mut position);
+        assert_eq!(out, [1.0, 1.0, -1.0, -1.0]); // Each sample held for 2 output samples at 4000 Hz
+        assert_eq!(position, 2.0);
+        emu.fill_audio_samples(&mut out, 8000, &mut position);
+        assert_eq!(out, [1.0, 1.0, -1.0, -1.0]);
+
+        // Wraps around to the start of the pattern
+        let mut position = 127.0;
+        emu.fill_audio_samples(&mut out, 4000, &mut position);
+        assert_eq!(out, [1.0, 1.0, -1.0, 1.0]);
+        assert_eq!(position, 3.0);
+    }
+
+    #[test]
+    fn store_register_range() {
+        for (opcode, expected) in [
+            ([0x52, 0x22], vec![2]), // A single register
+            ([0x52, 0x42], vec![2, 3, 4]),
+            ([0x54, 0x22], vec![4, 3, 2]), // Descending
+        ] {
+            let mut emu = Chip8::with_variant(Variant::XoChip);
+            emu.load(&opcode).unwrap();
+            emu.registers = core::array::from_fn(|i| 0x10 + i as u8);
+            emu.index_register = 0x300;
+            emu.step().unwrap();
+            let stored: Vec<usize> = emu.memory[0x300..0x300 + expected.len()].iter().map(|byte| (byte - 0x10) as usize).collect();
+            assert_eq!(stored, expected);
+            assert_eq!(emu.memory[0x300 + expected.len()], 0);
+            assert_eq!(emu.index_register, 0x300);
+        }
+    }
+
+    #[test]
+    fn load_register_range() {
+        for (opcode, expected) in [
+            ([0x57, 0x73], vec![7]), // A single register
+            ([0x51, 0x33], vec![1, 2, 3]),
+            ([0x5f, 0xd3], vec![0xf, 0xe, 0xd]), // Descending
+        ] {
+            let mut emu = Chip8::with_variant(Variant::XoChip);
+            emu.load(&opcode).unwrap();
+            emu.memory[0x300..0x303].copy_from_slice(&[0xa0, 0xa1, 0xa2]);
+            emu.index_register = 0x300;
+            emu.step().unwrap();
+            for reg in 0..16 {
+                let expected = expected.iter().position(|r| *r == reg).map_or(0, |i| 0xa0 + i as u8);
+                assert_eq!(emu.registers[reg], expected, "V{:X}", reg);
+            }
+            assert_eq!(emu.index_register, 0x300);
+        }
+    }
+
+    #[test]
+    fn register_range_needs_xochip() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&[0x50, 0xf2]).unwrap();
+        emu.index_register = 0xfffe;
+        assert_eq!(emu.step(), Err(Chip8Error::MemoryOutOfBounds { opcode: 0x50f2, pc: 0x200, address: 0xffff, history: history(&[0x200]) }));
+
+        for opcode in [0x5012u16, 0x5013] {
+            let mut emu = Chip8::new();
+            emu.load(&opcode.to_be_bytes()).unwrap();
+            assert_eq!(emu.step(), Err(Chip8Error::UnknownOpcode { opcode, pc: 0x200, history: history(&[0x200]) }));
+        }
+    }
+
+    #[test]
+    fn long_index() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&[0xf0, 0x00, 0x12, 0x34, 0x00, 0xe0]).unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.index_register, 0x1234);
+        assert_eq!(emu.program_counter, 0x204);
+
+        let mut emu = Chip8::new();
+        emu.load(&[0xf0, 0x00, 0x12, 0x34]).unwrap();
+        assert_eq!(emu.step(), Err(Chip8Error::UnknownOpcode { opcode: 0xf000, pc: 0x200, history: history(&[0x200]) }));
+    }
+
+    #[test]
+    fn skip_over_long_index() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&[
+            0x30, 0x00, // Skip if V0 == 0
+            0xf0, 0x00, 0x12, 0x34, // I = 0x1234, skipped
+            0x30, 0x00, // Skip if V0 == 0
+            0x61, 0x01, // V1 = 1, skipped
+            0x62, 0x02, // V2 = 2
+        ]).unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.program_counter, 0x206);
+        emu.step().unwrap();
+        assert_eq!(emu.program_counter, 0x20a);
+        emu.step().unwrap();
+        assert_eq!(emu.index_register, 0);
+        assert_eq!(emu.registers[1..3], [0, 2]);
+    }
+
+    #[test]
+    fn skips_are_two_bytes_outside_xochip() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x30, 0x00, 0xf0, 0x00]).unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.program_counter, 0x204);
+    }
+
+    #[test]
+    fn extended_memory() {
+        let mut emu = Chip8::with_config(Chip8Config { extended_memory: true, ..Chip8Config::xochip() });
+        assert_eq!(emu.memory_size(), 0x10000);
+        assert_eq!(Chip8::new().memory_size(), 0x1000);
+        let mut rom = vec![0; 0x8000];
+        rom[..8].copy_from_slice(&[0xf0, 0x00, 0x12, 0x34, 0xf0, 0x00, 0xff, 0xf0]);
+        rom[8..10].copy_from_slice(&[0xf1, 0x55]); // Store V0-V1 at 0xFFF0
+        emu.load(&rom).unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.index_register, 0x1234);
+        assert_eq!(emu.program_counter, 0x204);
+        emu.step().unwrap();
+        emu.registers[0..2].copy_from_slice(&[0xab, 0xcd]);
+        emu.step().unwrap();
+        assert_eq!(emu.memory[0xfff0..0xfff2], [0xab, 0xcd]);
+
+        let mut emu = Chip8::with_config(Chip8Config { extended_memory: false, ..Chip8Config::xochip() });
+        assert_eq!(emu.load(&rom), Err(LoadError::TooLarge { size: 0x8000, max: 0xe00 }));
+    }
+
+    #[test]
+    fn long_index_at_the_end_of_memory() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.memory[0xfffc..].copy_from_slice(&[0xf0, 0x00, 0x56, 0x78]);
+        emu.set_pc(0xfffc);
+        emu.step().unwrap();
+        assert_eq!(emu.index_register, 0x5678);
+        assert_eq!(emu.program_counter, 0); // Wraps around like any other PC update
+    }
+
+    #[test]
+    fn extended_memory_runs_code_above_4kb() {
+        // Counts up in V0 all the way to 0x1000, where V1 is set
+        let mut rom = [0x70, 0x01].repeat((0x1000 - 0x200) / 2);
+        rom.extend_from_slice(&[0x61, 0x42, 0x70, 0x01]);
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&rom).unwrap();
+        while emu.program_counter < 0x1004 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.registers[0], 0x01); // 0x700 additions wrap to 0, then one more
+        assert_eq!(emu.registers[1], 0x42);
+
+        let mut emu = Chip8::new();
+        assert_eq!(emu.load(&rom), Err(LoadError::TooLarge { size: 0xe04, max: 0xe00 }));
+    }
+
+    #[test]
+    fn classic_memory_index_wraps_at_12_bits() {
+        let mut emu = Chip8::new();
+        emu.load(&[0xf1, 0x1e]).unwrap();
+        emu.index_register = 0xff0;
+        emu.registers[1] = 0x20;
+        emu.step().unwrap();
+        assert_eq!(emu.index_register, 0x010);
+    }
+
+    #[test]
+    fn extended_memory_index_wraps_at_16_bits() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&[0xf1, 0x1e, 0xf1, 0x1e]).unwrap(); // I = I + V1, twice
+        emu.index_register = 0xff80;
+        emu.registers[1] = 0x40;
+        emu.step().unwrap();
+        assert_eq!(emu.index_register, 0xffc0);
+        emu.step().unwrap();
+        assert_eq!(emu.index_register, 0x0000);
+    }
+
+    #[test]
+    fn same_sprite_in_each_plane() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&[
+            0xd0, 0x12, // Draw into plane 0 at (0, 0)
+            0xf2, 0x01, 0x60, 0x02, 0xd0, 0x12, // Draw into plane 1 at (2, 0)
+        ]).unwrap();
+        emu.memory[0x300..0x302].copy_from_slice(&[0xf0, 0x90]);
+        emu.index_register = 0x300;
+        for _ in 0..4 {
+            emu.step().unwrap();
+        }
+        let (width, _) = emu.dimensions();
+        assert_eq!(emu.get_display_indexed()[..7], [1, 1, 3, 3, 2, 2, 0]);
+        assert_eq!(emu.get_display_indexed()[width..width + 7], [1, 0, 2, 1, 0, 2, 0]);
+    }
+
+    #[test]
+    fn clear_and_scroll_selected_planes() {
+        let mut emu = Chip8::with_variant(Variant::XoChip);
+        emu.load(&[
+            0xf2, 0x01, 0x00, 0xfb, // Scroll plane 1 right
+            0xf1, 0x01, 0x00, 0xe0, // Clear plane 0
+            0xf3, 0x01, 0x00, 0xfc, // Scroll both planes left
+            0x00, 0xe0, // Clear both planes
+        ]).unwrap();
+        light(&mut emu, 0, 0, 3);
+        light(&mut emu, 1, 0, 1);
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.get_display_indexed()[..6], [1, 1, 0, 0, 2, 0]);
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.get_display_indexed()[..6], [0, 0, 0, 0, 2, 0]);
+        assert_eq!(emu.get_display()[..6], [false, false, false, false, true, false]);
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.get_display_indexed()[..6], [2, 0, 0, 0, 0, 0]);
+        emu.step().unwrap();
+        assert_eq!(lit_pixels(&emu), []);
+    }
+
+    #[test]
+    fn get_row_matches_display() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 60, 3, &[0b1100_0011]);
+        assert_eq!(emu.get_row(3), 0b11 << 60); // The rest is clipped
+        assert_eq!(emu.get_row(2), 0);
+        assert_eq!(emu.get_row(SCREEN_HEIGHT), 0);
+        for y in 0..SCREEN_HEIGHT {
+            let row = emu.get_row(y);
+            for x in 0..SCREEN_WIDTH {
+                assert_eq!(row >> x & 1 == 1, emu.get_display()[x + y * SCREEN_WIDTH], "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn get_display_rows_packs_the_display() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 10, 5, &[0xf0, 0x81]);
+        draw_at(&mut emu, 60, 31, &[0b1100_0011]);
+        let rows = emu.get_display_rows();
+        assert_eq!(rows[5], 0xf0 << 46);
+        assert_eq!(rows[6], 0x81 << 46);
+        assert_eq!(rows[31], 0b1100); // The rest is clipped
+        assert_eq!(rows.iter().filter(|&&row| row != 0).count(), 3);
+    }
+
+    #[test]
+    fn get_display_rows_matches_display() {
+        let mut emu = Chip8::new();
+        let mut rng = Rng::new(7);
+        for _ in 0..8 {
+            for y in 0..SCREEN_HEIGHT {
+                for x in 0..SCREEN_WIDTH {
+                    emu.set_pixel(x, y, rng.next_u8() & 1 == 1);
+                }
+            }
+            let rows = emu.get_display_rows();
+            for (y, row) in rows.iter().enumerate() {
+                for x in 0..SCREEN_WIDTH {
+                    assert_eq!(row >> (63 - x) & 1 == 1, emu.get_display()[x + y * SCREEN_WIDTH], "({}, {})", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dirty_rows_track_changes() {
+        let mut emu = Chip8::new();
+        emu.was_redrawn();
+        assert_eq!(emu.take_dirty_rows().count(), 0);
+
+        draw_at(&mut emu, 0, 4, &[0x80, 0x00, 0x80]); // The blank row doesn't change
+        draw_at(&mut emu, 0, 30, &[0x80, 0x80, 0x80]); // Clipped at the bottom
+        assert!(emu.take_dirty_rows().eq([4, 6, 30, 31]));
+
+        emu.memory[0x200..0x202].copy_from_slice(&[0x00, 0xe0]);
+        emu.program_counter = 0x200;
+        emu.step().unwrap();
+        assert!(emu.take_dirty_rows().eq(0..SCREEN_HEIGHT));
+
+        emu.set_hires(true);
+        assert!(emu.take_dirty_rows().eq(0..HIRES_HEIGHT));
+    }
+
+    #[test]
+    fn take_dirty_rows_clears_them() {
+        let mut emu = Chip8::new();
+        assert!(emu.take_dirty_rows().eq(0..SCREEN_HEIGHT));
+        assert_eq!(emu.take_dirty_rows().count(), 0);
+
+        draw_at(&mut emu, 10, 5, &[0x80, 0x40, 0x20]);
+        assert!(emu.take_dirty_rows().eq(5..8));
+        assert!(!emu.needs_redraw());
+
+        emu.memory[0x200..0x202].copy_from_slice(&[0x00, 0xe0]);
+        emu.program_counter = 0x200;
+        emu.step().unwrap();
+        assert!(emu.needs_redraw());
+        assert!(emu.take_dirty_rows().eq(0..SCREEN_HEIGHT));
+    }
+
+    #[test]
+    fn set_and_get_pixel() {
+        let mut emu = Chip8::new();
+        emu.was_redrawn();
+        emu.set_pixel(10, 5, true);
+        assert_eq!(emu.get_pixel(10, 5), Some(true));
+        assert_eq!(emu.get_pixel(11, 5), Some(false));
+        assert!(emu.get_display()[10 + 5 * SCREEN_WIDTH]);
+        assert_eq!(emu.get_row(5), 1 << 10);
+        assert!(emu.needs_redraw());
+        assert!(emu.take_dirty_rows().eq([5]));
+
+        // Drawing over it collides like any other lit pixel
+        draw_at(&mut emu, 10, 5, &[0x80]);
+        assert_eq!(emu.get_pixel(10, 5), Some(false));
+        assert_eq!(emu.registers[0xf], 1);
+
+        emu.set_pixel(SCREEN_WIDTH, 0, true);
+        emu.set_pixel(0, SCREEN_HEIGHT, true);
+        assert_eq!(emu.get_pixel(SCREEN_WIDTH, 0), None);
+        assert_eq!(emu.get_pixel(0, SCREEN_HEIGHT), None);
+        assert!(lit_pixels(&emu).is_empty());
+
+        emu.set_hires(true);
+        emu.set_pixel(100, 50, true);
+        assert_eq!(emu.get_pixel(100, 50), Some(true));
+        emu.set_pixel(100, 50, false);
+        assert_eq!(emu.get_pixel(100, 50), Some(false));
+    }
+
+    #[test]
+    fn get_pixel_reads_the_corners() {
+        let mut emu = Chip8::new();
+        draw_at(&mut emu, 0, 0, &[0x80]);
+        draw_at(&mut emu, 63, 31, &[0x80]);
+        assert_eq!(emu.get_pixel(0, 0), Some(true));
+        assert_eq!(emu.get_pixel(63, 31), Some(true));
+        assert_eq!(emu.get_pixel(63, 0), Some(false));
+        assert_eq!(emu.get_pixel(0, 31), Some(false));
+        assert_eq!(emu.get_pixel(64, 31), None);
+        assert_eq!(emu.get_pixel(63, 32), None);
+        assert_eq!(emu.get_pixel(usize::MAX, usize::MAX), None);
+
+        // The limits follow the resolution
+        emu.set_hires(true);
+        assert_eq!(emu.get_pixel(127, 63), Some(false));
+        assert_eq!(emu.get_pixel(128, 0), None);
+    }
+
+    #[test]
+    fn clone_runs_identically() {
+        let mut emu = Chip8::with_rng(11);
+        emu.load(include_bytes!("../../roms/Stars [Sergey Naydenov, 2010].ch8")).unwrap();
+        emu.set_sound_callback(Box::new(|_| ()));
+        for _ in 0..500 {
+            emu.step().unwrap();
+        }
+        let mut copy = emu.clone();
+        assert_eq!(copy, emu);
+        assert!(copy.sound_callback.0.is_none());
+        for step in 0..2000 {
+            assert_eq!(copy.step(), emu.step());
+            if step % HEADLESS_STEPS_PER_FRAME == 0 {
+                copy.tick_timers();
+                emu.tick_timers();
+            }
+        }
+        assert_eq!(copy, emu);
+        copy.registers[0] ^= 1;
+        assert_ne!(copy, emu);
+    }
+
+    #[test]
+    fn default_and_independent_clones() {
+        #[derive(Default)]
+        struct Session {
+            emu: Chip8,
+        }
+
+        let mut session = Session::default();
+        assert_eq!(session.emu.config, Chip8Config::default());
+        session.emu.load(&[0x70, 0x01, 0x12, 0x00]).unwrap(); // Count up in V0 forever
+        for _ in 0..10 {
+            session.emu.step().unwrap();
+        }
+        let mut copy = session.emu.clone();
+        copy.step().unwrap();
+        copy.step().unwrap();
+        copy.write_memory(0x300, 0xaa).unwrap();
+        assert_eq!(copy.register(0), 6);
+        assert_eq!(session.emu.register(0), 5);
+        assert_eq!(session.emu.read_memory(0x300), Some(0));
+        session.emu.reset();
+        assert_eq!(session.emu.register(0), 0);
+        assert_eq!(copy.register(0), 6);
+    }
+
+    #[test]
+    fn counts_instructions() {
+        let mut emu = Chip8::with_quirks(Quirks { display_wait: true, ..Quirks::default() });
+        // V0 = 3, then count it down to 0 in a loop, draw twice and exit
+        emu.load(&[0x60, 0x03, 0x70, 0xff, 0x30, 0x00, 0x12, 0x02, 0xd0, 0x01, 0xd0, 0x01, 0x00, 0xfd]).unwrap();
+        assert_eq!(emu.cycles(), 0);
+        for _ in 0..10 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.registers[0], 0);
+        // Once, the 3 instruction loop twice, 2 on the last pass skipping the jump, then the first draw
+        assert_eq!(emu.cycles(), 10);
+        assert_eq!(emu.step(), Ok(StepOutcome::WaitingForVblank));
+        assert_eq!(emu.cycles(), 10);
+        emu.tick_timers();
+        emu.step().unwrap();
+        assert_eq!(emu.step(), Ok(StepOutcome::Halted));
+        assert_eq!(emu.step(), Ok(StepOutcome::Halted));
+        assert_eq!(emu.cycles(), 11);
+
+        emu.reset();
+        assert_eq!(emu.cycles(), 0);
+        emu.load(&[0x00, 0x00, 0xff, 0xff]).unwrap();
+        emu.step().unwrap();
+        assert!(emu.step().is_err());
+        assert_eq!(emu.cycles(), 1);
+    }
+
+    #[test]
+    fn read_only_accessors() {
+        let mut emu = Chip8::new();
+        // V0 = 5, V1 = 7, I = 0x345, delay = V0, sound = V1, then call 0x20E
+        emu.load(&[0x60, 0x05, 0x61, 0x07, 0xa3, 0x45, 0xf0, 0x15, 0xf1, 0x18, 0x22, 0x0e, 0x00, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(emu.registers(), &[0; 16]);
+        assert_eq!(emu.pc(), 0x200);
+        assert_eq!(emu.stack(), &[] as &[u16]);
+        emu.step_n(6).unwrap();
+        assert_eq!(emu.registers()[..2], [5, 7]);
+        assert_eq!(emu.pc(), 0x20e);
+        assert_eq!(emu.index(), 0x345);
+        assert_eq!(emu.delay_timer(), 5);
+        assert_eq!(emu.sound_timer(), 7);
+        assert_eq!(emu.stack(), &[0x20c]);
+        emu.tick_timers();
+        assert_eq!((emu.delay_timer(), emu.sound_timer()), (4, 6));
+    }
+
+    #[test]
+    fn memory_boundaries() {
+        let mut emu = Chip8::new();
+        assert_eq!(emu.write_memory(0x000, 0x12), Ok(()));
+        assert_eq!(emu.write_memory(0xfff, 0x34), Ok(()));
+        assert_eq!(emu.write_memory(0x1000, 0x56), Err(MemError::OutOfBounds { addr: 0x1000 }));
+        assert_eq!(emu.read_memory(0x000), Some(0x12));
+        assert_eq!(emu.read_memory(0xfff), Some(0x34));
+        assert_eq!(emu.read_memory(0x1000), None);
+
+        assert_eq!(emu.read_memory_range(0xffe, 2), Some(&[0, 0x34][..]));
+        assert_eq!(emu.read_memory_range(0xffe, 3), None);
+        assert_eq!(emu.read_memory_range(0x1000, 0), Some(&[][..]));
+        assert_eq!(emu.read_memory_range(0xffff, 2), None);
+        assert_eq!(emu.read_memory_range(0x200, usize::MAX), None);
+
+        // Writes past the end change nothing
+        assert_eq!(emu.write_memory_slice(0xffe, &[1, 2, 3]), Err(MemError::OutOfBounds { addr: 0x1000 }));
+        assert_eq!(emu.read_memory_range(0xffe, 2), Some(&[0, 0x34][..]));
+        assert_eq!(emu.write_memory_slice(0xffe, &[1, 2]), Ok(()));
+        assert_eq!(emu.read_memory_range(0xffe, 2), Some(&[1, 2][..]));
+    }
+
+    #[test]
+    fn font_protection() {
+        let mut emu = Chip8::new();
+        assert_eq!(emu.write_memory(FONT_ADDRESS, 0xff), Ok(()));
+        assert_eq!(emu.read_memory(FONT_ADDRESS), Some(0xff));
+
+        emu.set_font_protection(true);
+        assert_eq!(emu.write_memory(FONT_ADDRESS + 1, 0), Err(MemError::Protected { addr: 0x51 }));
+        assert_eq!(emu.write_memory_slice(0x40, &[0; 0x20]), Err(MemError::Protected { addr: 0x50 }));
+        assert_eq!(emu.write_memory(FONT_END - 1, 0), Err(MemError::Protected { addr: 0x13f }));
+        assert_eq!(emu.write_memory(FONT_ADDRESS - 1, 1), Ok(()));
+        assert_eq!(emu.write_memory(FONT_END, 1), Ok(()));
+        assert_eq!(emu.read_memory_range(0x40, 0x11), Some(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff][..]));
+
+        // Only writes through the API are refused, the program can still change the font
+        emu.load(&[0xa0, 0x50, 0xf0, 0x55]).unwrap();
+        emu.step_n(2).unwrap();
+        assert_eq!(emu.read_memory(FONT_ADDRESS), Some(0));
+    }
+
+    #[test]
+    fn step_n_stops_early() {
+        let mut emu = Chip8::new();
+        emu.load(&[0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x00, 0xfd]).unwrap(); // Add 1 to V0 3 times, then exit
+        emu.step_n(2).unwrap();
+        assert_eq!(emu.registers[0], 2);
+        emu.step_n(100).unwrap();
+        assert_eq!(emu.registers[0], 3);
+        assert!(emu.is_halted());
+        assert_eq!(emu.cycles(), 3);
+
+        let mut emu = Chip8::new();
+        emu.load(&[0x70, 0x01, 0xff, 0xff, 0x70, 0x01]).unwrap();
+        assert_eq!(emu.step_n(3), Err(Chip8Error::UnknownOpcode { opcode: 0xffff, pc: 0x202, history: history(&[0x200, 0x202]) }));
+        assert_eq!(emu.registers[0], 1);
+    }
+
+    #[test]
+    fn run_until_redraw_stops_at_draw() {
+        let mut emu = Chip8::new();
+        // Count V0 up twice, draw, then loop forever
+        emu.load(&[0x70, 0x01, 0x70, 0x01, 0xd1, 0x15, 0x12, 0x06]).unwrap();
+        emu.was_redrawn();
+        assert_eq!(emu.run_until_redraw(100), Ok(3));
+        assert!(emu.needs_redraw());
+        assert_eq!(emu.registers[0], 2);
+        assert_eq!(emu.run_until_redraw(100), Ok(0)); // Still needs a redraw
+        emu.was_redrawn();
+        assert_eq!(emu.run_until_redraw(100), Ok(100)); // Never draws again
+        assert_eq!(emu.program_counter, 0x206);
+    }
+
+    #[test]
+    fn debug_summarizes_memory() {
+        let emu = Chip8::new();
+        let debug = format!("{:?}", emu);
+        assert!(debug.starts_with("Chip8 { program_counter: 0x200, index_register: 0x000,"), "{}", debug);
+        assert!(debug.contains("memory: [4096 bytes, hash "), "{}", debug);
+        assert!(debug.len() < 1000, "{}", debug);
     }
 
     // TODO: Write tests for the rest of the instructions
@@ -0,0 +1,160 @@
+//! Rewinding to earlier frames, from save states kept in a ring buffer.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::Chip8;
+
+/// The rewind points of a machine, oldest first. Each point is a `save_state`, which is much
+/// smaller than a clone of the machine. It isn't part of the machine state, so it's ignored when
+/// comparing machines and isn't saved.
+#[derive(Clone, Default)]
+pub(crate) struct RewindBuffer {
+    capacity: usize,
+    points: VecDeque<Vec<u8>>,
+}
+
+impl PartialEq for RewindBuffer {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for RewindBuffer {}
+
+impl Chip8 {
+    /// Sets how many rewind points are kept, e.g. 600 for 10 seconds when pushing one per frame.
+    /// The oldest points are dropped past it. Rewinding is disabled with the default of 0.
+    pub fn set_rewind_capacity(&mut self, capacity: usize) {
+        let rewind = &mut self.rewind;
+        rewind.capacity = capacity;
+        while rewind.points.len() > capacity {
+            rewind.points.pop_front();
+        }
+    }
+
+    /// Returns how many rewind points are kept at most.
+    pub fn rewind_capacity(&self) -> usize {
+        self.rewind.capacity
+    }
+
+    /// Returns how many rewind points are available to go back to.
+    pub fn rewind_len(&self) -> usize {
+        self.rewind.points.len()
+    }
+
+    /// Saves the current state as a rewind point, meant to be called once per frame. The oldest
+    /// point is dropped when the buffer is full.
+    pub fn push_rewind_point(&mut self) {
+        if self.rewind.capacity == 0 {
+            return;
+        }
+        if self.rewind.points.len() == self.rewind.capacity {
+            self.rewind.points.pop_front();
+        }
+        let state = self.save_state();
+        self.rewind.points.push_back(state);
+    }
+
+    /// Goes back to the state saved `frames` rewind points ago, 1 being the last one pushed. That
+    /// point and the ones after it are dropped, so running on pushes a new timeline. Going back
+    /// further than the oldest point stops at it. Returns how many points were gone back.
+    pub fn rewind(&mut self, frames: usize) -> usize {
+        let frames = frames.min(self.rewind.points.len());
+        if frames == 0 {
+            return 0;
+        }
+        let keep = self.rewind.points.len() - frames;
+        let state = self.rewind.points.drain(keep..).next().expect("at least one point is dropped");
+        self.load_state(&state).expect("rewind points are valid save states");
+        frames
+    }
+
+    /// Drops all the rewind points.
+    pub fn clear_rewind(&mut self) {
+        self.rewind.points.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HEADLESS_STEPS_PER_FRAME;
+
+    /// Runs a frame of the program, pushing a rewind point at its start.
+    fn run_frame(emu: &mut Chip8) {
+        emu.push_rewind_point();
+        for _ in 0..HEADLESS_STEPS_PER_FRAME {
+            emu.step().unwrap();
+        }
+        emu.tick_timers();
+    }
+
+    fn stars() -> Chip8 {
+        let mut emu = Chip8::with_rng(5);
+        emu.load(include_bytes!("../../roms/Stars [Sergey Naydenov, 2010].ch8")).unwrap();
+        emu
+    }
+
+    #[test]
+    fn rewind_and_replay() {
+        let mut emu = stars();
+        emu.set_rewind_capacity(600);
+        let mut timeline = Vec::new();
+        for _ in 0..60 {
+            timeline.push(emu.clone());
+            run_frame(&mut emu);
+        }
+        let end = emu.clone();
+
+        assert_eq!(emu.rewind(30), 30);
+        assert_eq!(emu, timeline[30]);
+        assert_eq!(emu.get_display(), timeline[30].get_display());
+        assert_eq!(emu.rewind_len(), 30);
+
+        for expected in &timeline[30..] {
+            assert_eq!(&emu, expected);
+            run_frame(&mut emu);
+        }
+        assert_eq!(emu, end);
+        assert_eq!(emu.get_display(), end.get_display());
+        assert_eq!(emu.rewind_len(), 60);
+    }
+
+    #[test]
+    fn oldest_points_are_dropped() {
+        let mut emu = stars();
+        emu.set_rewind_capacity(10);
+        let mut timeline = Vec::new();
+        for _ in 0..25 {
+            timeline.push(emu.clone());
+            run_frame(&mut emu);
+        }
+        assert_eq!(emu.rewind_len(), 10);
+
+        // Clamps to the oldest point left, from frame 15
+        assert_eq!(emu.rewind(100), 10);
+        assert_eq!(emu, timeline[15]);
+        assert_eq!(emu.rewind_len(), 0);
+        assert_eq!(emu.rewind(1), 0);
+        assert_eq!(emu, timeline[15]);
+
+        for _ in 0..5 {
+            run_frame(&mut emu);
+        }
+        emu.set_rewind_capacity(2);
+        assert_eq!(emu.rewind(2), 2);
+        assert_eq!(emu, timeline[18]);
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let mut emu = stars();
+        assert_eq!(emu.rewind_capacity(), 0);
+        run_frame(&mut emu);
+        assert_eq!(emu.rewind_len(), 0);
+        let before = emu.clone();
+        assert_eq!(emu.rewind(5), 0);
+        assert_eq!(emu, before);
+    }
+}
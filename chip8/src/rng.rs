@@ -0,0 +1,36 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A small xorshift64* generator, so random numbers can be reproduced from a seed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a generator from `seed`, any value including 0 is allowed.
+    pub(crate) fn new(seed: u64) -> Self {
+        // The state must never be zero, otherwise the generator only returns zeroes
+        let state = if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed };
+        Rng { state }
+    }
+
+    /// Returns the internal state, to save the generator for later.
+    pub(crate) fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Recreates a generator saved with `state`, `None` if it can't be a valid state.
+    pub(crate) fn from_state(state: u64) -> Option<Self> {
+        (state != 0).then_some(Rng { state })
+    }
+
+    /// Returns the next random byte.
+    pub(crate) fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 56) as u8
+    }
+}
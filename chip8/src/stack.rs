@@ -0,0 +1,78 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The most subroutine calls that can be nested, whatever the configured stack limit.
+pub const STACK_SIZE: usize = 16;
+
+/// The subroutine return addresses, in a fixed array so the stack never allocates or grows past
+/// `STACK_SIZE`. Unused entries are kept at 0 so machines with the same calls compare equal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub(crate) struct Stack {
+    entries: [u16; STACK_SIZE],
+    len: usize,
+}
+
+impl Stack {
+    /// Creates a stack holding `addresses`, `None` if there are more than `STACK_SIZE`.
+    pub(crate) fn from_slice(addresses: &[u16]) -> Option<Self> {
+        let mut stack = Stack::default();
+        stack.entries.get_mut(..addresses.len())?.copy_from_slice(addresses);
+        stack.len = addresses.len();
+        Some(stack)
+    }
+
+    /// Pushes `address`, returns false if the stack is full.
+    pub(crate) fn push(&mut self, address: u16) -> bool {
+        if self.len == STACK_SIZE {
+            return false;
+        }
+        self.entries[self.len] = address;
+        self.len += 1;
+        true
+    }
+
+    /// Pops the last address pushed, `None` if the stack is empty.
+    pub(crate) fn pop(&mut self) -> Option<u16> {
+        self.len = self.len.checked_sub(1)?;
+        Some(core::mem::take(&mut self.entries[self.len]))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the addresses, oldest call first.
+    pub(crate) fn as_slice(&self) -> &[u16] {
+        &self.entries[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_and_pop() {
+        let mut stack = Stack::default();
+        assert_eq!(stack.len(), 0);
+        assert_eq!(stack.pop(), None);
+        for address in 0..STACK_SIZE as u16 {
+            assert!(stack.push(0x200 + address * 2));
+        }
+        assert!(!stack.push(0x300));
+        assert_eq!(stack.len(), STACK_SIZE);
+        assert_eq!(stack.as_slice()[..2], [0x200, 0x202]);
+        assert_eq!(stack.pop(), Some(0x21e));
+        assert_eq!(stack.len(), STACK_SIZE - 1);
+
+        // Popped entries don't affect comparisons
+        let mut other = Stack::from_slice(stack.as_slice()).unwrap();
+        assert_eq!(other, stack);
+        other.push(0x400);
+        other.pop();
+        assert_eq!(other, stack);
+
+        assert_eq!(Stack::from_slice(&[0; STACK_SIZE + 1]), None);
+    }
+}
@@ -0,0 +1,368 @@
+//! A compact binary save state format, independent of serde.
+//!
+//! A state starts with the `C8ST` magic number and a version byte, followed by the machine fields
+//! in a fixed order. Numbers are little endian and flags are single bytes. Version 2 added the
+//! instruction count at the end, version 1 states are loaded with it at 0.
+
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+
+use crate::rng::Rng;
+use crate::stack::Stack;
+use crate::{Chip8, Chip8Config, MemoryIncrement, Quirks, Variant};
+
+/// The bytes every save state starts with.
+const MAGIC: [u8; 4] = *b"C8ST";
+/// The format version written by `save_state`, bumped whenever the layout changes.
+const VERSION: u8 = 2;
+/// Marks that no key was pressed while waiting for FX0A.
+const NO_KEY: u8 = 0xff;
+
+/// Errors that prevent a save state from being loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// The data ended before the whole state was read.
+    Truncated,
+    /// The data doesn't start with the save state magic number.
+    BadMagic,
+    /// The state was written in format `version`, which this version of the crate can't read.
+    UnsupportedVersion { version: u8 },
+    /// The value of `field` is out of range, so the state is corrupted.
+    Invalid { field: &'static str },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion { version } => {
+                write!(f, "save state version {} is not supported, expected 1 to {}", version, VERSION)
+            },
+            StateError::Invalid { field } => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+/// Reads the fields of a save state in order.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.bytes(N)?.try_into().expect("read exactly N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn u128(&mut self) -> Result<u128, StateError> {
+        Ok(u128::from_le_bytes(self.array()?))
+    }
+
+    fn bool(&mut self, field: &'static str) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid { field }),
+        }
+    }
+
+    /// Reads a u32 length followed by that many bytes.
+    fn vec(&mut self) -> Result<Vec<u8>, StateError> {
+        let len = self.u32()? as usize;
+        Ok(self.bytes(len)?.to_vec())
+    }
+}
+
+fn write_vec(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn write_config(out: &mut Vec<u8>, config: &Chip8Config) {
+    out.push(match config.variant {
+        Variant::Chip8 => 0,
+        Variant::Chip48 => 1,
+        Variant::SuperChip => 2,
+        Variant::XoChip => 3,
+        Variant::Modern => 4,
+    });
+    let quirks = &config.quirks;
+    out.push(quirks.shift_uses_vy as u8);
+    out.push(match quirks.memory_increment {
+        MemoryIncrement::Unchanged => 0,
+        MemoryIncrement::XPlusOne => 1,
+        MemoryIncrement::X => 2,
+    });
+    out.push(quirks.jump_with_offset_uses_vx as u8);
+    out.push(quirks.logic_resets_vf as u8);
+    out.push(quirks.sprite_wrapping as u8);
+    out.push(quirks.display_wait as u8);
+    out.push(quirks.index_overflow_sets_vf as u8);
+    out.extend_from_slice(&(config.stack_limit as u32).to_le_bytes());
+    out.push(config.extended_memory as u8);
+}
+
+fn read_config(reader: &mut Reader) -> Result<Chip8Config, StateError> {
+    let variant = match reader.u8()? {
+        0 => Variant::Chip8,
+        1 => Variant::Chip48,
+        2 => Variant::SuperChip,
+        3 => Variant::XoChip,
+        4 => Variant::Modern,
+        _ => return Err(StateError::Invalid { field: "variant" }),
+    };
+    let shift_uses_vy = reader.bool("quirk")?;
+    let memory_increment = match reader.u8()? {
+        0 => MemoryIncrement::Unchanged,
+        1 => MemoryIncrement::XPlusOne,
+        2 => MemoryIncrement::X,
+        _ => return Err(StateError::Invalid { field: "memory increment quirk" }),
+    };
+    let quirks = Quirks {
+        shift_uses_vy,
+        memory_increment,
+        jump_with_offset_uses_vx: reader.bool("quirk")?,
+        logic_resets_vf: reader.bool("quirk")?,
+        sprite_wrapping: reader.bool("quirk")?,
+        display_wait: reader.bool("quirk")?,
+        index_overflow_sets_vf: reader.bool("quirk")?,
+    };
+    let stack_limit = reader.u32()? as usize;
+    let extended_memory = reader.bool("extended memory")?;
+    Ok(Chip8Config { variant, quirks, stack_limit, extended_memory })
+}
+
+impl Chip8 {
+    /// Saves the whole machine state in the crate's own binary format, to be loaded later with
+    /// `load_state`. Unlike `snapshot` it doesn't need the `serde` feature, and states from older
+    /// versions of the crate are recognized.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC);
+        out.push(VERSION);
+        write_config(&mut out, &self.config);
+        out.extend_from_slice(&self.program_counter.to_le_bytes());
+        out.extend_from_slice(&self.index_register.to_le_bytes());
+        out.extend_from_slice(&self.registers);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend_from_slice(&(self.stack.len() as u32).to_le_bytes());
+        for address in self.stack() {
+            out.extend_from_slice(&address.to_le_bytes());
+        }
+        write_vec(&mut out, &self.memory);
+        write_vec(&mut out, &self.program);
+        out.push(self.hires as u8);
+        out.push(self.selected_planes);
+        for row in &self.plane_rows {
+            out.extend_from_slice(&row.to_le_bytes());
+        }
+        out.extend(self.keyboard.iter().map(|pressed| *pressed as u8));
+        out.push(self.waiting_for_key as u8);
+        out.push(self.key_pressed_while_waiting.unwrap_or(NO_KEY));
+        out.push(self.halted as u8);
+        out.push(self.drawn_this_frame as u8);
+        out.extend_from_slice(&self.audio_pattern);
+        out.push(self.audio_pitch);
+        out.extend_from_slice(&self.rpl_flags);
+        out.extend_from_slice(&self.rng.state().to_le_bytes());
+        out.extend_from_slice(&self.instructions_executed.to_le_bytes());
+        out
+    }
+
+    /// Loads a state created by `save_state`, leaving the machine untouched if it can't be read.
+    /// The sound callback, rewind points and breakpoints are kept.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let mut reader = Reader { bytes };
+        if reader.bytes(MAGIC.len()).map_err(|_| StateError::BadMagic)? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version == 0 || version > VERSION {
+            return Err(StateError::UnsupportedVersion { version });
+        }
+
+        let mut loaded = Chip8::with_config(read_config(&mut reader)?);
+        loaded.program_counter = reader.u16()?;
+        loaded.index_register = reader.u16()?;
+        loaded.registers = reader.array()?;
+        loaded.delay_timer = reader.u8()?;
+        loaded.sound_timer = reader.u8()?;
+        let stack_depth = reader.u32()? as usize;
+        if stack_depth > loaded.config.stack_limit {
+            return Err(StateError::Invalid { field: "stack depth" });
+        }
+        let stack = (0..stack_depth).map(|_| reader.u16()).collect::<Result<Vec<_>, _>>()?;
+        loaded.stack = Stack::from_slice(&stack).ok_or(StateError::Invalid { field: "stack depth" })?;
+        let memory = reader.vec()?;
+        if memory.len() != loaded.memory.len() {
+            return Err(StateError::Invalid { field: "memory size" });
+        }
+        loaded.memory = memory;
+        loaded.program = reader.vec()?;
+        if loaded.program.len() > loaded.memory.len() - 0x200 {
+            return Err(StateError::Invalid { field: "program" });
+        }
+        loaded.hires = reader.bool("resolution")?;
+        loaded.selected_planes = reader.u8()?;
+        if loaded.selected_planes > 3 {
+            return Err(StateError::Invalid { field: "plane selection" });
+        }
+        for row in loaded.plane_rows.iter_mut() {
+            *row = reader.u128()?;
+        }
+        // Pixels past the right edge can only come from corrupted data
+        if !loaded.hires && loaded.plane_rows.iter().any(|row| row >> 64 != 0) {
+            return Err(StateError::Invalid { field: "display" });
+        }
+        for pressed in loaded.keyboard.iter_mut() {
+            *pressed = reader.bool("keyboard")?;
+        }
+        loaded.waiting_for_key = reader.bool("key wait")?;
+        loaded.key_pressed_while_waiting = match reader.u8()? {
+            NO_KEY => None,
+            key @ 0..=0xf => Some(key),
+            _ => return Err(StateError::Invalid { field: "key wait" }),
+        };
+        loaded.halted = reader.bool("halted")?;
+        loaded.drawn_this_frame = reader.bool("display wait")?;
+        loaded.audio_pattern = reader.array()?;
+        loaded.audio_pitch = reader.u8()?;
+        loaded.rpl_flags = reader.array()?;
+        loaded.rng = Rng::from_state(reader.u64()?).ok_or(StateError::Invalid { field: "random state" })?;
+        if version >= 2 {
+            loaded.instructions_executed = reader.u64()?;
+        }
+        if !reader.bytes.is_empty() {
+            return Err(StateError::Invalid { field: "length" });
+        }
+
+        loaded.dirty_rows = u64::MAX;
+        loaded.needs_redraw = true;
+        let was_beeping = self.is_beeping();
+        loaded.sound_callback = core::mem::take(&mut self.sound_callback);
+        loaded.rewind = core::mem::take(&mut self.rewind);
+        loaded.debugger = core::mem::take(&mut self.debugger);
+        *self = loaded;
+        self.notify_beep_change(was_beeping);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SCREEN_HEIGHT;
+
+    /// Runs the IBM logo ROM partway with some of every kind of state set.
+    fn example() -> Chip8 {
+        let mut emu = Chip8::with_rng(3);
+        emu.load(include_bytes!("../../roms/IBM Logo.ch8")).unwrap();
+        for _ in 0..15 {
+            emu.step().unwrap();
+        }
+        emu.stack = Stack::from_slice(&[0x234, 0x456]).unwrap();
+        emu.delay_timer = 20;
+        emu.sound_timer = 10;
+        emu.press_key(0xa);
+        emu.set_rpl_flags(&[9, 8, 7]);
+        emu.audio_pattern[3] = 0x5a;
+        emu
+    }
+
+    #[test]
+    fn round_trip() {
+        let emu = example();
+        let mut loaded = Chip8::with_variant(Variant::XoChip);
+        loaded.load_state(&emu.save_state()).unwrap();
+        assert_eq!(loaded, emu);
+        assert_eq!(loaded.get_display(), emu.get_display());
+        assert!(loaded.needs_redraw());
+        assert!(loaded.take_dirty_rows().eq(0..SCREEN_HEIGHT));
+
+        let emu = Chip8::new();
+        let mut loaded = Chip8::with_variant(Variant::XoChip);
+        loaded.load_state(&emu.save_state()).unwrap();
+        assert_eq!(loaded.variant(), Variant::Modern);
+    }
+
+    #[test]
+    fn matches_fixture() {
+        // Add a new fixture only when deliberately changing the format, along with VERSION
+        let fixture = include_bytes!("../fixtures/state-v2.bin");
+        assert!(example().save_state() == fixture[..], "the save state format changed");
+        let mut loaded = Chip8::new();
+        loaded.load_state(fixture).unwrap();
+        assert_eq!(loaded, example());
+    }
+
+    #[test]
+    fn loads_version_1() {
+        let mut loaded = Chip8::new();
+        loaded.load_state(include_bytes!("../fixtures/state-v1.bin")).unwrap();
+        let mut expected = example();
+        expected.instructions_executed = 0;
+        // It was saved when the default machine was labelled Chip8
+        expected.config.variant = Variant::Chip8;
+        assert_eq!(loaded, expected);
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        let state = example().save_state();
+        let mut emu = Chip8::new();
+        let before = emu.save_state();
+        assert_eq!(emu.load_state(&[]), Err(StateError::BadMagic));
+        assert_eq!(emu.load_state(b"PNG\x01 and more"), Err(StateError::BadMagic));
+
+        let mut unknown = state.clone();
+        unknown[4] = 0;
+        assert_eq!(emu.load_state(&unknown), Err(StateError::UnsupportedVersion { version: 0 }));
+        let mut newer = state.clone();
+        newer[4] = VERSION + 1;
+        assert_eq!(emu.load_state(&newer), Err(StateError::UnsupportedVersion { version: VERSION + 1 }));
+
+        for len in [5, 20, state.len() / 2, state.len() - 1] {
+            assert_eq!(emu.load_state(&state[..len]), Err(StateError::Truncated), "{} bytes", len);
+        }
+
+        let mut longer = state.clone();
+        longer.push(0);
+        assert_eq!(emu.load_state(&longer), Err(StateError::Invalid { field: "length" }));
+
+        let mut bad_variant = state.clone();
+        bad_variant[5] = 7;
+        assert_eq!(emu.load_state(&bad_variant), Err(StateError::Invalid { field: "variant" }));
+
+        // Nothing was changed by the failed loads
+        assert_eq!(emu.save_state(), before);
+    }
+}
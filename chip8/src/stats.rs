@@ -0,0 +1,129 @@
+//! Counting the executed instructions by kind, to profile programs.
+
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+use crate::disasm::InstructionKind;
+use crate::Chip8;
+
+/// The instructions executed by a machine, from `Chip8::stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Every instruction executed since the machine was created or reset, the same as
+    /// `Chip8::cycles`. Counted even while collecting stats is off.
+    pub total: u64,
+    /// The instructions executed since stats were turned on or last reset.
+    pub since_reset: u64,
+    /// How many instructions of each kind were executed since stats were turned on or last reset,
+    /// indexed by `kind as usize`.
+    pub counts: [u64; InstructionKind::COUNT],
+}
+
+impl Stats {
+    /// Returns how many instructions of `kind` were executed.
+    pub fn count(&self, kind: InstructionKind) -> u64 {
+        self.counts[kind as usize]
+    }
+
+    /// Returns the kinds that were executed with their counts, most executed first.
+    pub fn histogram(&self) -> Vec<(InstructionKind, u64)> {
+        let mut histogram: Vec<_> = InstructionKind::ALL.iter()
+            .map(|&kind| (kind, self.count(kind)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        histogram.sort_by_key(|&(_, count)| Reverse(count));
+        histogram
+    }
+}
+
+/// The counters kept while stats are on, boxed so they don't weigh on the machine while off.
+#[derive(Debug, Clone)]
+pub(crate) struct Counters {
+    since_reset: u64,
+    counts: [u64; InstructionKind::COUNT],
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Counters { since_reset: 0, counts: [0; InstructionKind::COUNT] }
+    }
+}
+
+impl Counters {
+    pub(crate) fn record(&mut self, kind: InstructionKind) {
+        self.since_reset += 1;
+        self.counts[kind as usize] += 1;
+    }
+}
+
+impl Chip8 {
+    /// Turns counting the executed instructions by kind on or off. It's off by default so
+    /// execution doesn't pay for it, and turning it on starts from zero.
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        if enabled != self.debugger.stats.is_some() {
+            self.debugger.stats = enabled.then(Default::default);
+        }
+    }
+
+    /// Returns the instructions executed so far. While stats are off only `total` is counted.
+    pub fn stats(&self) -> Stats {
+        let counters = self.debugger.stats.as_deref().cloned().unwrap_or_default();
+        Stats { total: self.instructions_executed, since_reset: counters.since_reset, counts: counters.counts }
+    }
+
+    /// Sets the counts of `stats` back to zero, except for the total.
+    pub fn reset_stats(&mut self) {
+        if let Some(counters) = self.debugger.stats.as_deref_mut() {
+            *counters = Counters::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn counts_a_loop() {
+        let program = assemble("
+                LD V1, 10
+            loop:
+                ADD V0, 2
+                ADD V1, 0xff
+                SE V1, 0
+                JP loop
+            end:
+                JP end
+        ").unwrap();
+        let mut emu = Chip8::new();
+        emu.load(&program).unwrap();
+        emu.step().unwrap();
+        emu.set_stats_enabled(true);
+        for _ in 0..40 {
+            emu.step().unwrap();
+        }
+        let stats = emu.stats();
+        assert_eq!((stats.total, stats.since_reset), (41, 40));
+        assert_eq!(stats.count(InstructionKind::AddImm), 20);
+        assert_eq!(stats.count(InstructionKind::SkipEqImm), 10);
+        // 9 jumps back to the loop, then 1 to the end of the program
+        assert_eq!(stats.count(InstructionKind::Jump), 10);
+        assert_eq!(stats.count(InstructionKind::LoadImm), 0);
+        assert_eq!(
+            stats.histogram(),
+            [(InstructionKind::AddImm, 20), (InstructionKind::Jump, 10), (InstructionKind::SkipEqImm, 10)]
+        );
+
+        emu.reset_stats();
+        emu.step().unwrap();
+        let stats = emu.stats();
+        assert_eq!((stats.total, stats.since_reset), (42, 1));
+        assert_eq!(stats.count(InstructionKind::Jump), 1);
+
+        emu.set_stats_enabled(false);
+        emu.step().unwrap();
+        let stats = emu.stats();
+        assert_eq!((stats.total, stats.since_reset), (43, 0));
+    }
+}
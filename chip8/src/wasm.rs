@@ -0,0 +1,89 @@
+//! Bindings for running the interpreter in a browser through `wasm-bindgen`, see `web/` for a page
+//! using them.
+
+use alloc::vec::Vec;
+
+use wasm_bindgen::prelude::*;
+
+use crate::Chip8;
+
+/// A `Chip8` that can be driven from JavaScript. Errors are thrown as JavaScript `Error`s.
+#[wasm_bindgen]
+pub struct WasmChip8 {
+    chip8: Chip8,
+}
+
+impl Default for WasmChip8 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl WasmChip8 {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmChip8 {
+        WasmChip8 { chip8: Chip8::new() }
+    }
+
+    /// Loads a program from a `Uint8Array`.
+    pub fn load(&mut self, rom: &[u8]) -> Result<(), JsError> {
+        Ok(self.chip8.load(rom)?)
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> Result<(), JsError> {
+        self.chip8.step()?;
+        Ok(())
+    }
+
+    /// Decrements the timers, meant to be called 60 times per second.
+    pub fn tick_timers(&mut self) {
+        self.chip8.tick_timers();
+    }
+
+    pub fn press_key(&mut self, key: u8) {
+        self.chip8.press_key(key);
+    }
+
+    pub fn unpress_key(&mut self, key: u8) {
+        self.chip8.unpress_key(key);
+    }
+
+    /// Returns whether the sound timer is running.
+    pub fn is_beeping(&self) -> bool {
+        self.chip8.is_beeping()
+    }
+
+    /// Returns the display as a `Uint8Array`, 1 for lit pixels and 0 for clear ones,
+    /// `display_width` pixels per row.
+    pub fn display(&self) -> Vec<u8> {
+        self.chip8.get_display().iter().map(|&pixel| pixel as u8).collect()
+    }
+
+    pub fn display_width(&self) -> usize {
+        self.chip8.display_width()
+    }
+
+    pub fn display_height(&self) -> usize {
+        self.chip8.display_height()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_a_program() {
+        let mut emu = WasmChip8::new();
+        emu.load(&[0xa0, 0x50, 0xd0, 0x05]).unwrap(); // Draw the 0 of the font at (0, 0)
+        emu.step().unwrap();
+        emu.step().unwrap();
+        let display = emu.display();
+        assert_eq!(display.len(), emu.display_width() * emu.display_height());
+        assert_eq!(display[..5], [1, 1, 1, 1, 0]);
+        assert_eq!(display[64..69], [1, 0, 0, 1, 0]);
+        assert_eq!(display.iter().filter(|&&pixel| pixel == 1).count(), 14);
+    }
+}
@@ -0,0 +1,72 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SizedSample};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const FREQUENCY: f32 = 440.0;
+const VOLUME: f32 = 0.2;
+// Time the tone takes to fade in or out, switching instantly makes an audible click
+const RAMP_SECONDS: f32 = 0.005;
+
+/// Plays a square wave on the default output device while it is told to beep.
+pub struct Beeper {
+    beeping: Arc<AtomicBool>,
+    // The tone stops when the stream is dropped
+    _stream: cpal::Stream,
+}
+
+impl Beeper {
+    /// Opens the default output device, returns `None` if there is no usable one.
+    pub fn new() -> Option<Self> {
+        let device = cpal::default_host().default_output_device()?;
+        let supported = device.default_output_config().ok()?;
+        let config = supported.config();
+        let beeping = Arc::new(AtomicBool::new(false));
+        let stream = match supported.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, beeping.clone()),
+            cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, beeping.clone()),
+            cpal::SampleFormat::U16 => build_stream::<u16>(&device, &config, beeping.clone()),
+            _ => return None,
+        }.ok()?;
+        stream.play().ok()?;
+        Some(Beeper { beeping, _stream: stream })
+    }
+
+    /// Starts or stops the tone.
+    pub fn set_beeping(&self, beeping: bool) {
+        self.beeping.store(beeping, Ordering::Relaxed);
+    }
+}
+
+fn build_stream<T>(device: &cpal::Device, config: &cpal::StreamConfig, beeping: Arc<AtomicBool>) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let sample_rate = config.sample_rate.0 as f32;
+    let channels = config.channels as usize;
+    let ramp_step = VOLUME / (sample_rate * RAMP_SECONDS);
+    let mut phase = 0.0;
+    let mut amplitude = 0.0;
+
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let target = if beeping.load(Ordering::Relaxed) { VOLUME } else { 0.0 };
+            for frame in data.chunks_mut(channels) {
+                amplitude = if amplitude < target {
+                    (amplitude + ramp_step).min(target)
+                } else {
+                    (amplitude - ramp_step).max(target)
+                };
+                phase = (phase + FREQUENCY / sample_rate) % 1.0;
+                let value = if phase < 0.5 { amplitude } else { -amplitude };
+                let sample = T::from_sample(value);
+                for channel in frame.iter_mut() {
+                    *channel = sample;
+                }
+            }
+        },
+        |err| eprintln!("Audio stream error: {}", err),
+        None,
+    )
+}
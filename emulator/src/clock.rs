@@ -0,0 +1,57 @@
+use std::time::{Duration, Instant};
+
+const TIMER_HZ: u32 = 60;
+// Elapsed time is capped so a stall (e.g. dragging the window) doesn't make the CPU race to catch up
+const MAX_CATCH_UP: Duration = Duration::from_millis(100);
+
+/// Tracks wall clock time to work out how many CPU cycles and timer ticks are due, so the CPU runs at
+/// its own rate while the timers always tick 60 times a second.
+pub struct Clock {
+    cpu_hz: u32,
+    last_update: Instant,
+    cpu_time: Duration,
+    timer_time: Duration,
+}
+
+impl Clock {
+    pub fn new(cpu_hz: u32) -> Self {
+        Clock { cpu_hz, last_update: Instant::now(), cpu_time: Duration::ZERO, timer_time: Duration::ZERO }
+    }
+
+    pub fn cpu_hz(&self) -> u32 {
+        self.cpu_hz
+    }
+
+    pub fn set_cpu_hz(&mut self, cpu_hz: u32) {
+        self.cpu_hz = cpu_hz;
+    }
+
+    /// Returns the number of CPU cycles and timer ticks that are due since the last call.
+    pub fn update(&mut self) -> (u32, u32) {
+        let now = Instant::now();
+        let elapsed = (now - self.last_update).min(MAX_CATCH_UP);
+        self.last_update = now;
+        self.cpu_time += elapsed;
+        self.timer_time += elapsed;
+
+        let cycles = Self::take_periods(&mut self.cpu_time, self.cpu_hz);
+        let timer_ticks = Self::take_periods(&mut self.timer_time, TIMER_HZ);
+        (cycles, timer_ticks)
+    }
+
+    /// Returns the number of CPU cycles in frame `frame` when every frame is exactly one timer tick
+    /// long, for runs that have to repeat exactly whatever the wall clock does.
+    pub fn frame_cycles(&self, frame: u64) -> u32 {
+        let hz = self.cpu_hz as u64;
+        let per_tick = TIMER_HZ as u64;
+        ((frame + 1) * hz / per_tick - frame * hz / per_tick) as u32
+    }
+
+    /// Removes all the whole periods at `hz` from `time`, returning how many there were.
+    fn take_periods(time: &mut Duration, hz: u32) -> u32 {
+        let period = Duration::from_secs(1) / hz;
+        let count = (time.as_nanos() / period.as_nanos()) as u32;
+        *time -= period * count;
+        count
+    }
+}
@@ -0,0 +1,78 @@
+use gilrs::{Button, EventType, Gilrs};
+
+use crate::keymap::{Keymap, PadButton};
+
+/// Reads the connected game controllers and presses the CHIP-8 keys their buttons are mapped to.
+pub struct Gamepads {
+    gilrs: Gilrs,
+}
+
+impl Gamepads {
+    /// Starts listening for controllers, returns `None` if the platform doesn't support them.
+    pub fn new() -> Option<Self> {
+        match Gilrs::new() {
+            Ok(gilrs) => {
+                for (_, gamepad) in gilrs.gamepads() {
+                    println!("Gamepad {} connected", gamepad.name());
+                }
+                Some(Gamepads { gilrs })
+            },
+            Err(err) => {
+                eprintln!("Unable to read gamepads: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Updates `keys`, bit `n` set while CHIP-8 key `n` is held, with the button presses since the
+    /// last call. Meant to be called every frame.
+    pub fn poll(&mut self, keymap: &Keymap, keys: &mut u16) {
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(key) = pad_button(button).and_then(|button| keymap.get_button(button)) {
+                        *keys |= 1 << key;
+                    }
+                },
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(key) = pad_button(button).and_then(|button| keymap.get_button(button)) {
+                        *keys &= !(1 << key);
+                    }
+                },
+                EventType::Connected => {
+                    println!("Gamepad {} connected", self.gilrs.gamepad(event.id).name());
+                },
+                EventType::Disconnected => {
+                    println!("Gamepad {} disconnected", self.gilrs.gamepad(event.id).name());
+                    // Its buttons won't be released anymore, so release every key a button could hold
+                    for key in 0..16 {
+                        if keymap.is_button_key(key) {
+                            *keys &= !(1 << key);
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+/// The mappable button `button` is, if any.
+fn pad_button(button: Button) -> Option<PadButton> {
+    let button = match button {
+        Button::DPadUp => PadButton::DPadUp,
+        Button::DPadDown => PadButton::DPadDown,
+        Button::DPadLeft => PadButton::DPadLeft,
+        Button::DPadRight => PadButton::DPadRight,
+        Button::South => PadButton::South,
+        Button::East => PadButton::East,
+        Button::North => PadButton::North,
+        Button::West => PadButton::West,
+        Button::LeftTrigger => PadButton::LeftTrigger,
+        Button::RightTrigger => PadButton::RightTrigger,
+        Button::Select => PadButton::Select,
+        Button::Start => PadButton::Start,
+        _ => return None,
+    };
+    Some(button)
+}
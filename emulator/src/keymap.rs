@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::fs;
+
+use winit::keyboard::KeyCode;
+
+/// The keypad layout used without a mapping file, the left side of a QWERTY keyboard:
+/// 1 2 3 C / 4 5 6 D / 7 8 9 E / A 0 B F on 1234 / QWER / ASDF / ZXCV.
+const DEFAULT_LAYOUT: [(KeyCode, u8); 16] = [
+    (KeyCode::Digit1, 0x1), (KeyCode::Digit2, 0x2), (KeyCode::Digit3, 0x3), (KeyCode::Digit4, 0xc),
+    (KeyCode::KeyQ, 0x4), (KeyCode::KeyW, 0x5), (KeyCode::KeyE, 0x6), (KeyCode::KeyR, 0xd),
+    (KeyCode::KeyA, 0x7), (KeyCode::KeyS, 0x8), (KeyCode::KeyD, 0x9), (KeyCode::KeyF, 0xe),
+    (KeyCode::KeyZ, 0xa), (KeyCode::KeyX, 0x0), (KeyCode::KeyC, 0xb), (KeyCode::KeyV, 0xf),
+];
+
+/// The gamepad layout used without a mapping file. The D-pad is on 5 7 8 9 like WASD, which most
+/// recent games use for directions, and the face buttons on the keys around them.
+const DEFAULT_PAD_LAYOUT: [(PadButton, u8); 8] = [
+    (PadButton::DPadUp, 0x5), (PadButton::DPadLeft, 0x7), (PadButton::DPadDown, 0x8), (PadButton::DPadRight, 0x9),
+    (PadButton::South, 0x6), (PadButton::East, 0x4), (PadButton::West, 0xe), (PadButton::North, 0xd),
+];
+
+/// Keys used by the emulator itself, which can't be mapped to the keypad.
+const RESERVED: [KeyCode; 11] = [
+    KeyCode::Escape, KeyCode::F5, KeyCode::F6, KeyCode::F7, KeyCode::F11, KeyCode::F12,
+    KeyCode::Minus, KeyCode::Equal, KeyCode::KeyP, KeyCode::KeyN, KeyCode::Backspace,
+];
+
+/// The gamepad buttons that can be mapped, named like gilrs names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PadButton {
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    RightTrigger,
+    Select,
+    Start,
+}
+
+impl PadButton {
+    fn parse(name: &str) -> Option<PadButton> {
+        use PadButton::*;
+
+        let button = match name {
+            "DPadUp" => DPadUp,
+            "DPadDown" => DPadDown,
+            "DPadLeft" => DPadLeft,
+            "DPadRight" => DPadRight,
+            "South" => South,
+            "East" => East,
+            "North" => North,
+            "West" => West,
+            "LeftTrigger" => LeftTrigger,
+            "RightTrigger" => RightTrigger,
+            "Select" => Select,
+            "Start" => Start,
+            _ => return None,
+        };
+        Some(button)
+    }
+}
+
+/// Maps physical keys and gamepad buttons to the 16 CHIP-8 keys.
+pub struct Keymap {
+    keys: HashMap<KeyCode, u8>,
+    buttons: HashMap<PadButton, u8>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap { keys: HashMap::from(DEFAULT_LAYOUT), buttons: HashMap::from(DEFAULT_PAD_LAYOUT) }
+    }
+}
+
+impl Keymap {
+    /// Reads a mapping file, see `keys.example` for the format.
+    pub fn load(path: &str) -> Result<Keymap, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Unable to read {}: {}", path, err))?;
+        Keymap::parse(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    /// Parses a mapping with one `<CHIP-8 key> = <physical key>` line per key, where the CHIP-8 key
+    /// is a hex digit and the physical key is named after its position on a US keyboard, like
+    /// `KeyW` or `Digit1`, or is a gamepad button like `DPadUp` or `South`. `#` starts a comment.
+    /// The keyboard and the gamepad keep their default layout unless the mapping has lines for
+    /// them, and then the keys and buttons that aren't listed do nothing.
+    pub fn parse(text: &str) -> Result<Keymap, String> {
+        let mut keys = HashMap::new();
+        let mut buttons = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            let (chip8_key, name) = line.split_once('=')
+                .ok_or_else(|| error(format!("expected `<CHIP-8 key> = <key>`, got `{}`", line)))?;
+            let chip8_key = u8::from_str_radix(chip8_key.trim(), 16).ok()
+                .filter(|&key| key < 16)
+                .ok_or_else(|| error(format!("`{}` is not a CHIP-8 key, expected 0 to F", chip8_key.trim())))?;
+            let name = name.trim();
+            let mapped_twice = if let Some(keycode) = parse_keycode(name) {
+                if RESERVED.contains(&keycode) {
+                    return Err(error(format!("{} is used by the emulator", name)));
+                }
+                keys.insert(keycode, chip8_key).is_some()
+            } else if let Some(button) = PadButton::parse(name) {
+                buttons.insert(button, chip8_key).is_some()
+            } else {
+                return Err(error(format!("unknown key `{}`", name)));
+            };
+            if mapped_twice {
+                return Err(error(format!("{} is mapped twice", name)));
+            }
+        }
+        let default = Keymap::default();
+        Ok(Keymap {
+            keys: if keys.is_empty() { default.keys } else { keys },
+            buttons: if buttons.is_empty() { default.buttons } else { buttons },
+        })
+    }
+
+    /// Returns the CHIP-8 key `keycode` is mapped to.
+    pub fn get(&self, keycode: KeyCode) -> Option<u8> {
+        self.keys.get(&keycode).copied()
+    }
+
+    /// Returns the CHIP-8 key `button` is mapped to.
+    #[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+    pub fn get_button(&self, button: PadButton) -> Option<u8> {
+        self.buttons.get(&button).copied()
+    }
+
+    /// Returns whether a gamepad button is mapped to the CHIP-8 key `key`.
+    #[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+    pub fn is_button_key(&self, key: u8) -> bool {
+        self.buttons.values().any(|&mapped| mapped == key)
+    }
+}
+
+/// Parses the name of a key as winit spells it. Only keys that make sense for a keypad are known.
+fn parse_keycode(name: &str) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    const LETTERS: [KeyCode; 26] = [
+        KeyA, KeyB, KeyC, KeyD, KeyE, KeyF, KeyG, KeyH, KeyI, KeyJ, KeyK, KeyL, KeyM,
+        KeyN, KeyO, KeyP, KeyQ, KeyR, KeyS, KeyT, KeyU, KeyV, KeyW, KeyX, KeyY, KeyZ,
+    ];
+    const DIGITS: [KeyCode; 10] = [Digit0, Digit1, Digit2, Digit3, Digit4, Digit5, Digit6, Digit7, Digit8, Digit9];
+    const NUMPAD: [KeyCode; 10] = [
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    ];
+
+    let single = |suffix: &str, first: char| {
+        let mut chars = suffix.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c >= first => Some(c as usize - first as usize),
+            _ => None,
+        }
+    };
+    if let Some(letter) = name.strip_prefix("Key") {
+        return single(letter, 'A').and_then(|i| LETTERS.get(i).copied());
+    }
+    if let Some(digit) = name.strip_prefix("Digit") {
+        return single(digit, '0').and_then(|i| DIGITS.get(i).copied());
+    }
+    // The other numpad keys like `NumpadAdd` are named below
+    if let Some(i) = name.strip_prefix("Numpad").and_then(|digit| single(digit, '0')) {
+        return NUMPAD.get(i).copied();
+    }
+    let keycode = match name {
+        "ArrowUp" => ArrowUp,
+        "ArrowDown" => ArrowDown,
+        "ArrowLeft" => ArrowLeft,
+        "ArrowRight" => ArrowRight,
+        "Space" => Space,
+        "Enter" => Enter,
+        "Tab" => Tab,
+        "ShiftLeft" => ShiftLeft,
+        "ShiftRight" => ShiftRight,
+        "ControlLeft" => ControlLeft,
+        "ControlRight" => ControlRight,
+        "Comma" => Comma,
+        "Period" => Period,
+        "Semicolon" => Semicolon,
+        "Quote" => Quote,
+        "Slash" => Slash,
+        "Backslash" => Backslash,
+        "BracketLeft" => BracketLeft,
+        "BracketRight" => BracketRight,
+        "Backquote" => Backquote,
+        "NumpadAdd" => NumpadAdd,
+        "NumpadSubtract" => NumpadSubtract,
+        "NumpadMultiply" => NumpadMultiply,
+        "NumpadDivide" => NumpadDivide,
+        "NumpadDecimal" => NumpadDecimal,
+        "NumpadEnter" => NumpadEnter,
+        _ => return None,
+    };
+    Some(keycode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys_and_buttons() {
+        let keymap = Keymap::parse("
+            # Arrows for movement
+            5 = ArrowUp
+            8 = ArrowDown   # and a comment after
+            a = KeyW
+            A = Numpad7
+            F = South
+        ").unwrap();
+        assert_eq!(keymap.get(KeyCode::ArrowUp), Some(0x5));
+        assert_eq!(keymap.get(KeyCode::ArrowDown), Some(0x8));
+        // Several keys can press the same CHIP-8 key
+        assert_eq!(keymap.get(KeyCode::KeyW), Some(0xa));
+        assert_eq!(keymap.get(KeyCode::Numpad7), Some(0xa));
+        assert_eq!(keymap.get_button(PadButton::South), Some(0xf));
+        // Listing some keys and buttons unmaps the rest of their defaults
+        assert_eq!(keymap.get(KeyCode::Digit1), None);
+        assert_eq!(keymap.get_button(PadButton::DPadUp), None);
+        assert!(keymap.is_button_key(0xf));
+        assert!(!keymap.is_button_key(0x5));
+    }
+
+    #[test]
+    fn falls_back_to_the_default_layouts() {
+        let keymap = Keymap::parse("# Nothing but comments\n\n").unwrap();
+        assert_eq!(keymap.get(KeyCode::Digit1), Some(0x1));
+        assert_eq!(keymap.get(KeyCode::KeyV), Some(0xf));
+        assert_eq!(keymap.get_button(PadButton::DPadUp), Some(0x5));
+
+        // Only the keyboard is mapped, so the gamepad keeps its layout, and the other way around
+        let keymap = Keymap::parse("0 = Space").unwrap();
+        assert_eq!(keymap.get(KeyCode::KeyX), None);
+        assert_eq!(keymap.get_button(PadButton::South), Some(0x6));
+        let keymap = Keymap::parse("0 = Start").unwrap();
+        assert_eq!(keymap.get(KeyCode::KeyX), Some(0x0));
+        assert_eq!(keymap.get_button(PadButton::South), None);
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let error = |text| Keymap::parse(text).err().unwrap();
+        assert_eq!(error("1 = KeyQ\n2 = KeyP"), "line 2: KeyP is used by the emulator");
+        assert_eq!(error("1 = KeyN"), "line 1: KeyN is used by the emulator");
+        // The other reserved keys aren't keypad keys, so they aren't known at all
+        assert_eq!(error("1 = F5"), "line 1: unknown key `F5`");
+        assert_eq!(error("1 = KeyQ\n2 = KeyQ"), "line 2: KeyQ is mapped twice");
+        assert_eq!(error("1 = South\n\n2 = South"), "line 3: South is mapped twice");
+        assert_eq!(error("10 = KeyQ"), "line 1: `10` is not a CHIP-8 key, expected 0 to F");
+        assert_eq!(error("G = KeyQ"), "line 1: `G` is not a CHIP-8 key, expected 0 to F");
+        assert_eq!(error("1 = Hyper"), "line 1: unknown key `Hyper`");
+        assert_eq!(error("1 KeyQ"), "line 1: expected `<CHIP-8 key> = <key>`, got `1 KeyQ`");
+    }
+
+    #[test]
+    fn parses_key_names() {
+        assert_eq!(parse_keycode("KeyA"), Some(KeyCode::KeyA));
+        assert_eq!(parse_keycode("KeyZ"), Some(KeyCode::KeyZ));
+        assert_eq!(parse_keycode("Digit0"), Some(KeyCode::Digit0));
+        assert_eq!(parse_keycode("Numpad9"), Some(KeyCode::Numpad9));
+        assert_eq!(parse_keycode("NumpadEnter"), Some(KeyCode::NumpadEnter));
+        assert_eq!(parse_keycode("ArrowLeft"), Some(KeyCode::ArrowLeft));
+        for name in ["Key", "Keya", "KeyAB", "Key[", "Digit", "Digit10", "Digit:", "Numpad", "arrowleft", ""] {
+            assert_eq!(parse_keycode(name), None, "{}", name);
+        }
+    }
+}
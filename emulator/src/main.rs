@@ -1,150 +1,376 @@
-use chip8::Chip8;
-use softbuffer::Surface;
+use chip8::{Chip8, SCREEN_HEIGHT, SCREEN_WIDTH};
+use softbuffer::{Rect, Surface};
 use std::num::NonZeroU32;
+use std::ops::Range;
 use std::rc::Rc;
+use std::path::Path;
 use std::{env, fs};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use clock::Clock;
+use meter::SpeedMeter;
+use keymap::Keymap;
+use replay::{Recorder, Replay};
 use std::thread::sleep;
-use winit::dpi::LogicalSize;
+use winit::dpi::{LogicalSize, PhysicalSize};
 use winit::event::{Event, WindowEvent, ElementState};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Window, WindowBuilder};
+use winit::window::{Fullscreen, Window, WindowBuilder};
 use winit::keyboard::{KeyCode, PhysicalKey};
 
-const SCALE: usize = 15; // 15x scale to the display
-                         // const SCALE: usize = 1; // 15x scale to the display
-const SCREEN_WIDTH: usize = 64;
-// const SCREEN_HEIGHT: usize = 32;
-const SCALED_WIDTH: usize = 64 * SCALE;
-const SCALED_HEIGHT: usize = 32 * SCALE;
-const TICKS_PER_FRAME: u8 = 10;
+#[cfg(feature = "audio")]
+mod audio;
+mod clock;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod keymap;
+mod meter;
+mod replay;
 
-fn draw_screen(surface: &mut Surface<Rc<Window>, Rc<Window>>, emulator: &mut Chip8) {
-    let mut buffer = surface.buffer_mut().unwrap();
-    let display = emulator.get_display();
-    let dark_gray = 0x3a3b3c;
-    let light_gray = 0xb0b3b8;
+const SCALE: usize = 15; // the window starts at 15x scale, and can be resized from there
+const SCALED_WIDTH: usize = SCREEN_WIDTH * SCALE;
+const SCALED_HEIGHT: usize = SCREEN_HEIGHT * SCALE;
+const CPU_HZ: u32 = 700; // default speed, set with --cpu-hz and adjustable with - and =
+const CPU_HZ_STEP: u32 = 60; // one more instruction per frame
+const MIN_CPU_HZ: u32 = 60;
+const MAX_CPU_HZ: u32 = 6000;
+const PIXEL_ON: u32 = 0x3a3b3c; // dark gray
+const PIXEL_OFF: u32 = 0xb0b3b8; // light gray
+// Colors for each XO-CHIP plane combination: none, first plane, second plane, both
+const PALETTE: [u32; 4] = [PIXEL_OFF, PIXEL_ON, 0x2d7dd2, 0x1b1c1d];
+const LETTERBOX: u32 = 0x000000; // black bars around the display when the window isn't 2:1
+// A rewind point is saved every 6 frames and 300 are kept, going back up to 30 seconds. Each point
+// is a save state of about 8 KB, so a full buffer takes about 2.4 MB
+const REWIND_INTERVAL: u64 = 6;
+const REWIND_POINTS: usize = 300;
 
-    for (index, pixel) in display.iter().enumerate() {
-        let x = index % SCREEN_WIDTH;
-        let y = index / SCREEN_WIDTH;
+/// The part of the window the display is drawn in, the largest rectangle with the display's 2:1
+/// aspect ratio centered in it. The rest of the window is filled with `LETTERBOX`.
+#[derive(Clone, Copy)]
+struct Viewport {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
 
-        let value = if *pixel { dark_gray } else { light_gray };
+impl Viewport {
+    /// Fits the display in a `window_width` by `window_height` window.
+    fn fit(window_width: usize, window_height: usize) -> Self {
+        let width = window_width.min(window_height * 2);
+        let height = width / 2;
+        Viewport { x: (window_width - width) / 2, y: (window_height - height) / 2, width, height }
+    }
 
-        for sy in 0..SCALE {
-            for sx in 0..SCALE {
-                let scaled_y = y * SCALE + sy;
-                let scaled_x = x * SCALE + sx;
+    /// The window rows showing display row `y` when the display is `height` rows tall.
+    fn rows(&self, y: usize, height: usize) -> Range<usize> {
+        let first_row = |y: usize| self.y + (y * self.height).div_ceil(height);
+        first_row(y)..first_row(y + 1)
+    }
+}
 
-                let index = scaled_y * SCALED_WIDTH + scaled_x;
-                buffer[index] = value;
+/// Draws the display in the window, which is `window_width` pixels wide and the size the
+/// surface was last resized to.
+fn draw_screen(surface: &mut Surface<Rc<Window>, Rc<Window>>, emulator: &mut Chip8, window_width: usize, window_height: usize) {
+    let mut buffer = surface.buffer_mut().unwrap();
+    let display = emulator.get_display_indexed();
+    let (width, height) = emulator.dimensions();
+    let viewport = Viewport::fit(window_width, window_height);
+    // The buffer only still holds the previous frame when its age is 1, otherwise repaint it all
+    let repaint = buffer.age() != 1;
+    let dirty_rows: Vec<usize> = emulator.take_dirty_rows().collect();
+    let rows = if repaint { (0..height).collect() } else { dirty_rows };
+    if repaint {
+        buffer.fill(LETTERBOX);
+    }
+
+    // Each window pixel samples the display pixel under it, so both resolutions fill the viewport
+    let mut damage = Vec::new();
+    for y in rows {
+        let window_rows = viewport.rows(y, height);
+        if window_rows.is_empty() {
+            continue;
+        }
+        for window_y in window_rows.clone() {
+            let row = &mut buffer[window_y * window_width + viewport.x..][..viewport.width];
+            for (window_x, value) in row.iter_mut().enumerate() {
+                let x = window_x * width / viewport.width;
+                *value = PALETTE[display[x + y * width] as usize];
             }
         }
+        damage.push(Rect {
+            x: viewport.x as u32,
+            y: window_rows.start as u32,
+            width: NonZeroU32::new(viewport.width as u32).unwrap(),
+            height: NonZeroU32::new(window_rows.len() as u32).unwrap(),
+        });
     }
 
-    emulator.was_redrawn();
-    buffer.present().unwrap();
+    if repaint || damage.is_empty() {
+        buffer.present().unwrap();
+    } else {
+        buffer.present_with_damage(&damage).unwrap();
+    }
 }
 
-/// Handles a keypress, returns whether the application should exit.
-fn handle_key(state: ElementState, key: PhysicalKey, emulator: &mut Chip8, rom: &Vec<u8>) -> bool {
-    match state {
-        ElementState::Pressed => {
-            match key {
-                PhysicalKey::Code(keycode) => {
-                    match keycode {
-                        KeyCode::Escape => {
-                            return true;
-                        },
-                        KeyCode::F5 => {
-                            emulator.reset();
-                            emulator.load(rom);
-                        },
-                        KeyCode::Digit1 => emulator.press_key(0x1),
-                        KeyCode::Digit2 => emulator.press_key(0x2),
-                        KeyCode::Digit3 => emulator.press_key(0x3),
-                        KeyCode::Digit4 => emulator.press_key(0xc),
-                        KeyCode::KeyQ => emulator.press_key(0x4),
-                        KeyCode::KeyW => emulator.press_key(0x5),
-                        KeyCode::KeyE => emulator.press_key(0x6),
-                        KeyCode::KeyR => emulator.press_key(0xd),
-                        KeyCode::KeyA => emulator.press_key(0x7),
-                        KeyCode::KeyS => emulator.press_key(0x8),
-                        KeyCode::KeyD => emulator.press_key(0x9),
-                        KeyCode::KeyF => emulator.press_key(0xe),
-                        KeyCode::KeyZ => emulator.press_key(0xa),
-                        KeyCode::KeyX => emulator.press_key(0x0),
-                        KeyCode::KeyC => emulator.press_key(0xb),
-                        KeyCode::KeyV => emulator.press_key(0xf),
-                        _ => ()
-                    }
-                },
-                _ => ()
+/// Resizes the surface to `size`, unless the window is minimized to nothing.
+fn resize_surface(surface: &mut Surface<Rc<Window>, Rc<Window>>, size: PhysicalSize<u32>) {
+    if let (Some(width), Some(height)) = (NonZeroU32::new(size.width), NonZeroU32::new(size.height)) {
+        surface.resize(width, height).unwrap();
+    }
+}
+
+/// Saves the display as `screenshot-<timestamp>.png` in the working directory.
+fn save_screenshot(emulator: &Chip8) {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default();
+    let path = format!("screenshot-{}.png", timestamp);
+    match emulator.to_image(SCALED_WIDTH / emulator.display_width(), PIXEL_ON, PIXEL_OFF).save(&path) {
+        Ok(()) => println!("Saved {}", path),
+        Err(err) => eprintln!("Unable to save {}: {}", path, err),
+    }
+}
+
+/// The window title, showing the game, the target speed and the measured speed.
+fn window_title(game: &str, cpu_hz: u32, meter: &SpeedMeter) -> String {
+    format!("Chip8 - {} - {} Hz - {} FPS - {} IPS", game, cpu_hz, meter.fps(), meter.ips())
+}
+
+/// Holds exactly the CHIP-8 keys in `keys`, bit `n` set for key `n`.
+fn set_keys(emulator: &mut Chip8, keys: u16) {
+    for key in 0..16 {
+        if keys & 1 << key != 0 {
+            emulator.press_key(key);
+        } else {
+            emulator.unpress_key(key);
+        }
+    }
+}
+
+/// A random seed for recordings that aren't given one.
+fn time_seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos() as u64).unwrap_or_default()
+}
+
+/// Where the keypad input comes from.
+enum Session {
+    /// The keyboard and gamepads.
+    Live,
+    /// The keyboard and gamepads, with the keys held on each frame written to a file.
+    Recording(Recorder),
+    /// A recording played back, live input is ignored.
+    Replaying(Replay),
+}
+
+impl Session {
+    /// Returns the keys to hold on `frame`, given the keys `held` live.
+    fn keys(&mut self, frame: u64, held: u16) -> u16 {
+        match self {
+            Session::Live => held,
+            Session::Recording(recorder) => {
+                if let Err(err) = recorder.record(frame, held) {
+                    eprintln!("Unable to record frame {}: {}", frame, err);
+                }
+                held
+            },
+            Session::Replaying(replay) => replay.keys(frame),
+        }
+    }
+
+    /// Returns whether the emulator's own key `keycode` works. A replay only matches its recording
+    /// if nothing but the recorded keys changes the machine, so recording leaves out reset, quick
+    /// load, pause and speed changes, and replaying only allows quitting.
+    fn allows(&self, keycode: KeyCode) -> bool {
+        match self {
+            Session::Live => true,
+            Session::Recording(_) => matches!(keycode, KeyCode::Escape | KeyCode::F11 | KeyCode::F12),
+            Session::Replaying(_) => keycode == KeyCode::Escape,
+        }
+    }
+}
+
+/// Handles a press of one of the emulator's own keys, returns whether the application should exit.
+fn handle_key(keycode: KeyCode, window: &Window, emulator: &mut Chip8, crashed: &mut bool, paused: &mut bool, cpu_hz: &mut u32, quick_save: &mut Option<Vec<u8>>) -> bool {
+    match keycode {
+        KeyCode::Escape => {
+            return true;
+        },
+        KeyCode::F5 => {
+            emulator.reset();
+            *crashed = false;
+        },
+        KeyCode::F6 => {
+            *quick_save = Some(emulator.snapshot());
+        },
+        KeyCode::F7 => {
+            if let Some(snapshot) = quick_save {
+                match emulator.restore(snapshot) {
+                    Ok(()) => *crashed = false,
+                    Err(err) => eprintln!("Unable to load the quick save: {}", err),
+                }
             }
         },
-        ElementState::Released => {
-            match key {
-                PhysicalKey::Code(keycode) => {
-                    match keycode {
-                        KeyCode::Digit1 => emulator.unpress_key(0x1),
-                        KeyCode::Digit2 => emulator.unpress_key(0x2),
-                        KeyCode::Digit3 => emulator.unpress_key(0x3),
-                        KeyCode::Digit4 => emulator.unpress_key(0xc),
-                        KeyCode::KeyQ => emulator.unpress_key(0x4),
-                        KeyCode::KeyW => emulator.unpress_key(0x5),
-                        KeyCode::KeyE => emulator.unpress_key(0x6),
-                        KeyCode::KeyR => emulator.unpress_key(0xd),
-                        KeyCode::KeyA => emulator.unpress_key(0x7),
-                        KeyCode::KeyS => emulator.unpress_key(0x8),
-                        KeyCode::KeyD => emulator.unpress_key(0x9),
-                        KeyCode::KeyF => emulator.unpress_key(0xe),
-                        KeyCode::KeyZ => emulator.unpress_key(0xa),
-                        KeyCode::KeyX => emulator.unpress_key(0x0),
-                        KeyCode::KeyC => emulator.unpress_key(0xb),
-                        KeyCode::KeyV => emulator.unpress_key(0xf),
-                        _ => ()
-                    }
-                },
-                _ => ()
+        // Held down, key repeat keeps going back
+        KeyCode::Backspace if emulator.rewind(1) > 0 => {
+            *crashed = false;
+        },
+        KeyCode::F11 => {
+            // The surface follows through the resize event
+            let fullscreen = window.fullscreen().is_none().then_some(Fullscreen::Borderless(None));
+            window.set_fullscreen(fullscreen);
+        },
+        KeyCode::F12 => save_screenshot(emulator),
+        KeyCode::Minus => {
+            *cpu_hz = cpu_hz.saturating_sub(CPU_HZ_STEP).max(MIN_CPU_HZ);
+        },
+        KeyCode::Equal => {
+            *cpu_hz = cpu_hz.saturating_add(CPU_HZ_STEP).min(MAX_CPU_HZ);
+        },
+        KeyCode::KeyP => {
+            *paused = !*paused;
+        },
+        KeyCode::KeyN if *paused && !*crashed => {
+            if let Err(err) = emulator.step() {
+                eprintln!("Execution stopped: {}, press F5 to restart.", err);
+                *crashed = true;
             }
-        }
+        },
+        _ => ()
     }
     false
 }
 
+/// The command line options.
+struct Options {
+    path: String,
+    mute: bool,
+    cpu_hz: u32,
+    keymap: Keymap,
+    seed: Option<u64>,
+    record: Option<String>,
+    replay: Option<String>,
+}
+
+/// Parses the command line arguments, returns an error message if they are invalid.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut path = None;
+    let mut mute = false;
+    let mut cpu_hz = CPU_HZ;
+    let mut keymap = Keymap::default();
+    let mut seed = None;
+    let mut record = None;
+    let mut replay = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mute" => mute = true,
+            "--cpu-hz" => {
+                let value = args.next().ok_or("--cpu-hz needs a value")?;
+                cpu_hz = value.parse().ok()
+                    .filter(|hz| (MIN_CPU_HZ..=MAX_CPU_HZ).contains(hz))
+                    .ok_or(format!("--cpu-hz must be between {} and {}, got {}", MIN_CPU_HZ, MAX_CPU_HZ, value))?;
+            },
+            "--keys" => {
+                let path = args.next().ok_or("--keys needs a mapping file")?;
+                keymap = Keymap::load(&path)?;
+            },
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a value")?;
+                seed = Some(value.parse().map_err(|_| format!("--seed must be a number, got {}", value))?);
+            },
+            "--record" => record = Some(args.next().ok_or("--record needs a file")?),
+            "--replay" => replay = Some(args.next().ok_or("--replay needs a file")?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => path = Some(arg),
+        }
+    }
+    let path = path.ok_or("No game given")?;
+    if record.is_some() && replay.is_some() {
+        return Err("--record and --replay can't be used together".to_string());
+    }
+    Ok(Options { path, mute, cpu_hz, keymap, seed, record, replay })
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: cargo run [game/path]");
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            println!("{}", err);
+            println!("Usage: cargo run [game/path] [--mute] [--cpu-hz N] [--keys mapping/file] [--seed N] [--record file | --replay file]");
+            return;
+        }
+    };
+    let Options { path, mute, mut cpu_hz, keymap, mut seed, record, replay } = options;
+    let program = fs::read(&path).expect("Unable to open file");
+
+    let mut session = Session::Live;
+    if let Some(replay_path) = replay {
+        // Replays run with the seed and speed they were recorded with
+        match Replay::load(&replay_path) {
+            Ok(replay) => {
+                seed = Some(replay.seed);
+                cpu_hz = replay.cpu_hz;
+                session = Session::Replaying(replay);
+            },
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        }
+    }
+    if let Some(record_path) = record {
+        let seed = *seed.get_or_insert_with(time_seed);
+        match Recorder::create(&record_path, seed, cpu_hz) {
+            Ok(recorder) => session = Session::Recording(recorder),
+            Err(err) => {
+                println!("Unable to create {}: {}", record_path, err);
+                return;
+            }
+        }
+    }
+    let mut emulator = seed.map_or_else(Chip8::new, Chip8::with_rng);
+    emulator.set_rewind_capacity(REWIND_POINTS);
+    if let Err(err) = emulator.load(&program) {
+        println!("Unable to load {}: {}", path, err);
         return;
     }
-    let program = fs::read(&args[1]).expect("Unable to open file");
-
-    let mut emulator = Chip8::new();
-    emulator.load(&program);
+    #[cfg(feature = "audio")]
+    let beeper = if mute { None } else { audio::Beeper::new() };
+    #[cfg(not(feature = "audio"))]
+    let _ = mute;
+    #[cfg(feature = "gamepad")]
+    let mut gamepads = gamepad::Gamepads::new();
+    // Shows the file name of the game in the title, without its directory
+    let game = Path::new(&path).file_name().map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+    let mut meter = SpeedMeter::new(emulator.cycles());
     let event_loop = EventLoop::new().unwrap();
     let window_size = LogicalSize::new(SCALED_WIDTH as u32, SCALED_HEIGHT as u32);
     let window = Rc::new(
         WindowBuilder::new()
-            .with_resizable(false)
+            .with_title(window_title(&game, cpu_hz, &meter))
             .with_inner_size(window_size)
             .build(&event_loop)
             .unwrap(),
     );
     let context = softbuffer::Context::new(window.clone()).unwrap();
     let mut surface = softbuffer::Surface::new(&context, window.clone()).unwrap();
-    surface
-        .resize(
-            NonZeroU32::new(SCALED_WIDTH as u32).unwrap(),
-            NonZeroU32::new(SCALED_HEIGHT as u32).unwrap(),
-        )
-        .unwrap();
+    // The surface always matches the window, in physical pixels
+    let mut surface_size = window.inner_size();
+    resize_surface(&mut surface, surface_size);
     event_loop.set_control_flow(ControlFlow::Poll);
 
     // Time controls for the frame rate
     let mut last_frame_time = Instant::now();
     let target_frame_rate = 60.0;
     let time_per_frame: u64 = ((1.0 / target_frame_rate) * 1_000.0) as u64;
+    // Set when the program hits an error, execution stays stopped until it is reset
+    let mut crashed = false;
+    // Set with the pause key, the program only advances through the step key while paused
+    let mut paused = false;
+    // Runs the CPU at its own speed, separately from the 60 Hz timers
+    let mut clock = Clock::new(cpu_hz);
+    let mut quick_save = None;
+    // The CHIP-8 keys held on the keyboard and gamepads, bit `n` for key `n`
+    let mut held_keys = 0u16;
+    // Counts the frames since the start, a recording stores the keys by frame
+    let mut frame = 0;
 
     event_loop
         .run(move |event, elwt| {
@@ -156,13 +382,43 @@ fn main() {
                     elwt.exit();
                 }
                 Event::AboutToWait => {
-                    for _ in 0..TICKS_PER_FRAME {
-                        emulator.step();
+                    #[cfg(feature = "gamepad")]
+                    if let Some(gamepads) = &mut gamepads {
+                        gamepads.poll(&keymap, &mut held_keys);
+                    }
+                    set_keys(&mut emulator, session.keys(frame, held_keys));
+                    // Time keeps passing while stopped, it just isn't spent on the program. Recordings
+                    // and replays run the same cycles on every frame instead, so they match exactly
+                    let (cycles, timer_ticks) = match session {
+                        Session::Live => clock.update(),
+                        _ => (clock.frame_cycles(frame), 1),
+                    };
+                    frame += 1;
+                    if !crashed && !paused && !emulator.is_halted() {
+                        if frame % REWIND_INTERVAL == 0 {
+                            emulator.push_rewind_point();
+                        }
+                        // Stops early when a draw waits for the next timer tick, skipping the remaining cycles
+                        if let Err(err) = emulator.step_n(cycles as usize) {
+                            eprintln!("Execution stopped: {}, press F5 to restart.", err);
+                            crashed = true;
+                        } else if emulator.is_halted() {
+                            println!("Program ended, press F5 to restart.");
+                        }
+                        for _ in 0..timer_ticks {
+                            emulator.tick_timers();
+                        }
+                    }
+                    #[cfg(feature = "audio")]
+                    if let Some(beeper) = &beeper {
+                        beeper.set_beeping(!crashed && !paused && emulator.is_beeping());
                     }
-                    emulator.tick_timers();
                     if emulator.needs_redraw() {
                         window.request_redraw();
                     }
+                    if meter.frame(emulator.cycles()) {
+                        window.set_title(&window_title(&game, clock.cpu_hz(), &meter));
+                    }
                     // Limits the frame rate to 60 fps, avoids running too fast 
                     let time_elapsed: u64 = last_frame_time.elapsed().as_millis().try_into().unwrap_or_default();
                     last_frame_time = Instant::now();
@@ -171,16 +427,41 @@ fn main() {
                     }
                 }
                 Event::WindowEvent { window_id: _, event: WindowEvent::KeyboardInput { event, .. }} => {
-                    let should_exit = handle_key(event.state, event.physical_key, &mut emulator, &program);
+                    let PhysicalKey::Code(keycode) = event.physical_key else {
+                        return;
+                    };
+                    // The keymap never holds the emulator's own keys, so they can't clash
+                    if let Some(key) = keymap.get(keycode) {
+                        match event.state {
+                            ElementState::Pressed => held_keys |= 1 << key,
+                            ElementState::Released => held_keys &= !(1 << key),
+                        }
+                        return;
+                    }
+                    if event.state == ElementState::Released || !session.allows(keycode) {
+                        return;
+                    }
+                    let mut cpu_hz = clock.cpu_hz();
+                    let should_exit = handle_key(keycode, &window, &mut emulator, &mut crashed, &mut paused, &mut cpu_hz, &mut quick_save);
+                    if cpu_hz != clock.cpu_hz() {
+                        clock.set_cpu_hz(cpu_hz);
+                        window.set_title(&window_title(&game, cpu_hz, &meter));
+                    }
                     if should_exit {
                         elwt.exit();
                     }
                 }
+                Event::WindowEvent { window_id: _, event: WindowEvent::Resized(size) } => {
+                    surface_size = size;
+                    resize_surface(&mut surface, size);
+                    window.request_redraw();
+                }
+                // Nothing to draw while the window is minimized
                 Event::WindowEvent {
                     window_id: _,
                     event: WindowEvent::RedrawRequested,
-                } => {
-                    draw_screen(&mut surface, &mut emulator);
+                } if surface_size.width > 0 && surface_size.height > 0 => {
+                    draw_screen(&mut surface, &mut emulator, surface_size.width as usize, surface_size.height as usize);
                 }
                 _ => (),
             }
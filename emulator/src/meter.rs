@@ -0,0 +1,45 @@
+use std::time::{Duration, Instant};
+
+// How long each measurement is averaged over
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Measures the frames and instructions run per second, to show whether the emulator keeps up
+/// with its target speed.
+pub struct SpeedMeter {
+    start: Instant,
+    frames: u32,
+    start_cycles: u64,
+    fps: u32,
+    ips: u64,
+}
+
+impl SpeedMeter {
+    /// Starts measuring from the machine's current cycle count.
+    pub fn new(cycles: u64) -> Self {
+        SpeedMeter { start: Instant::now(), frames: 0, start_cycles: cycles, fps: 0, ips: 0 }
+    }
+
+    /// Counts a frame, `cycles` is the machine's cycle count after it. Returns true when a new
+    /// measurement is ready.
+    pub fn frame(&mut self, cycles: u64) -> bool {
+        self.frames += 1;
+        let elapsed = self.start.elapsed();
+        if elapsed < INTERVAL {
+            return false;
+        }
+        let seconds = elapsed.as_secs_f64();
+        self.fps = (self.frames as f64 / seconds).round() as u32;
+        // The count starts over when the machine is reset
+        self.ips = (cycles.saturating_sub(self.start_cycles) as f64 / seconds).round() as u64;
+        *self = SpeedMeter { fps: self.fps, ips: self.ips, ..SpeedMeter::new(cycles) };
+        true
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.fps
+    }
+
+    pub fn ips(&self) -> u64 {
+        self.ips
+    }
+}
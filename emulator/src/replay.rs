@@ -0,0 +1,177 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+
+use crate::{MAX_CPU_HZ, MIN_CPU_HZ};
+
+// The first line of every recording
+const HEADER: &str = "# CHIP-8 input recording";
+
+/// Writes the keys held on each frame to a file, so the session can be replayed exactly with
+/// `Replay`. Only the frames where the keys change get a line:
+///
+/// ```text
+/// # CHIP-8 input recording
+/// seed 1234
+/// cpu-hz 700
+/// 0 0000
+/// 95 0020
+/// ```
+///
+/// Each frame line is the frame number and the held keys as 4 hex digits, bit `n` set while key
+/// `n` is held.
+pub struct Recorder {
+    file: File,
+    keys: Option<u16>,
+}
+
+impl Recorder {
+    /// Starts a recording of a machine seeded with `seed` and running at `cpu_hz`.
+    pub fn create(path: &str, seed: u64, cpu_hz: u32) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        writeln!(file, "{}\nseed {}\ncpu-hz {}", HEADER, seed, cpu_hz)?;
+        Ok(Recorder { file, keys: None })
+    }
+
+    /// Records the keys held on `frame`, frames are counted from 0.
+    pub fn record(&mut self, frame: u64, keys: u16) -> io::Result<()> {
+        if self.keys == Some(keys) {
+            return Ok(());
+        }
+        self.keys = Some(keys);
+        writeln!(self.file, "{} {:04x}", frame, keys)
+    }
+}
+
+/// Plays back the keys of a file written by `Recorder`.
+pub struct Replay {
+    pub seed: u64,
+    pub cpu_hz: u32,
+    // The frames the keys change on, in order
+    changes: Vec<(u64, u16)>,
+    next: usize,
+    keys: u16,
+}
+
+impl Replay {
+    /// Reads a recording.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Unable to read {}: {}", path, err))?;
+        Replay::parse(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err("not an input recording".to_string());
+        }
+        let mut seed = None;
+        let mut cpu_hz = None;
+        let mut changes: Vec<(u64, u16)> = Vec::new();
+        for (i, line) in lines.filter(|(_, line)| !line.trim().is_empty()) {
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            let (name, value) = line.trim().split_once(' ')
+                .ok_or_else(|| error(format!("expected `<frame> <keys>`, got `{}`", line)))?;
+            let invalid = || error(format!("invalid value `{}`", value));
+            match name {
+                "seed" => seed = Some(value.parse().map_err(|_| invalid())?),
+                "cpu-hz" => cpu_hz = Some(value.parse().map_err(|_| invalid())?),
+                _ => {
+                    let frame = name.parse().map_err(|_| error(format!("invalid frame `{}`", name)))?;
+                    let keys = u16::from_str_radix(value, 16).map_err(|_| invalid())?;
+                    if changes.last().is_some_and(|&(last, _)| last >= frame) {
+                        return Err(error(format!("frame {} is out of order", frame)));
+                    }
+                    changes.push((frame, keys));
+                },
+            }
+        }
+        let cpu_hz = cpu_hz.ok_or("missing cpu-hz")?;
+        if !(MIN_CPU_HZ..=MAX_CPU_HZ).contains(&cpu_hz) {
+            return Err(format!("cpu-hz must be between {} and {}, got {}", MIN_CPU_HZ, MAX_CPU_HZ, cpu_hz));
+        }
+        Ok(Replay {
+            seed: seed.ok_or("missing seed")?,
+            cpu_hz,
+            changes,
+            next: 0,
+            keys: 0,
+        })
+    }
+
+    /// Returns the keys held on `frame`, called for each frame in order.
+    pub fn keys(&mut self, frame: u64) -> u16 {
+        while let Some(&(change, keys)) = self.changes.get(self.next) {
+            if change > frame {
+                break;
+            }
+            self.keys = keys;
+            self.next += 1;
+        }
+        self.keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> String {
+        Replay::parse(text).err().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join(format!("chip8-replay-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut recorder = Recorder::create(path, 1234, 900).unwrap();
+        for (frame, keys) in [0, 0, 0x20, 0x20, 0x21, 0].into_iter().enumerate() {
+            recorder.record(frame as u64, keys).unwrap();
+        }
+        drop(recorder);
+
+        let text = fs::read_to_string(path).unwrap();
+        assert_eq!(text, format!("{}\nseed 1234\ncpu-hz 900\n0 0000\n2 0020\n4 0021\n5 0000\n", HEADER));
+        let mut replay = Replay::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!((replay.seed, replay.cpu_hz), (1234, 900));
+        let keys: Vec<u16> = (0..8).map(|frame| replay.keys(frame)).collect();
+        assert_eq!(keys, [0, 0, 0x20, 0x20, 0x21, 0, 0, 0]);
+    }
+
+    #[test]
+    fn keys_steps_over_skipped_frames() {
+        let mut replay = Replay::parse(&format!("{}\nseed 1\ncpu-hz 700\n\n3 0001\n5 0002\n9 ffff\n", HEADER)).unwrap();
+        assert_eq!(replay.keys(0), 0); // Nothing held before the first change
+        assert_eq!(replay.keys(6), 2); // Frames 3 and 5 both passed
+        assert_eq!(replay.keys(8), 2);
+        assert_eq!(replay.keys(100), 0xffff);
+    }
+
+    #[test]
+    fn checks_the_header() {
+        assert_eq!(parse_error(""), "not an input recording");
+        assert_eq!(parse_error("seed 1\ncpu-hz 700\n"), "not an input recording");
+    }
+
+    #[test]
+    fn needs_the_seed_and_speed() {
+        assert_eq!(parse_error(&format!("{}\ncpu-hz 700\n0 0000\n", HEADER)), "missing seed");
+        assert_eq!(parse_error(&format!("{}\nseed 1\n0 0000\n", HEADER)), "missing cpu-hz");
+        assert_eq!(parse_error(&format!("{}\nseed x\n", HEADER)), "line 2: invalid value `x`");
+        assert_eq!(
+            parse_error(&format!("{}\nseed 1\ncpu-hz 0\n", HEADER)),
+            format!("cpu-hz must be between {} and {}, got 0", MIN_CPU_HZ, MAX_CPU_HZ)
+        );
+        assert!(parse_error(&format!("{}\nseed 1\ncpu-hz {}\n", HEADER, MAX_CPU_HZ + 1)).starts_with("cpu-hz must be"));
+    }
+
+    #[test]
+    fn rejects_bad_frames() {
+        let error = |frames: &str| parse_error(&format!("{}\nseed 1\ncpu-hz 700\n{}", HEADER, frames));
+        assert_eq!(error("5 0001\n5 0002\n"), "line 5: frame 5 is out of order");
+        assert_eq!(error("5 0001\n4 0002\n"), "line 5: frame 4 is out of order");
+        assert_eq!(error("x 0001\n"), "line 4: invalid frame `x`");
+        assert_eq!(error("1 10000\n"), "line 4: invalid value `10000`");
+        assert_eq!(error("1\n"), "line 4: expected `<frame> <keys>`, got `1`");
+    }
+}
@@ -0,0 +1,3 @@
+//! The WebAssembly module loaded by `index.html`, build it with `wasm-pack build --target web`.
+
+pub use chip8::wasm::WasmChip8;